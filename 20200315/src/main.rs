@@ -10,16 +10,43 @@ use std::cell::{RefCell};
 use std::rc::Rc;
 extern crate rand;
 use rand::seq::SliceRandom;
+use sketch_utils::boundary::Boundary;
+use sketch_utils::emit::{EmitContext, Emitter, EmitterExt, Group, MouseBrush, Random};
+use sketch_utils::force::{CurlNoise, ForceField, NoiseKind, Vortex};
+use sketch_utils::outline::{Outline, OutlineEmitter, Sampling};
+use sketch_utils::spring::Spring;
+use sketch_utils::step::{drag_from_friction, per_frame_force, FixedStep, Integrator, REFERENCE_RATE};
 
 mod son;
 
+/// Counts the allocations made in the audio render callback.
+#[cfg(feature = "rt_audit")]
+#[global_allocator]
+static ALLOCATOR: audio_utils::rt_audit::CountingAllocator = audio_utils::rt_audit::CountingAllocator;
+
 const MAX_LINE_LENGTH2: f32 = 2500.0;
 const MAX_LINES_EVER: usize = 20;
+const RENDER: bool = true;
+/// Simulation steps per second.
+const STEP_RATE: f32 = 240.0;
+const INTEGRATOR: Integrator = Integrator::SemiImplicitEuler;
 
 struct Ids {
     max_lines: widget::Id,
-    friction: widget::Id,
-    force_strength: widget::Id,
+    drag: widget::Id,
+    stiffness: widget::Id,
+    damping: widget::Id,
+    breaking_tension: widget::Id,
+}
+
+/// The position, the velocity relative to the point and the spring of a
+/// connected point.
+type Neighbour = (Point2, Vector2, Spring);
+
+/// A line to another point, both points have one.
+struct Line {
+    point: Rc<RefCell<LinePoint>>,
+    spring: Spring,
 }
 
 struct LinePoint {
@@ -27,7 +54,7 @@ struct LinePoint {
     vel: Vector2,
     sine_i: usize,
     max_lines: usize,
-    lines: Vec<Rc<RefCell<LinePoint>>>,
+    lines: Vec<Line>,
 }
 
 impl LinePoint {
@@ -43,38 +70,58 @@ impl LinePoint {
         }
     }
 
-    fn update(&mut self, win_rect: &nannou::geom::rect::Rect, friction: f32, force_strength: f32, audio_interface: &mut son::AudioInterface) {
+    fn update(&mut self, win_rect: &nannou::geom::rect::Rect, boundary: &Boundary, spring: &Spring, audio_interface: &mut son::AudioInterface) {
         // Remove lines if there are too many.
         if self.lines.len() > self.max_lines {
             for _ in 0..self.lines.len() - self.max_lines {
                 self.lines.pop();
             }
         }
-        // Remove lines to points that are too far away.
+        // Snap lines that are stretched too far.
         let local_pos = self.pos;
-        self.lines.retain(|x| 
-            x.borrow().pos.distance2(local_pos) < MAX_LINE_LENGTH2);
-        
-        // Move towards connected dots it's far away from and away from close ones.
-        self.vel *= friction; // Velocity damping, 0.7 is gooood
-        let dist_range = Range::new(0.0, MAX_LINE_LENGTH2);
-        let vel_range = Range::new(-force_strength, force_strength);
-        for lp in &self.lines {
-            let dist2 = lp.borrow().pos.distance2(local_pos);
-            let force = dist_range.map_value(dist2, &vel_range);
-            self.vel += (lp.borrow().pos - self.pos) * force;
+        for line in &mut self.lines {
+            line.spring.tune(spring);
         }
+        self.lines.retain(|line| {
+            let length = boundary.offset(*win_rect, local_pos, line.point.borrow().pos).magnitude();
+            !line.spring.breaks(length)
+        });
 
-        self.pos += self.vel;
-
+        // The speed in units per frame, like before the fixed timestep.
+        let speed2 = (self.vel / REFERENCE_RATE).magnitude2();
         let speed_range = Range::new(0.0, 10.0);
         let freq_range = Range::new(100.0, 1000.0);
-        let freq: f64 = speed_range.map_value(self.vel.distance2(pt2(0.0, 0.0)) as f64, &freq_range);
+        let freq: f64 = speed_range.map_value(speed2 as f64, &freq_range);
         audio_interface.set_sine_freq(self.sine_i, freq);
+    }
+
+    /// Move the point by `dt` seconds, `drag` is per second.
+    /// `neighbours` is scratch space, reused for every point.
+    fn step(&mut self, win_rect: &nannou::geom::rect::Rect, boundary: &Boundary, forces: &ForceField, time: f32, dt: f32, drag: f32, neighbours: &mut Vec<Neighbour>) {
+        let vel = self.vel;
+        neighbours.clear();
+        neighbours.extend(self.lines.iter().map(|line| {
+            let np = line.point.borrow();
+            (np.pos, np.vel - vel, line.spring)
+        }));
+        let neighbours = &*neighbours;
+        let accel = |pos: Point2| {
+            let mut accel = per_frame_force(forces.force(pos, time));
+            // Pulled by the springs to the connected dots.
+            for (np, relative_vel, spring) in neighbours {
+                // The short way, across the edges of the screen.
+                let offset = boundary.offset(*win_rect, pos, *np);
+                accel += spring.acceleration(offset, *relative_vel);
+            }
+            accel
+        };
+        INTEGRATOR.step(&mut self.pos, &mut self.vel, dt, drag, accel);
+        for line in &mut self.lines {
+            line.spring.age(dt);
+        }
 
-        // Wrap at the edges of the screen.
-        self.pos.x = self.pos.x.max(win_rect.left()).min(win_rect.right());
-        self.pos.y = self.pos.y.max(win_rect.bottom()).min(win_rect.top());
+        // Keep the point inside the sketch.
+        boundary.apply(*win_rect, &mut self.pos, &mut self.vel, dt);
     }
 
     fn trigger_sound(&self, audio_interface: &mut son::AudioInterface) {
@@ -96,12 +143,19 @@ struct Model {
     _window: window::Id,
     audio_interface: son::AudioInterface,
     points: Vec<Rc<RefCell<LinePoint>>>,
+    emitter: Group,
+    forces: ForceField,
     ui: Ui,
     widget_ids: Ids,
-    friction: f32,
+    /// Per second.
+    drag: f32,
     max_lines: usize,
-    force_strength: f32,
+    /// The springs of new lines, the sliders change all of them.
+    spring: Spring,
+    clock: FixedStep,
+    neighbours: Vec<Neighbour>,
     show_gui: bool,
+    boundary: Boundary,
 }
 
 impl Model {
@@ -128,23 +182,52 @@ fn model(app: &App) -> Model {
 
     let widget_ids = Ids {
         max_lines: ui.generate_widget_id(),
-        friction: ui.generate_widget_id(),
-        force_strength: ui.generate_widget_id(),
+        drag: ui.generate_widget_id(),
+        stiffness: ui.generate_widget_id(),
+        damping: ui.generate_widget_id(),
+        breaking_tension: ui.generate_widget_id(),
     };
 
+    let emitter = Group::new()
+        .with(MouseBrush { radius: 15.0 }.chance(0.4))
+        .with(Random::default().chance(0.4));
+
+    // Slowly swirl the network around, the vortex is off until its slider is
+    // moved.
+    let mut curl = CurlNoise::new(NoiseKind::Simplex, 15, 0.3);
+    curl.params.strength = curl.params.strength.animate(0.2, 20.0);
+    let forces = ForceField::new()
+        .with(curl)
+        .with(Vortex::new(pt2(0.0, 0.0), 0.0, 400.0));
+
+    // Points leave at one edge and come back at the other.
+    let boundary = Boundary::Wrap;
+
+    // Lines grow in over half a second and snap when stretched by about half
+    // the distance points connect at.
+    let mut spring = Spring::new(0.0, per_frame_force(0.2));
+    spring.damping = 5.0;
+    spring.breaking_tension = spring.stiffness * MAX_LINE_LENGTH2.sqrt() * 0.5;
+    spring.birth = 0.5;
+
     Model { 
         _window, 
         audio_interface, 
         points: vec![], 
+        emitter,
+        forces,
         ui, widget_ids, 
-        friction: 0.7, 
+        drag: drag_from_friction(0.7),
         max_lines: 10, 
-        force_strength: 0.1, 
-        show_gui: false
+        spring,
+        clock: FixedStep::new(STEP_RATE),
+        neighbours: Vec::with_capacity(MAX_LINES_EVER),
+        show_gui: false,
+        boundary,
     }
 }
 
-fn update(app: &App, model: &mut Model, _update: Update) {
+fn update(app: &App, model: &mut Model, update: Update) {
     {
         // Calling `set_widgets` allows us to instantiate some widgets.
         let ui = &mut model.ui.set_widgets();
@@ -158,12 +241,12 @@ fn update(app: &App, model: &mut Model, _update: Update) {
                 .border(1.0)
         }
 
-        for value in slider(model.friction, 0.0, 1.0)
+        for value in slider(model.drag, 0.0, 60.0)
             .top_left_with_margin(20.0)
-            .label("Friction")
-            .set(model.widget_ids.friction, ui)
+            .label(&format!("Drag: {:.1}/s", model.drag))
+            .set(model.widget_ids.drag, ui)
         {
-            model.friction = value;
+            model.drag = value;
         }
 
         for value in slider(model.max_lines as f32, 0.0, MAX_LINES_EVER as f32)
@@ -174,44 +257,40 @@ fn update(app: &App, model: &mut Model, _update: Update) {
             model.max_lines = value as usize;
         }
 
-        for value in slider(model.force_strength, 0.0, 1.0)
+        for value in slider(model.spring.stiffness, 0.0, per_frame_force(1.0))
             .down(20.0)
-            .label("Force strength")
-            .set(model.widget_ids.force_strength, ui)
+            .label(&format!("Stiffness: {:.0}/s²", model.spring.stiffness))
+            .set(model.widget_ids.stiffness, ui)
         {
-            model.force_strength = value;
+            model.spring.stiffness = value;
         }
-    }
 
-    // Create a new point when the left mouse button is pressed
-    if let Some(pushed_point) = app.mouse.buttons.left().if_down() {
-        let mouse_pos = pt2(app.mouse.x, app.mouse.y);
-        let distance_from_pushed: f32 = mouse_pos.distance(pushed_point);
-        if distance_from_pushed > 0.0
-            && random_f32() > 0.6
+        for value in slider(model.spring.damping, 0.0, 30.0)
+            .down(20.0)
+            .label(&format!("Damping: {:.1}/s", model.spring.damping))
+            .set(model.widget_ids.damping, ui)
         {
-            let distance_from_pushed = 15.0; // set distance instead of dynamic
-            // random_range crashes if both values are the same
-            let new_pos = mouse_pos 
-            + pt2(random_range(-distance_from_pushed, distance_from_pushed), 
-                random_range(-distance_from_pushed, distance_from_pushed));
-            let sine_i = model.audio_interface.get_new_sine();
-            let new_point = Rc::new(RefCell::new(LinePoint::new_at(new_pos, sine_i)));
-            model.points.push(new_point);
+            model.spring.damping = value;
         }
+
+        for value in slider(model.spring.breaking_tension, 0.0, 100000.0)
+            .down(20.0)
+            .label(&format!("Breaking tension: {:.0}", model.spring.breaking_tension))
+            .set(model.widget_ids.breaking_tension, ui)
+        {
+            model.spring.breaking_tension = value;
+        }
+
+        model.forces.sliders(ui);
     }
 
-    // Create a random new point.
-    if random_f32() > 0.6 {
-        let win = app.window_rect();
-        let new_pos = 
-            pt2(random_range(win.left(), win.right()), 
-                random_range(win.top(), win.bottom()));
+    // Create new points at random and around the mouse while it is dragged.
+    let ctx = EmitContext::from_app(app);
+    for seed in model.emitter.seeds(&ctx) {
         let sine_i = model.audio_interface.get_new_sine();
-        let new_point = Rc::new(RefCell::new(LinePoint::new_at(new_pos, sine_i)));
+        let new_point = Rc::new(RefCell::new(LinePoint::new_at(seed.pos, sine_i)));
         model.points.push(new_point);
     }
-    
 
     // remove lines for random points
     // for _ in 0..model.points.len()/2 {
@@ -233,34 +312,53 @@ fn update(app: &App, model: &mut Model, _update: Update) {
             let pos = p.borrow().pos.clone();
             for np in &model.points {
                 if np.borrow().lines.len() < np.borrow().max_lines 
-                    && !np.borrow().lines.contains(p)
-                    && !p.borrow().lines.contains(np)
+                    && !np.borrow().lines.iter().any(|line| line.point == *p)
+                    && !p.borrow().lines.iter().any(|line| line.point == *np)
                 {
-                    let dist = np.borrow().pos.distance2(pos);
+                    let dist = model.boundary.offset(app.window_rect(), pos, np.borrow().pos).magnitude2();
                     if dist < min_dist && dist > 0.0 && dist < MAX_LINE_LENGTH2 {
                         closest_neighbour = Some(Rc::clone(&np));
                     }
                 }
             }
             if let Some(point_rc) = closest_neighbour {
-                // add as a line to both points
-                point_rc.borrow_mut().lines.push(Rc::clone(p));
-                p.borrow_mut().lines.push(point_rc);
+                // add as a line to both points, resting at their distance
+                let rest_length = model.boundary.offset(app.window_rect(), pos, point_rc.borrow().pos).magnitude();
+                let spring = model.spring.with_rest_length(rest_length);
+                point_rc.borrow_mut().lines.push(Line { point: Rc::clone(p), spring });
+                p.borrow_mut().lines.push(Line { point: point_rc, spring });
                 p.borrow().trigger_sound(&mut model.audio_interface);
             }
         }
     }
 
     // Update all points.
+    let win_rect = app.window_rect();
     for p in &model.points {
         p.borrow_mut().max_lines = model.max_lines;
-        p.borrow_mut().update(&app.window_rect(), model.friction, model.force_strength, &mut model.audio_interface);
+        p.borrow_mut().update(&win_rect, &model.boundary, &model.spring, &mut model.audio_interface);
+    }
+
+    // Move them in fixed steps, every rendered frame is the same time apart
+    // when rendering.
+    let elapsed = if RENDER {
+        1.0 / REFERENCE_RATE
+    } else {
+        update.since_last.as_secs_f32()
+    };
+    let start = model.clock.time;
+    for i in 0..model.clock.steps(elapsed) {
+        let time = start + i as f32 * model.clock.dt;
+        for p in &model.points {
+            p.borrow_mut().step(&win_rect, &model.boundary, &model.forces, time, model.clock.dt, model.drag, &mut model.neighbours);
+        }
+        model.forces.advance(time, model.clock.dt);
     }
     
     model.audio_interface.update();
 }
 
-fn window_event(_app: &App, model: &mut Model, event: WindowEvent) {
+fn window_event(app: &App, model: &mut Model, event: WindowEvent) {
     match event {
         KeyPressed(key) => {
             match key {
@@ -293,7 +391,29 @@ fn window_event(_app: &App, model: &mut Model, event: WindowEvent) {
         Touch(_touch) => {}
         TouchPressure(_pressure) => {}
         HoveredFile(_path) => {}
-        DroppedFile(_path) => {}
+        DroppedFile(path) => {
+            // Grow the network along a dropped SVG file, or the name of the
+            // sketch in a dropped font.
+            let is_font = path.extension().map_or(false, |ext| ext == "ttf" || ext == "otf");
+            let outline = if is_font {
+                Outline::from_font_file(&path, "20200315", 100.0)
+            } else {
+                Outline::from_svg_file(&path)
+            };
+            match outline {
+                Ok(mut outline) => {
+                    outline.fit(app.window_rect().pad(100.0));
+                    let spacing = MAX_LINE_LENGTH2.sqrt() * 0.5;
+                    let mut emitter = OutlineEmitter::new(outline, Sampling::Along { spacing });
+                    for seed in emitter.seeds(&EmitContext::from_app(app)) {
+                        let sine_i = model.audio_interface.get_new_sine();
+                        let new_point = Rc::new(RefCell::new(LinePoint::new_at(seed.pos, sine_i)));
+                        model.points.push(new_point);
+                    }
+                }
+                Err(e) => println!("Couldn't load {}: {}", path.display(), e),
+            }
+        }
         HoveredFileCancelled => {}
         Focused => {}
         Unfocused => {}
@@ -313,11 +433,18 @@ fn view(app: &App, model: &Model, frame: Frame) {
             .radius(3.0)
             .color(hsla(0.7, 0.8, 0.4, 0.2));
         // draw lines
-        for np in &p.borrow().lines {
-            draw.line()
-                .points(pos, np.borrow().pos)
-                .color(hsla(0.7, 0.5, 0.7, 0.1))
-                .weight(2.0);
+        for line in &p.borrow().lines {
+            // New lines grow out of both points and meet in the middle.
+            let growth = line.spring.growth();
+            let offset = model.boundary.offset(app.window_rect(), pos, line.point.borrow().pos);
+            let mut end = pos + offset * growth;
+            model.boundary.apply(app.window_rect(), &mut end, &mut vec2(0.0, 0.0), 0.0);
+            for (a, b) in model.boundary.segments(app.window_rect(), pos, end) {
+                draw.line()
+                    .points(a, b)
+                    .color(hsla(0.7, 0.5, 0.7, 0.1 * growth))
+                    .weight(2.0);
+            }
         }
     }
     // Write to the window frame.
@@ -332,8 +459,10 @@ fn view(app: &App, model: &Model, frame: Frame) {
     //
     // NOTE: You can speed this up with `capture_frame_threaded`, however be aware that if the
     // image writing threads can't keep up you may quickly begin to run out of RAM!
-    let file_path = captured_frame_path(app, &frame);
-    app.main_window().capture_frame(file_path);
+    if RENDER {
+        let file_path = captured_frame_path(app, &frame);
+        app.main_window().capture_frame(file_path);
+    }
 }
 
 fn captured_frame_path(app: &App, frame: &Frame) -> std::path::PathBuf {
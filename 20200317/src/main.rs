@@ -4,14 +4,27 @@ use nannou::geom::range::Range;
 
 use std::cell::{RefCell};
 use std::rc::Rc;
+use sketch_utils::boundary::Boundary;
+use sketch_utils::curve::{ArcLength, Curve, FromFn, Heart};
+use sketch_utils::emit::{EmitContext, Emitter, EmitterExt, Parametric};
+use sketch_utils::step::{drag_from_friction, per_frame_force, FixedStep, Integrator, REFERENCE_RATE};
 extern crate rand;
 use rand::seq::SliceRandom;
 
 mod son;
 
+/// Counts the allocations made in the audio render callback.
+#[cfg(feature = "rt_audit")]
+#[global_allocator]
+static ALLOCATOR: audio_utils::rt_audit::CountingAllocator = audio_utils::rt_audit::CountingAllocator;
+
 const MAX_LINE_LENGTH2: f32 = 4000.0;
 const MAX_LINES_EVER: usize = 20;
 const LIFETIME: i32 = 60 * 8;
+const RENDER: bool = true;
+/// Simulation steps per second.
+const STEP_RATE: f32 = 240.0;
+const INTEGRATOR: Integrator = Integrator::SemiImplicitEuler;
 
 struct Ids {
     max_lines: widget::Id,
@@ -42,7 +55,7 @@ impl LinePoint {
         }
     }
 
-    fn update(&mut self, win_rect: &nannou::geom::rect::Rect, friction: f32, force_strength: f32) {
+    fn update(&mut self) {
         self.lifetime -= 1;
         // Remove lines if there are too many.
         if self.lines.len() > self.max_lines {
@@ -54,27 +67,35 @@ impl LinePoint {
         let local_pos = self.pos;
         self.lines.retain(|x| 
             x.borrow().pos.distance2(local_pos) < MAX_LINE_LENGTH2);
-        
-        // Move towards connected dots it's far away from and away from close ones.
-        self.vel *= friction; // Velocity damping, 0.7 is gooood
-        let dist_range = Range::new(0.0, MAX_LINE_LENGTH2);
-        let vel_range = Range::new(-force_strength, force_strength);
-        for lp in &self.lines {
-            let dist2 = lp.borrow().pos.distance2(local_pos);
-            let force = dist_range.map_value(dist2, &vel_range);
-            self.vel += (lp.borrow().pos - self.pos) * force;
-        }
-
-        self.pos += self.vel;
 
+        // The speed in units per frame, like before the fixed timestep.
+        let speed2 = (self.vel / REFERENCE_RATE).magnitude2();
         let speed_range = Range::new(0.0, 10.0);
         let freq_range = Range::new(100.0, 1000.0);
-        let freq: f64 = speed_range.map_value(self.vel.distance2(pt2(0.0, 0.0)) as f64, &freq_range);
+        let freq: f64 = speed_range.map_value(speed2 as f64, &freq_range);
         // audio_interface.set_sine_freq(self.sine_i, freq);
+    }
 
-        // Wrap at the edges of the screen.
-        self.pos.x = self.pos.x.max(win_rect.left()).min(win_rect.right());
-        self.pos.y = self.pos.y.max(win_rect.bottom()).min(win_rect.top());
+    /// Move the point by `dt` seconds. `drag` is per second and `stiffness`
+    /// per second², `neighbours` is scratch space, reused for every point.
+    fn step(&mut self, win_rect: &nannou::geom::rect::Rect, boundary: &Boundary, dt: f32, drag: f32, stiffness: f32, neighbours: &mut Vec<Point2>) {
+        neighbours.clear();
+        neighbours.extend(self.lines.iter().map(|lp| lp.borrow().pos));
+        let neighbours = &*neighbours;
+        let dist_range = Range::new(0.0, MAX_LINE_LENGTH2);
+        let accel_range = Range::new(-stiffness, stiffness);
+        let accel = |pos: Point2| {
+            let mut accel = vec2(0.0, 0.0);
+            // Move towards connected dots it's far away from and away from close ones.
+            for &np in neighbours {
+                accel += (np - pos) * dist_range.map_value(np.distance2(pos), &accel_range);
+            }
+            accel
+        };
+        INTEGRATOR.step(&mut self.pos, &mut self.vel, dt, drag, accel);
+
+        // Keep the point inside the sketch.
+        boundary.apply(*win_rect, &mut self.pos, &mut self.vel, dt);
     }
 
     fn trigger_sound(&self, audio_interface: &mut son::AudioInterface) {
@@ -88,6 +109,16 @@ impl PartialEq for LinePoint {
     }
 }
 
+/// The heart curve, growing and shrinking slowly as the angle goes round.
+/// The points are evenly spaced along it.
+fn heart() -> impl Curve {
+    let heart = ArcLength::new(Heart, 1000);
+    FromFn(move |angle: f32| {
+        let scale = (angle / 6.0).cos() * 82.0 + 100.0;
+        (heart.point(angle) + vec2(0.0, 1.0)) * scale
+    })
+}
+
 fn main() {
     nannou::app(model).update(update).run();
 }
@@ -96,12 +127,17 @@ struct Model {
     _window: window::Id,
     // audio_interface: son::AudioInterface,
     points: Vec<Rc<RefCell<LinePoint>>>,
+    heart: Box<dyn Emitter>,
     ui: Ui,
     widget_ids: Ids,
     friction: f32,
     max_lines: usize,
+    /// Per frame at `REFERENCE_RATE`, it is animated and drawn.
     force_strength: f32,
+    clock: FixedStep,
+    neighbours: Vec<Point2>,
     show_gui: bool,
+    boundary: Boundary,
 }
 
 impl Model {
@@ -132,19 +168,27 @@ fn model(app: &App) -> Model {
         force_strength: ui.generate_widget_id(),
     };
 
+    let heart = Parametric::new(0.0, 0.06, heart()).boxed();
+
+    let boundary = Boundary::Clamp;
+
     Model { 
         _window, 
         // audio_interface, 
         points: vec![], 
+        heart,
         ui, widget_ids, 
         friction: 0.7, 
         max_lines: 10, 
         force_strength: 0.0, 
-        show_gui: false
+        clock: FixedStep::new(STEP_RATE),
+        neighbours: Vec::with_capacity(MAX_LINES_EVER),
+        show_gui: false,
+        boundary,
     }
 }
 
-fn update(app: &App, model: &mut Model, _update: Update) {
+fn update(app: &App, model: &mut Model, update: Update) {
     {
         // Calling `set_widgets` allows us to instantiate some widgets.
         let ui = &mut model.ui.set_widgets();
@@ -184,16 +228,10 @@ fn update(app: &App, model: &mut Model, _update: Update) {
     }
 
     // Create new point on a heart curve.
-    if app.elapsed_frames() % 1 == 0 {
-        let frame_num = app.elapsed_frames() as f32;
-        let angle = frame_num * 0.06; 
-        // Heart curve equation from here: https://mathworld.wolfram.com/HeartCurve.html
-        let scale = (frame_num * 0.01).cos() * 82.0 + 100.0;
-        let r = 2.0 - 2.0*angle.sin() + (angle.sin() * ( angle.cos().abs().sqrt() / (angle.sin() + 1.4)));
-        let new_pos = 
-            pt2(angle.cos() * r * scale, (angle.sin() * r + 1.0) * scale);
+    let ctx = EmitContext::from_app(app);
+    for seed in model.heart.seeds(&ctx) {
         // let sine_i = model.audio_interface.get_new_sine();
-        let new_point = Rc::new(RefCell::new(LinePoint::new_at(new_pos, 0, LIFETIME)));
+        let new_point = Rc::new(RefCell::new(LinePoint::new_at(seed.pos, 0, LIFETIME)));
         model.points.push(new_point);
     }
 
@@ -231,7 +269,24 @@ fn update(app: &App, model: &mut Model, _update: Update) {
     // Update all points.
     for p in &model.points {
         p.borrow_mut().max_lines = model.max_lines;
-        p.borrow_mut().update(&app.window_rect(), model.friction, model.force_strength);// &mut model.audio_interface);
+        p.borrow_mut().update();// &mut model.audio_interface);
+    }
+
+    // Move them in fixed steps, every rendered frame is the same time apart
+    // when rendering. Friction and force strength are per frame like the
+    // sliders.
+    let win_rect = app.window_rect();
+    let elapsed = if RENDER {
+        1.0 / REFERENCE_RATE
+    } else {
+        update.since_last.as_secs_f32()
+    };
+    let drag = drag_from_friction(model.friction);
+    let stiffness = per_frame_force(model.force_strength);
+    for _ in 0..model.clock.steps(elapsed) {
+        for p in &model.points {
+            p.borrow_mut().step(&win_rect, &model.boundary, model.clock.dt, drag, stiffness, &mut model.neighbours);
+        }
     }
 
     // Remove expired points.
@@ -322,8 +377,10 @@ fn view(app: &App, model: &Model, frame: Frame) {
     //
     // NOTE: You can speed this up with `capture_frame_threaded`, however be aware that if the
     // image writing threads can't keep up you may quickly begin to run out of RAM!
-    let file_path = captured_frame_path(app, &frame);
-    app.main_window().capture_frame(file_path);
+    if RENDER {
+        let file_path = captured_frame_path(app, &frame);
+        app.main_window().capture_frame(file_path);
+    }
 }
 
 fn captured_frame_path(app: &App, frame: &Frame) -> std::path::PathBuf {
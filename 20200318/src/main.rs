@@ -6,14 +6,26 @@ use nannou::noise::Seedable;
 
 use std::cell::{RefCell};
 use std::rc::Rc;
+use sketch_utils::boundary::Boundary;
+use sketch_utils::curve::{ArcLength, Curve, FromFn, RoseHeart};
+use sketch_utils::emit::{EmitContext, Emitter, EmitterExt, Parametric, Repeat};
+use sketch_utils::step::{drag_from_friction, per_frame_force, FixedStep, Integrator, REFERENCE_RATE};
 extern crate rand;
 
 mod son;
 
+/// Counts the allocations made in the audio render callback.
+#[cfg(feature = "rt_audit")]
+#[global_allocator]
+static ALLOCATOR: audio_utils::rt_audit::CountingAllocator = audio_utils::rt_audit::CountingAllocator;
+
 const MAX_LINE_LENGTH2: f32 = 400.0;
 const MAX_LINES_EVER: usize = 20;
 const LIFETIME: i32 = 60 * 8;
 const RENDER: bool = true;
+/// Simulation steps per second.
+const STEP_RATE: f32 = 240.0;
+const INTEGRATOR: Integrator = Integrator::SemiImplicitEuler;
 
 struct Ids {
     max_lines: widget::Id,
@@ -48,7 +60,7 @@ impl LinePoint {
         }
     }
 
-    fn update(&mut self, win_rect: &nannou::geom::rect::Rect, friction: f32, force_strength: f32) {
+    fn update(&mut self, force_strength: f32) {
         if force_strength > self.force_strength {
             self.force_strength = force_strength;
         }
@@ -63,27 +75,36 @@ impl LinePoint {
         let local_pos = self.pos;
         self.lines.retain(|x| 
             x.borrow().pos.distance2(local_pos) < MAX_LINE_LENGTH2);
-        
-        // Move towards connected dots it's far away from and away from close ones.
-        self.vel *= friction; // Velocity damping, 0.7 is gooood
-        let dist_range = Range::new(0.0, MAX_LINE_LENGTH2);
-        let vel_range = Range::new(-self.force_strength, self.force_strength);
-        for lp in &self.lines {
-            let dist2 = lp.borrow().pos.distance2(local_pos);
-            let force = dist_range.map_value(dist2, &vel_range);
-            self.vel += (lp.borrow().pos - self.pos) * force;
-        }
-
-        self.pos += self.vel;
 
+        // The speed in units per frame, like before the fixed timestep.
+        let speed2 = (self.vel / REFERENCE_RATE).magnitude2();
         let speed_range = Range::new(0.0, 10.0);
         let freq_range = Range::new(100.0, 1000.0);
-        let freq: f64 = speed_range.map_value(self.vel.distance2(pt2(0.0, 0.0)) as f64, &freq_range);
+        let freq: f64 = speed_range.map_value(speed2 as f64, &freq_range);
         // audio_interface.set_sine_freq(self.sine_i, freq);
+    }
 
-        // Wrap at the edges of the screen.
-        self.pos.x = self.pos.x.max(win_rect.left()).min(win_rect.right());
-        self.pos.y = self.pos.y.max(win_rect.bottom()).min(win_rect.top());
+    /// Move the point by `dt` seconds with its own force strength. `drag` is
+    /// per second, `neighbours` is scratch space, reused for every point.
+    fn step(&mut self, win_rect: &nannou::geom::rect::Rect, boundary: &Boundary, dt: f32, drag: f32, neighbours: &mut Vec<Point2>) {
+        neighbours.clear();
+        neighbours.extend(self.lines.iter().map(|lp| lp.borrow().pos));
+        let neighbours = &*neighbours;
+        let stiffness = per_frame_force(self.force_strength);
+        let dist_range = Range::new(0.0, MAX_LINE_LENGTH2);
+        let accel_range = Range::new(-stiffness, stiffness);
+        let accel = |pos: Point2| {
+            let mut accel = vec2(0.0, 0.0);
+            // Move towards connected dots it's far away from and away from close ones.
+            for &np in neighbours {
+                accel += (np - pos) * dist_range.map_value(np.distance2(pos), &accel_range);
+            }
+            accel
+        };
+        INTEGRATOR.step(&mut self.pos, &mut self.vel, dt, drag, accel);
+
+        // Keep the point inside the sketch.
+        boundary.apply(*win_rect, &mut self.pos, &mut self.vel, dt);
     }
 
     fn trigger_sound(&self, audio_interface: &mut son::AudioInterface) {
@@ -97,6 +118,16 @@ impl PartialEq for LinePoint {
     }
 }
 
+/// The rose-like heart, growing and shrinking slowly as the angle goes
+/// round. The points are evenly spaced along it.
+fn heart() -> impl Curve {
+    let heart = ArcLength::new(RoseHeart, 2000);
+    FromFn(move |angle: f32| {
+        let scale = (angle * 0.01).cos() * 82.0 + 100.0;
+        (heart.point(angle) - vec2(0.0, 0.35)) * scale
+    })
+}
+
 fn main() {
     nannou::app(model).update(update).run();
 }
@@ -105,13 +136,17 @@ struct Model {
     _window: window::Id,
     // audio_interface: son::AudioInterface,
     points: Vec<Rc<RefCell<LinePoint>>>,
+    heart: Repeat<Parametric<Box<dyn Curve>>>,
     ui: Ui,
     widget_ids: Ids,
     friction: f32,
     max_lines: usize,
+    /// Per frame at `REFERENCE_RATE`, it is animated and drawn.
     force_strength: f32,
+    clock: FixedStep,
+    neighbours: Vec<Point2>,
     show_gui: bool,
-    shape_angle: f32,
+    boundary: Boundary,
 }
 
 impl Model {
@@ -142,20 +177,32 @@ fn model(app: &App) -> Model {
         force_strength: ui.generate_widget_id(),
     };
 
+    let heart = Parametric::new(0.02, 0.02, Box::new(heart()) as Box<dyn Curve>).repeat(4);
+
+    // Keep the points in a circle around the heart.
+    let boundary = Boundary::Circle {
+        center: pt2(0.0, 0.0),
+        radius: 480.0,
+        restitution: 0.5,
+    };
+
     Model { 
         _window, 
         // audio_interface, 
         points: vec![], 
+        heart,
         ui, widget_ids, 
         friction: 0.7, 
         max_lines: 10, 
         force_strength: 0.0, 
+        clock: FixedStep::new(STEP_RATE),
+        neighbours: Vec::with_capacity(MAX_LINES_EVER),
         show_gui: false,
-        shape_angle: 0.0,
+        boundary,
     }
 }
 
-fn update(app: &App, model: &mut Model, _update: Update) {
+fn update(app: &App, model: &mut Model, update: Update) {
     {
         // Calling `set_widgets` allows us to instantiate some widgets.
         let ui = &mut model.ui.set_widgets();
@@ -194,18 +241,11 @@ fn update(app: &App, model: &mut Model, _update: Update) {
         }
     }
 
-    // Create new point on a heart curve.
-    for _ in 0..4 {
-        model.shape_angle += 0.02; 
-        let angle = &model.shape_angle;
-        // Heart curve equation from here: https://mathworld.wolfram.com/HeartCurve.html
-        let scale = (angle * 0.01).cos() * 82.0 + 100.0;
-        // let r = 2.0 - 2.0*angle.sin() + (angle.sin() * ( angle.cos().abs().sqrt() / (angle.sin() + 1.4)));
-        let r = angle.sin() + ((angle*5.0)/2.0).sin().powf(3.0);
-        let new_pos = 
-            pt2(angle.cos() * r * scale, (angle.sin() * r - 0.35) * scale);
+    // Create new points on a heart curve.
+    let ctx = EmitContext::from_app(app);
+    for seed in model.heart.seeds(&ctx) {
         // let sine_i = model.audio_interface.get_new_sine();
-        let new_point = Rc::new(RefCell::new(LinePoint::new_at(new_pos, 0, LIFETIME, *angle * 0.01)));
+        let new_point = Rc::new(RefCell::new(LinePoint::new_at(seed.pos, 0, LIFETIME, seed.t * 0.01)));
         model.points.push(new_point);
     }
 
@@ -243,7 +283,22 @@ fn update(app: &App, model: &mut Model, _update: Update) {
     // Update all points.
     for p in &model.points {
         p.borrow_mut().max_lines = model.max_lines;
-        p.borrow_mut().update(&app.window_rect(), model.friction, model.force_strength);// &mut model.audio_interface);
+        p.borrow_mut().update(model.force_strength);// &mut model.audio_interface);
+    }
+
+    // Move them in fixed steps, every rendered frame is the same time apart
+    // when rendering. Friction is per frame like the slider.
+    let win_rect = app.window_rect();
+    let elapsed = if RENDER {
+        1.0 / REFERENCE_RATE
+    } else {
+        update.since_last.as_secs_f32()
+    };
+    let drag = drag_from_friction(model.friction);
+    for _ in 0..model.clock.steps(elapsed) {
+        for p in &model.points {
+            p.borrow_mut().step(&win_rect, &model.boundary, model.clock.dt, drag, &mut model.neighbours);
+        }
     }
 
     // Remove expired points.
@@ -300,9 +355,10 @@ fn view(app: &App, model: &Model, frame: Frame) {
     let draw = app.draw();
     // Beating of the heart.
     let angle = app.elapsed_frames() as f32 * 0.06;
+    let shape_angle = model.heart.emitter.param;
     let noise = nannou::noise::Perlin::new().set_seed(42);
-    let beating: f32 = noise.get([angle as f64 * 0.2, model.shape_angle as f64 * 0.1]) as f32;
-    let beating2: f32 = noise.get([angle as f64 * 0.6, model.shape_angle as f64 * 0.5]) as f32;
+    let beating: f32 = noise.get([angle as f64 * 0.2, shape_angle as f64 * 0.1]) as f32;
+    let beating2: f32 = noise.get([angle as f64 * 0.6, shape_angle as f64 * 0.5]) as f32;
     let scale = 1.0;
     // Clear the background to pink.
     let hue = model.points[model.points.len()/2].borrow().hue;
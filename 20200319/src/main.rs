@@ -6,14 +6,28 @@ use nannou::noise::Seedable;
 
 use std::cell::{RefCell};
 use std::rc::Rc;
+use sketch_utils::boundary::Boundary;
+use sketch_utils::emit::{EmitContext, Emitter, HexGrid};
+use sketch_utils::poisson::{self, PoissonDisk, PoissonEmitter, Radius, Region};
+use sketch_utils::step::{drag_from_friction, per_frame_force, FixedStep, Integrator, REFERENCE_RATE};
 extern crate rand;
 
 mod son;
 
+/// Counts the allocations made in the audio render callback.
+#[cfg(feature = "rt_audit")]
+#[global_allocator]
+static ALLOCATOR: audio_utils::rt_audit::CountingAllocator = audio_utils::rt_audit::CountingAllocator;
+
 const MAX_LINE_LENGTH2: f32 = 1000.0;
 const MAX_LINES_EVER: usize = 20;
 const LIFETIME: i32 = 60 * 8;
 const RENDER: bool = true;
+/// Simulation steps per second.
+const STEP_RATE: f32 = 240.0;
+const INTEGRATOR: Integrator = Integrator::SemiImplicitEuler;
+/// Seed the points with Poisson-disk sampling instead of the hex grid.
+const POISSON_DISK: bool = false;
 
 struct Ids {
     max_lines: widget::Id,
@@ -48,7 +62,7 @@ impl LinePoint {
         }
     }
 
-    fn update(&mut self, win_rect: &nannou::geom::rect::Rect, friction: f32, force_strength: f32) {
+    fn update(&mut self, force_strength: f32) {
         if force_strength > self.force_strength {
             self.force_strength = force_strength;
         }
@@ -63,27 +77,36 @@ impl LinePoint {
         let local_pos = self.pos;
         self.lines.retain(|x| 
             x.borrow().pos.distance2(local_pos) < MAX_LINE_LENGTH2);
-        
-        // Move towards connected dots it's far away from and away from close ones.
-        self.vel *= friction; // Velocity damping, 0.7 is gooood
-        let dist_range = Range::new(0.0, MAX_LINE_LENGTH2);
-        let vel_range = Range::new(-self.force_strength, self.force_strength);
-        for lp in &self.lines {
-            let dist2 = lp.borrow().pos.distance2(local_pos);
-            let force = dist_range.map_value(dist2, &vel_range);
-            self.vel += (lp.borrow().pos - self.pos) * force;
-        }
-
-        self.pos += self.vel;
 
+        // The speed in units per frame, like before the fixed timestep.
+        let speed2 = (self.vel / REFERENCE_RATE).magnitude2();
         let speed_range = Range::new(0.0, 10.0);
         let freq_range = Range::new(100.0, 1000.0);
-        let freq: f64 = speed_range.map_value(self.vel.distance2(pt2(0.0, 0.0)) as f64, &freq_range);
+        let freq: f64 = speed_range.map_value(speed2 as f64, &freq_range);
         // audio_interface.set_sine_freq(self.sine_i, freq);
+    }
 
-        // Wrap at the edges of the screen.
-        self.pos.x = self.pos.x.max(win_rect.left()).min(win_rect.right());
-        self.pos.y = self.pos.y.max(win_rect.bottom()).min(win_rect.top());
+    /// Move the point by `dt` seconds with its own force strength. `drag` is
+    /// per second, `neighbours` is scratch space, reused for every point.
+    fn step(&mut self, win_rect: &nannou::geom::rect::Rect, boundary: &Boundary, dt: f32, drag: f32, neighbours: &mut Vec<Point2>) {
+        neighbours.clear();
+        neighbours.extend(self.lines.iter().map(|lp| lp.borrow().pos));
+        let neighbours = &*neighbours;
+        let stiffness = per_frame_force(self.force_strength);
+        let dist_range = Range::new(0.0, MAX_LINE_LENGTH2);
+        let accel_range = Range::new(-stiffness, stiffness);
+        let accel = |pos: Point2| {
+            let mut accel = vec2(0.0, 0.0);
+            // Move towards connected dots it's far away from and away from close ones.
+            for &np in neighbours {
+                accel += (np - pos) * dist_range.map_value(np.distance2(pos), &accel_range);
+            }
+            accel
+        };
+        INTEGRATOR.step(&mut self.pos, &mut self.vel, dt, drag, accel);
+
+        // Keep the point inside the sketch.
+        boundary.apply(*win_rect, &mut self.pos, &mut self.vel, dt);
     }
 
     fn trigger_sound(&self, audio_interface: &mut son::AudioInterface) {
@@ -109,9 +132,13 @@ struct Model {
     widget_ids: Ids,
     friction: f32,
     max_lines: usize,
+    /// Per frame at `REFERENCE_RATE`, it is animated and drawn.
     force_strength: f32,
+    clock: FixedStep,
+    neighbours: Vec<Point2>,
     show_gui: bool,
     shape_angle: f32,
+    boundary: Boundary,
 }
 
 impl Model {
@@ -145,22 +172,22 @@ fn model(app: &App) -> Model {
     let mut points = vec![];
 
     // Fill screen with points
-    let mut point_dist: f32 = MAX_LINE_LENGTH2.sqrt();
-    point_dist *= 0.6;
-    let points_per_row: u64 = ((app.window_rect().right() - app.window_rect().left()) / point_dist) as u64;
-    
-    for ix in 1..points_per_row {
-        for iy in 1..points_per_row {
-            let mut x = ix as f32 * point_dist + app.window_rect().left();
-            if iy % 2 == 1 { x += point_dist/2.0; }
-            let y = app.window_rect().top() - iy as f32 * point_dist;
-            if !(iy % 2 == 0 && ix == 1) {
-                let new_point = Rc::new(RefCell::new(LinePoint::new_at(pt2(x, y), 0, LIFETIME, (ix + iy) as f32 * 0.005)));
-                points.push(new_point);
-            }
-        }
+    let mut seeding: Box<dyn Emitter> = if POISSON_DISK {
+        let radius = Radius::Uniform(poisson::radius_for_line_length(MAX_LINE_LENGTH2));
+        Box::new(PoissonEmitter::new(PoissonDisk::new(Region::Rect(app.window_rect()), radius, 19)))
+    } else {
+        Box::new(HexGrid::new(MAX_LINE_LENGTH2.sqrt() * 0.6))
+    };
+    for seed in seeding.seeds(&EmitContext::from_app(app)) {
+        let new_point = Rc::new(RefCell::new(LinePoint::new_at(seed.pos, 0, LIFETIME, seed.t * 0.5)));
+        points.push(new_point);
     }
-    
+
+    // Push points back softly before they reach the edges.
+    let boundary = Boundary::SoftWalls {
+        margin: 60.0,
+        strength: per_frame_force(0.5),
+    };
 
     Model { 
         _window, 
@@ -170,12 +197,15 @@ fn model(app: &App) -> Model {
         friction: 0.7, 
         max_lines: 4, 
         force_strength: 0.0, 
+        clock: FixedStep::new(STEP_RATE),
+        neighbours: Vec::with_capacity(MAX_LINES_EVER),
         show_gui: false,
         shape_angle: 0.0,
+        boundary,
     }
 }
 
-fn update(app: &App, model: &mut Model, _update: Update) {
+fn update(app: &App, model: &mut Model, update: Update) {
     {
         // Calling `set_widgets` allows us to instantiate some widgets.
         let ui = &mut model.ui.set_widgets();
@@ -268,7 +298,22 @@ fn update(app: &App, model: &mut Model, _update: Update) {
     // Update all points.
     for p in &model.points {
         p.borrow_mut().max_lines = model.max_lines;
-        p.borrow_mut().update(&app.window_rect(), model.friction, model.force_strength);// &mut model.audio_interface);
+        p.borrow_mut().update(model.force_strength);// &mut model.audio_interface);
+    }
+
+    // Move them in fixed steps, every rendered frame is the same time apart
+    // when rendering. Friction is per frame like the slider.
+    let win_rect = app.window_rect();
+    let elapsed = if RENDER {
+        1.0 / REFERENCE_RATE
+    } else {
+        update.since_last.as_secs_f32()
+    };
+    let drag = drag_from_friction(model.friction);
+    for _ in 0..model.clock.steps(elapsed) {
+        for p in &model.points {
+            p.borrow_mut().step(&win_rect, &model.boundary, model.clock.dt, drag, &mut model.neighbours);
+        }
     }
 
     // Remove expired points.
@@ -335,7 +380,8 @@ fn view(app: &App, model: &Model, frame: Frame) {
     
     draw.background().color(hsl(hue, 0.5, 0.0 + lightness));
     for p in &model.points {
-        let vel = p.borrow().vel;
+        // In units per frame, like before the fixed timestep.
+        let vel = p.borrow().vel / REFERENCE_RATE;
         let lifeforce = vel.distance(pt2(0.0, 0.0)).min(2.9) / 3.0;
         let pos = p.borrow().pos;
         let hue = p.borrow().hue;
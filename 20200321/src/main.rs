@@ -1,7 +1,4 @@
 use nannou::geom::range::Range;
-use nannou::image;
-use nannou::image::GenericImageView;
-use nannou::image::Pixel;
 use nannou::noise::NoiseFn;
 use nannou::noise::Seedable;
 use nannou::prelude::*;
@@ -9,16 +6,29 @@ use nannou::ui::prelude::*;
 
 use std::cell::RefCell;
 use std::rc::Rc;
+use sketch_utils::boundary::Boundary;
+use sketch_utils::emit::{EmitContext, Emitter, ImageDensity};
+use sketch_utils::image_field::{ImageField, ImageFit};
+use sketch_utils::step::{drag_from_friction, per_frame_force, FixedStep, Integrator, REFERENCE_RATE};
+use sketch_utils::video::{Loading, Source, VideoField};
 extern crate rand;
 
-type ImgBuf = image::ImageBuffer<image::Rgba<u8>, Vec<u8>>;
-
 mod son;
 
+/// Counts the allocations made in the audio render callback.
+#[cfg(feature = "rt_audit")]
+#[global_allocator]
+static ALLOCATOR: audio_utils::rt_audit::CountingAllocator = audio_utils::rt_audit::CountingAllocator;
+
 const MAX_LINE_LENGTH2: f32 = 1000.0;
 const MAX_LINES_EVER: usize = 20;
 const LIFETIME: i32 = 60 * 8;
 const RENDER: bool = false;
+/// Simulation steps per second.
+const STEP_RATE: f32 = 240.0;
+const INTEGRATOR: Integrator = Integrator::SemiImplicitEuler;
+/// Slow the points down with the frames in assets/video instead of the image.
+const VIDEO: bool = false;
 
 struct Ids {
     max_lines: widget::Id,
@@ -53,15 +63,7 @@ impl LinePoint {
         }
     }
 
-    fn update(
-        &mut self,
-        win_rect: &nannou::geom::rect::Rect,
-        friction: f32,
-        force_strength: f32,
-        img: &ImgBuf,
-        img_mult: f32,
-        screen_off: &Point2,
-    ) {
+    fn update(&mut self, force_strength: f32) {
         self.force_strength = force_strength;
         // self.lifetime -= 1;
         // Remove lines if there are too many.
@@ -75,42 +77,51 @@ impl LinePoint {
         self.lines
             .retain(|x| x.borrow().pos.distance2(local_pos) < MAX_LINE_LENGTH2);
 
-        // Move towards connected dots it's far away from and away from close ones.
-        self.vel *= friction; // Velocity damping, 0.7 is gooood
-        let dist_range = Range::new(0.0, MAX_LINE_LENGTH2);
-        let vel_range = Range::new(-self.force_strength, self.force_strength);
-        for lp in &self.lines {
-            let dist2 = lp.borrow().pos.distance2(local_pos);
-            let force = dist_range.map_value(dist2, &vel_range);
-            self.vel += (lp.borrow().pos - self.pos) * force;
-        }
-
-        // Get movement speed from image
-        let img_pos = pt2(
-            (self.pos.x + screen_off.x) * img_mult,
-            (screen_off.y - self.pos.y) * img_mult,
-        );
-        // Check that the position if within bounds
-        let luma =
-            if img_pos.x >= 0.0 && img_pos.x < 400.0 && img_pos.y >= 0.0 && img_pos.y < 283.0 {
-                img.get_pixel(img_pos.x as u32, img_pos.y as u32).to_luma()[0]
-            } else {
-                0
-            };
-
-        let speed = 1.0 - (luma as f32 / 100.0).min(1.0).powf(2.0);
-
-        self.pos += self.vel * speed;
-
+        // The speed in units per frame, like before the fixed timestep.
+        let speed2 = (self.vel / REFERENCE_RATE).magnitude2();
         let speed_range = Range::new(0.0, 10.0);
         let freq_range = Range::new(100.0, 1000.0);
-        let freq: f64 =
-            speed_range.map_value(self.vel.distance2(pt2(0.0, 0.0)) as f64, &freq_range);
+        let freq: f64 = speed_range.map_value(speed2 as f64, &freq_range);
         // audio_interface.set_sine_freq(self.sine_i, freq);
+    }
+
+    /// Move the point by `dt` seconds with its own force strength, slowed
+    /// down by the brightness of `field`. `drag` is per second, `neighbours`
+    /// is scratch space, reused for every point.
+    fn step(
+        &mut self,
+        win_rect: &nannou::geom::rect::Rect,
+        boundary: &Boundary,
+        dt: f32,
+        drag: f32,
+        field: &ImageField,
+        neighbours: &mut Vec<Point2>,
+    ) {
+        neighbours.clear();
+        neighbours.extend(self.lines.iter().map(|lp| lp.borrow().pos));
+        let neighbours = &*neighbours;
+        let stiffness = per_frame_force(self.force_strength);
+        let dist_range = Range::new(0.0, MAX_LINE_LENGTH2);
+        let accel_range = Range::new(-stiffness, stiffness);
+        let accel = |pos: Point2| {
+            let mut accel = vec2(0.0, 0.0);
+            // Move towards connected dots it's far away from and away from close ones.
+            for &np in neighbours {
+                accel += (np - pos) * dist_range.map_value(np.distance2(pos), &accel_range);
+            }
+            accel
+        };
+
+        // Get movement speed from image, points are slow in bright areas
+        let luma = field.luma(self.pos) * 255.0;
+        let speed = 1.0 - (luma / 100.0).min(1.0).powf(2.0);
+
+        let before = self.pos;
+        INTEGRATOR.step(&mut self.pos, &mut self.vel, dt, drag, accel);
+        self.pos = before + (self.pos - before) * speed;
 
-        // Wrap at the edges of the screen.
-        self.pos.x = self.pos.x.max(win_rect.left()).min(win_rect.right());
-        self.pos.y = self.pos.y.max(win_rect.bottom()).min(win_rect.top());
+        // Keep the point inside the sketch.
+        boundary.apply(*win_rect, &mut self.pos, &mut self.vel, dt);
     }
 
     fn trigger_sound(&self, audio_interface: &mut son::AudioInterface) {
@@ -136,12 +147,15 @@ struct Model {
     widget_ids: Ids,
     friction: f32,
     max_lines: usize,
+    /// Per frame at `REFERENCE_RATE` like the slider.
     force_strength: f32,
+    clock: FixedStep,
+    neighbours: Vec<Point2>,
     show_gui: bool,
     shape_angle: f32,
-    image: ImgBuf,
-    img_mult: f32,
-    screen_off: Point2,
+    field: ImageField,
+    video: Option<VideoField>,
+    boundary: Boundary,
 }
 
 impl Model {}
@@ -171,47 +185,41 @@ fn model(app: &App) -> Model {
 
     // Load an image
     let image_path = app.assets_path().unwrap().join("images").join("corona.jpg");
-    let image = image::open(image_path).unwrap();
-    let (img_w, img_h) = image.dimensions();
+    let field = ImageField::open(image_path, ImageFit::Fill, app.window_rect()).unwrap();
+    let (img_w, img_h) = field.image().dimensions();
     println!("img dimensions: {}x{}", img_w, img_h);
-    let (win_w, win_h) = app.window_rect().w_h();
-    println!("window dimanesions: {}x{}", win_w, win_h);
-    let img_ratio = win_h as f32 / img_h as f32;
-    println!("img ratiO: {}", img_ratio);
-    let image_rgba = image.into_rgba();
 
-    let mut points = vec![];
-    let left = app.window_rect().left();
-    let top = app.window_rect().top();
-    let screen_off = pt2(app.window_rect().right(), top);
-    let prob = 4000.0 / (img_w * img_h) as f32;
     // Fill screen with points from the image
-    for (x, y, pixel) in image_rgba.enumerate_pixels() {
-        let screen_point = pt2((x as f32 * img_ratio) + left, top - y as f32 * img_ratio);
-        let random_offset = pt2(
-            random_range(-250.0, 250.0),
-            random_range(-250.0, 250.0)
-        );
-        let luma = pixel.to_luma()[0];
-        // println!("luma: {:?}", luma);
-        if random_f32() < (luma as f32 / 255.0) * prob {
-            let rgb_vals: Vec<f32> = pixel
-                .channels()
-                .iter()
-                .cloned()
-                .map(|x| x as f32 / 255.0)
-                .collect();
-            let color: Rgba = rgba(rgb_vals[0], rgb_vals[1], rgb_vals[2], rgb_vals[3] * 0.3);
-            let new_point = Rc::new(RefCell::new(LinePoint::new_at(
-                screen_point + random_offset,
-                0,
-                LIFETIME,
-                color,
-            )));
-            points.push(new_point);
-        }
+    let mut density = ImageDensity::new(field, 4000.0);
+    density.jitter = 250.0;
+    let mut points = vec![];
+    for seed in density.seeds(&EmitContext::from_app(app)) {
+        let mut color = seed.color.unwrap();
+        color.alpha *= 0.3;
+        let new_point = Rc::new(RefCell::new(LinePoint::new_at(seed.pos, 0, LIFETIME, color)));
+        points.push(new_point);
     }
     println!("Done with placing {} points!", points.len());
+    let field = density.field;
+
+    let video = if VIDEO {
+        let video_path = app.assets_path().unwrap().join("video");
+        match Source::from_path(video_path) {
+            Ok(source) => Some(VideoField::open(
+                source,
+                Loading::Stream { buffer: 8 },
+                true,
+                ImageFit::Fill,
+                app.window_rect(),
+            )),
+            Err(e) => {
+                println!("Couldn't open the video: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
 
     // let mut point_dist: f32 = MAX_LINE_LENGTH2.sqrt();
     // point_dist *= 0.6;
@@ -247,6 +255,8 @@ fn model(app: &App) -> Model {
     // }
     // }
 
+    let boundary = Boundary::Clamp;
+
     Model {
         _window,
         // audio_interface,
@@ -256,15 +266,17 @@ fn model(app: &App) -> Model {
         friction: 0.7,
         max_lines: 4,
         force_strength: 0.25,
+        clock: FixedStep::new(STEP_RATE),
+        neighbours: Vec::with_capacity(MAX_LINES_EVER),
         show_gui: false,
         shape_angle: 0.0,
-        image: image_rgba,
-        img_mult: img_ratio,
-        screen_off,
+        field,
+        video,
+        boundary,
     }
 }
 
-fn update(app: &App, model: &mut Model, _update: Update) {
+fn update(app: &App, model: &mut Model, update: Update) {
     {
         // Calling `set_widgets` allows us to instantiate some widgets.
         let ui = &mut model.ui.set_widgets();
@@ -356,16 +368,42 @@ fn update(app: &App, model: &mut Model, _update: Update) {
     }
 
     // Update all points.
+    if let Some(video) = &mut model.video {
+        video.advance();
+        if let Some(e) = video.error() {
+            println!("Couldn't decode the video: {}", e);
+            model.video = None;
+        }
+    }
+    let field = match &model.video {
+        Some(video) => video.field(),
+        None => &model.field,
+    };
     for p in &model.points {
         p.borrow_mut().max_lines = model.max_lines;
-        p.borrow_mut().update(
-            &app.window_rect(),
-            model.friction,
-            model.force_strength,
-            &model.image,
-            model.img_mult,
-            &model.screen_off,
-        ); // &mut model.audio_interface);
+        p.borrow_mut().update(model.force_strength); // &mut model.audio_interface);
+    }
+
+    // Move them in fixed steps, every rendered frame is the same time apart
+    // when rendering. Friction is per frame like the slider.
+    let win_rect = app.window_rect();
+    let elapsed = if RENDER {
+        1.0 / REFERENCE_RATE
+    } else {
+        update.since_last.as_secs_f32()
+    };
+    let drag = drag_from_friction(model.friction);
+    for _ in 0..model.clock.steps(elapsed) {
+        for p in &model.points {
+            p.borrow_mut().step(
+                &win_rect,
+                &model.boundary,
+                model.clock.dt,
+                drag,
+                field,
+                &mut model.neighbours,
+            );
+        }
     }
 
     // Remove expired points.
@@ -431,7 +469,8 @@ fn view(app: &App, model: &Model, frame: Frame) {
 
     draw.background().color(hsl(hue, 0.5, 0.0 + lightness));
     for p in &model.points {
-        let vel = p.borrow().vel;
+        // In units per frame, like before the fixed timestep.
+        let vel = p.borrow().vel / REFERENCE_RATE;
         let lifeforce = vel.distance(pt2(0.0, 0.0)).min(2.9) / 3.0;
         let pos = p.borrow().pos;
         let color = p.borrow().color;
@@ -1,11 +1,19 @@
+use audio_utils::param::{Smoothed, Smoothing, DEFAULT_SMOOTHING_MS};
+use audio_utils::rt_audit;
 use nannou_audio as audio;
 use nannou_audio::Buffer;
 use std::f64::consts::PI;
 
 pub const NUM_SINES: usize = 1000;
 
+/// The sample rate the sines are created with, the real one is set once the
+/// stream renders.
+const INITIAL_SAMPLE_RATE: f64 = 44100.0;
+
 pub struct AudioInterface {
   stream: audio::Stream<Audio>,
+  // Reports on the render callback when dropped, with the rt_audit feature.
+  _audit: rt_audit::Monitor,
   next_free_sine: usize,
   amp_changes: Vec<(usize, f32)>,
   freq_changes: Vec<(usize, f64)>,
@@ -49,6 +57,7 @@ impl AudioInterface {
 
     AudioInterface {
       stream,
+      _audit: rt_audit::Monitor::start(),
       next_free_sine: 0,
       amp_changes: vec![],
       freq_changes: vec![],
@@ -80,6 +89,8 @@ impl AudioInterface {
     let freq_changes = self.freq_changes.clone();
     self.stream
       .send(move |audio| {
+        // The closure runs on the audio thread and drops the changes there.
+        let _audit = rt_audit::enter();
         for (i, amp) in amp_changes {
           audio.set_sine_amp(i, amp);
         }
@@ -106,17 +117,21 @@ impl Audio {
   }
   
   pub fn set_sine_freq(&mut self, index: usize, freq: f64) {
-    self.sines[index].hz = freq;
+    self.sines[index].hz.set(freq);
   }
   pub fn set_sine_amp(&mut self, index: usize, amp: f32) {
-    self.sines[index].amp = amp;
+    self.sines[index].amp.set(amp as f64);
   }
 }
 
 // A function that renders the given `Audio` to the given `Buffer`.
 pub fn audio(audio: &mut Audio, buffer: &mut Buffer) {
+  let _audit = rt_audit::callback(buffer.len_frames(), buffer.sample_rate() as usize);
   let sample_rate = buffer.sample_rate() as f64;
   let volume = 0.5;
+  for sine in audio.sines.iter_mut() {
+    sine.set_sample_rate(sample_rate);
+  }
   for frame in buffer.frames_mut() {
     let mut sample: f32 = 0.0;
     for sine in audio.sines.iter_mut() {
@@ -133,34 +148,38 @@ pub fn audio(audio: &mut Audio, buffer: &mut Buffer) {
   }
 }
 
+/// The frequency and amplitude glide per sample so changes don't click.
 #[derive(Copy, Clone)]
 struct Sine {
   phase: f64,
-  hz: f64,
-  amp: f32,
-  current_amp: f32,
+  hz: Smoothed,
+  amp: Smoothed,
 }
 
 impl Sine {
   pub fn new() -> Self {
     Sine {
       phase: 0.0,
-      hz: 220.0,
-      amp: 0.0,
-      current_amp: 0.0,
+      hz: Smoothed::new(220.0, INITIAL_SAMPLE_RATE, DEFAULT_SMOOTHING_MS, Smoothing::OnePole),
+      amp: Smoothed::new(0.0, INITIAL_SAMPLE_RATE, DEFAULT_SMOOTHING_MS, Smoothing::Linear),
     }
   }
 
+  fn set_sample_rate(&mut self, sample_rate: f64) {
+    self.hz.set_sample_rate(sample_rate);
+    self.amp.set_sample_rate(sample_rate);
+  }
+
   fn next_sample(&mut self, sample_rate: f64) -> f32 {
     let sine_amp = (2.0 * PI * self.phase).sin() as f32;
-    self.phase += self.hz / sample_rate;
+    self.phase += self.hz.next() / sample_rate;
     self.phase %= sample_rate;
-    return sine_amp * self.current_amp;
+    return sine_amp * self.amp.next() as f32;
   }
 
   fn update(&mut self) {
-    self.amp *= 0.95;
-    self.current_amp = self.current_amp * 0.95 + self.amp * 0.05;
+    // Decay once per buffer, the smoothing ramps between the steps.
+    self.amp.set(self.amp.target() * 0.95);
   }
 }
 
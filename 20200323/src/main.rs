@@ -10,12 +10,18 @@ use rand::prelude::*;
 
 use std::cell::RefCell;
 use std::rc::Rc;
+use sketch_utils::emit::{EmitContext, Emitter, EmitterExt, Rings, Shuffled};
 extern crate rand;
 
 type ImgBuf = image::ImageBuffer<image::Rgba<u8>, Vec<u8>>;
 
 mod son;
 
+/// Counts the allocations made in the audio render callback.
+#[cfg(feature = "rt_audit")]
+#[global_allocator]
+static ALLOCATOR: audio_utils::rt_audit::CountingAllocator = audio_utils::rt_audit::CountingAllocator;
+
 const MAX_LINE_LENGTH2: f32 = 2500.0;
 const MAX_LINES_EVER: usize = 20;
 const LIFETIME: i32 = 20;
@@ -127,34 +133,19 @@ struct Model {
     show_gui: bool,
     shape_angle: f32,
     ring_width: f32,
+    rings: Shuffled<Rings>,
     points_removed: usize,
 }
 
 impl Model {
-    fn generate_points(&mut self) {
-        let points = &mut self.points;
-        points.clear();
-        let mut rng = rand::thread_rng();
-        // Create rings of points
-        // let ring_width = 40.0;
-        for ring in 1..15 {
-            let r = ring as f32 * self.ring_width;
-            let num_points = (r * PI * 0.04);
-            let num_pointsi = num_points as usize;
-            let angle_offset = rng.gen::<f32>()*10.0;
-            for n in 0..num_pointsi {
-                let angle_offset2 = rng.gen::<f32>() * 0.3;
-                let angle = (angle_offset + angle_offset2 + PI * 2.0 * n as f32) / num_points;
-                let new_point = Rc::new(RefCell::new(LinePoint::new_at(
-                    pt2(angle.cos() * r, angle.sin() * r),
-                    0,
-                    LIFETIME,
-                )));
-                points.push(new_point);
-            }
+    fn generate_points(&mut self, app: &App) {
+        self.points.clear();
+        self.rings.emitter.width = self.ring_width;
+        self.rings.reset();
+        for seed in self.rings.seeds(&EmitContext::from_app(app)) {
+            let new_point = Rc::new(RefCell::new(LinePoint::new_at(seed.pos, 0, LIFETIME)));
+            self.points.push(new_point);
         }
-        // Shuffling changes the order the points are checked for distance -> the order they are connected
-        points.shuffle(&mut rng);
     }
 }
 
@@ -218,6 +209,14 @@ fn model(app: &App) -> Model {
     // }
     // }
 
+    // Rings of points, shuffling changes the order the points are checked for
+    // distance -> the order they are connected
+    let mut rings = Rings::new(14, 30.0, 50.0);
+    rings.rotation = 10.0;
+    rings.jitter = 0.3;
+    rings.per_point = true;
+    let rings = rings.shuffled();
+
     let mut model = Model {
         _window,
         // audio_interface,
@@ -231,9 +230,10 @@ fn model(app: &App) -> Model {
         show_gui: false,
         shape_angle: 0.0,
         ring_width: 30.0,
+        rings,
         points_removed: 10,
     };
-    model.generate_points();
+    model.generate_points(app);
     model
 }
 
@@ -296,7 +296,7 @@ fn update(app: &App, model: &mut Model, _update: Update) {
 
     // Every x frames, generate new points and activate one of them
     // if app.elapsed_frames() % 140 == 0 {
-    //     model.generate_points();
+    //     model.generate_points(app);
     //     model.points.choose(&mut thread_rng()).unwrap().borrow_mut().activated = true;;
     // }
 
@@ -308,7 +308,7 @@ fn update(app: &App, model: &mut Model, _update: Update) {
     if active_points == 0 {
         if model.points.len() < 50 {
             // Generate a new set of points
-            model.generate_points();
+            model.generate_points(app);
             model.isolated_points = vec![];
             // remove a number of points
             // let num_points_to_remove = (thread_rng().gen::<f32>() * model.points.len() as f32) as usize;
@@ -383,7 +383,7 @@ fn update(app: &App, model: &mut Model, _update: Update) {
     // println!("fps: {}, points: {}", app.fps(), model.points.len());
 }
 
-fn window_event(_app: &App, model: &mut Model, event: WindowEvent) {
+fn window_event(app: &App, model: &mut Model, event: WindowEvent) {
     match event {
         KeyPressed(key) => {
             match key {
@@ -402,7 +402,7 @@ fn window_event(_app: &App, model: &mut Model, event: WindowEvent) {
                     model.show_gui = !model.show_gui;
                 }
                 Key::C => {
-                    model.generate_points();
+                    model.generate_points(app);
                     let num_points_to_remove = (thread_rng().gen::<f32>() * model.points.len() as f32) as usize;
                     for _ in 0..num_points_to_remove {
                         model.points.pop();
@@ -10,12 +10,18 @@ use rand::prelude::*;
 
 use std::cell::RefCell;
 use std::rc::Rc;
+use sketch_utils::emit::{EmitContext, Emitter, EmitterExt, Rings, Shuffled};
 extern crate rand;
 
 type ImgBuf = image::ImageBuffer<image::Rgba<u8>, Vec<u8>>;
 
 mod son;
 
+/// Counts the allocations made in the audio render callback.
+#[cfg(feature = "rt_audit")]
+#[global_allocator]
+static ALLOCATOR: audio_utils::rt_audit::CountingAllocator = audio_utils::rt_audit::CountingAllocator;
+
 const MAX_LINE_LENGTH2: f32 = 300.0;
 const MAX_LINES_EVER: usize = 20;
 const LIFETIME: i32 = 120;
@@ -129,37 +135,21 @@ struct Model {
     show_gui: bool,
     shape_angle: f32,
     ring_width: f32,
+    rings: Shuffled<Rings>,
     points_removed: usize,
 }
 
 impl Model {
-    fn generate_points(&mut self) {
-        let points = &mut self.points;
-        points.clear();
-        let mut rng = rand::thread_rng();
-        // Create rings of points
-        // let ring_width = 40.0;
-        let num_rings = 200;
-        for ring in 1..num_rings {
-            let r = ring as f32 * self.ring_width;
-            let num_points = (r * PI * 0.04);
-            let num_pointsi = num_points as usize;
-            let angle_offset = rng.gen::<f32>()*0.1;
-            for n in 0..num_pointsi {
-                let angle_offset2 = rng.gen::<f32>() * 0.3;
-                let angle = ((PI * 2.0 * n as f32) / num_points) + angle_offset + angle_offset2;
-                let mut new_point = Rc::new(RefCell::new(LinePoint::new_at(
-                    pt2(angle.cos() * r, angle.sin() * r),
-                    0,
-                    LIFETIME,
-                )));
-                new_point.borrow_mut().max_lines = random_range(1, 3);
-                new_point.borrow_mut().hue = ring as f32 / num_rings as f32;
-                points.push(new_point);
-            }
+    fn generate_points(&mut self, app: &App) {
+        self.points.clear();
+        self.rings.emitter.width = self.ring_width;
+        self.rings.reset();
+        for seed in self.rings.seeds(&EmitContext::from_app(app)) {
+            let new_point = Rc::new(RefCell::new(LinePoint::new_at(seed.pos, 0, LIFETIME)));
+            new_point.borrow_mut().max_lines = random_range(1, 3);
+            new_point.borrow_mut().hue = seed.t;
+            self.points.push(new_point);
         }
-        // Shuffling changes the order the points are checked for distance -> the order they are connected
-        points.shuffle(&mut rng);
     }
 }
 
@@ -223,6 +213,13 @@ fn model(app: &App) -> Model {
     // }
     // }
 
+    // Rings of points, shuffling changes the order the points are checked for
+    // distance -> the order they are connected
+    let mut rings = Rings::new(199, 2.5, 50.0);
+    rings.rotation = 0.1;
+    rings.jitter = 0.3;
+    let rings = rings.shuffled();
+
     let mut model = Model {
         _window,
         // audio_interface,
@@ -236,9 +233,10 @@ fn model(app: &App) -> Model {
         show_gui: false,
         shape_angle: 0.0,
         ring_width: 2.5,
+        rings,
         points_removed: 10,
     };
-    model.generate_points();
+    model.generate_points(app);
     model
 }
 
@@ -301,7 +299,7 @@ fn update(app: &App, model: &mut Model, _update: Update) {
 
     // Every x frames, generate new points and activate one of them
     // if app.elapsed_frames() % 140 == 0 {
-    //     model.generate_points();
+    //     model.generate_points(app);
     //     model.points.choose(&mut thread_rng()).unwrap().borrow_mut().activated = true;;
     // }
 
@@ -313,7 +311,7 @@ fn update(app: &App, model: &mut Model, _update: Update) {
     if active_points == 0 {
         if model.points.len() < 5000 {
             // Generate a new set of points
-            model.generate_points();
+            model.generate_points(app);
             model.isolated_points = vec![];
             // remove a number of points
             // let num_points_to_remove = (thread_rng().gen::<f32>() * model.points.len() as f32) as usize;
@@ -392,7 +390,7 @@ fn update(app: &App, model: &mut Model, _update: Update) {
     // println!("fps: {}, points: {}", app.fps(), model.points.len());
 }
 
-fn window_event(_app: &App, model: &mut Model, event: WindowEvent) {
+fn window_event(app: &App, model: &mut Model, event: WindowEvent) {
     match event {
         KeyPressed(key) => {
             match key {
@@ -411,7 +409,7 @@ fn window_event(_app: &App, model: &mut Model, event: WindowEvent) {
                     model.show_gui = !model.show_gui;
                 }
                 Key::C => {
-                    model.generate_points();
+                    model.generate_points(app);
                     let num_points_to_remove = (thread_rng().gen::<f32>() * model.points.len() as f32) as usize;
                     for _ in 0..num_points_to_remove {
                         model.points.pop();
@@ -0,0 +1,56 @@
+//! Minimal command line parsing shared by the synths.
+//!
+//! Every group of options (render, backend, connections) takes the arguments
+//! it knows from the list, so the groups can be combined freely. Whatever is
+//! left at the end is an unknown argument.
+
+/// Remove every `name <value>` pair from `args` and return the values in order.
+pub fn take_values(args: &mut Vec<String>, name: &str) -> Result<Vec<String>, String> {
+    let mut values = vec![];
+    let mut i = 0;
+    while i < args.len() {
+        if args[i] == name {
+            if i + 1 >= args.len() {
+                return Err(format!("Missing value for {}", name));
+            }
+            values.push(args.remove(i + 1));
+            args.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+    Ok(values)
+}
+
+/// Remove `name <value>` from `args`, if it is given more than once the last
+/// value is used.
+pub fn take_value(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    Ok(take_values(args, name)?.pop())
+}
+
+/// Like `take_value`, parsing the value with `parse`.
+pub fn take_parsed<T, F>(args: &mut Vec<String>, name: &str, parse: F) -> Result<Option<T>, String>
+where
+    F: Fn(&str) -> Result<T, String>,
+{
+    match take_value(args, name)? {
+        Some(value) => parse(&value).map(Some).map_err(|e| format!("{}: {}", name, e)),
+        None => Ok(None),
+    }
+}
+
+/// Remove every occurrence of the flag `name` and return whether it was given.
+pub fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let len = args.len();
+    args.retain(|a| a != name);
+    args.len() != len
+}
+
+/// Return an error listing the arguments that no group of options took.
+pub fn finish(args: &[String], usage: &str) -> Result<(), String> {
+    if args.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Unknown arguments {}\n{}", args.join(" "), usage))
+    }
+}
@@ -0,0 +1,197 @@
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use super::jack_connect::{ConnectionRules, JackNotifications};
+use super::jack_transport::{self, Timebase, TransportSettings};
+use super::{AudioBackend, AudioBuffer, AudioProcess, BackendError, ProcessInfo, MAX_CHANNELS};
+use crate::rt_audit;
+
+/// Runs the process callback as a JACK client with one output port per channel.
+pub struct JackBackend {
+    client: jack::Client,
+    ports: Vec<jack::Port<jack::AudioOut>>,
+    port_names: Vec<String>,
+    rules: ConnectionRules,
+    transport: TransportSettings,
+}
+
+impl JackBackend {
+    /// Open a client called `client_name` and register an output port for
+    /// every name in `port_names`. The JACK server has to be running already.
+    pub fn new(client_name: &str, port_names: &[&str]) -> Result<Self, BackendError> {
+        assert!(port_names.len() <= MAX_CHANNELS);
+        let (client, _status) =
+            jack::Client::new(client_name, jack::ClientOptions::NO_START_SERVER)?;
+        let mut ports = vec![];
+        for name in port_names {
+            ports.push(client.register_port(name, jack::AudioOut::default())?);
+        }
+        Ok(JackBackend {
+            client,
+            ports,
+            port_names: port_names.iter().map(|n| n.to_string()).collect(),
+            rules: ConnectionRules::default(),
+            transport: TransportSettings::default(),
+        })
+    }
+
+    /// Set how the output ports are connected once the client is running.
+    pub fn connection_rules(mut self, rules: ConnectionRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Set whether the process callback follows the transport and whether
+    /// the client becomes timebase master.
+    pub fn transport(mut self, transport: TransportSettings) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn client(&self) -> &jack::Client {
+        &self.client
+    }
+}
+
+impl AudioBackend for JackBackend {
+    type Running = JackRunning;
+
+    fn sample_rate(&self) -> usize {
+        self.client.sample_rate()
+    }
+
+    fn buffer_size(&self) -> usize {
+        self.client.buffer_size() as usize
+    }
+
+    fn channels(&self) -> usize {
+        self.ports.len()
+    }
+
+    fn start<P: AudioProcess>(self, process: P) -> Result<JackRunning, BackendError> {
+        let JackBackend {
+            client,
+            ports,
+            port_names,
+            rules,
+            transport,
+        } = self;
+        let handler = JackProcess {
+            sample_rate: client.sample_rate(),
+            ports,
+            process: Box::new(process),
+            frames_processed: 0,
+            transport: if transport.follow {
+                Some(client.transport())
+            } else {
+                None
+            },
+        };
+        let timebase = transport
+            .master
+            .map(|tempo| Box::new(Timebase::new(tempo, client.sample_rate())));
+        let (reconnect_tx, reconnect_rx) = mpsc::sync_channel(1);
+        let notifications = JackNotifications {
+            reconnect: if rules.auto_connect && rules.reconnect {
+                Some(reconnect_tx)
+            } else {
+                None
+            },
+        };
+        let active_client = Arc::new(client.activate_async(notifications, handler)?);
+        let running = JackRunning {
+            active_client,
+            port_names,
+            timebase,
+        };
+        if let Some(timebase) = &running.timebase {
+            timebase.register(running.client())?;
+        }
+
+        // Connecting only works after the client has been activated.
+        let outputs = running.port_names();
+        rules.apply(running.client(), &outputs);
+        if rules.auto_connect && rules.reconnect {
+            // The thread only holds a weak reference so that dropping
+            // `JackRunning` deactivates the client, which drops the sender
+            // in the notification handler and ends the thread.
+            let client = Arc::downgrade(&running.active_client);
+            thread::spawn(move || {
+                while reconnect_rx.recv().is_ok() {
+                    match client.upgrade() {
+                        Some(client) => rules.apply(client.as_client(), &outputs),
+                        None => break,
+                    }
+                }
+            });
+        }
+        Ok(running)
+    }
+}
+
+/// The JACK process handler calling the `AudioProcess` with the port buffers.
+pub struct JackProcess {
+    sample_rate: usize,
+    ports: Vec<jack::Port<jack::AudioOut>>,
+    process: Box<dyn AudioProcess>,
+    frames_processed: u64,
+    /// Set when following the transport.
+    transport: Option<jack::Transport>,
+}
+
+impl jack::ProcessHandler for JackProcess {
+    fn process(&mut self, _: &jack::Client, ps: &jack::ProcessScope) -> jack::Control {
+        let _audit = rt_audit::callback(ps.n_frames() as usize, self.sample_rate);
+        let channels = self.ports.len();
+        // Collect the port buffers on the stack to not allocate in the callback.
+        let mut slices: [&mut [f32]; MAX_CHANNELS] = Default::default();
+        for (slice, port) in slices.iter_mut().zip(self.ports.iter_mut()) {
+            *slice = port.as_mut_slice(ps);
+        }
+        let info = ProcessInfo {
+            sample_rate: self.sample_rate,
+            buffer_size: ps.n_frames() as usize,
+            frames_processed: self.frames_processed,
+            transport: self
+                .transport
+                .as_ref()
+                .and_then(|transport| jack_transport::query(transport, self.sample_rate)),
+        };
+        self.process
+            .process(&mut AudioBuffer::NonInterleaved(&mut slices[..channels]), &info);
+        self.frames_processed += info.buffer_size as u64;
+        jack::Control::Continue
+    }
+}
+
+/// A running JACK client, deactivated when dropped.
+pub struct JackRunning {
+    active_client: Arc<jack::AsyncClient<JackNotifications, JackProcess>>,
+    port_names: Vec<String>,
+    /// Read by the timebase callback, released before it is dropped.
+    timebase: Option<Box<Timebase>>,
+}
+
+impl Drop for JackRunning {
+    fn drop(&mut self) {
+        if self.timebase.is_some() {
+            Timebase::release(self.client());
+        }
+    }
+}
+
+impl JackRunning {
+    pub fn client(&self) -> &jack::Client {
+        self.active_client.as_client()
+    }
+
+    /// The full names (`client:port`) of the output ports. The client name is
+    /// the one JACK actually gave us, which may differ from the one asked for.
+    pub fn port_names(&self) -> Vec<String> {
+        let client_name = self.client().name();
+        self.port_names
+            .iter()
+            .map(|port| format!("{}:{}", client_name, port))
+            .collect()
+    }
+}
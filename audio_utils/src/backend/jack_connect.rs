@@ -0,0 +1,189 @@
+//! Rules for automatically connecting the output ports of a JACK client.
+use crate::args;
+
+pub const DEFAULT_TARGET: &str = "system:playback_.*";
+
+pub const CONNECT_USAGE: &str = "    --connect <regex>      input ports to connect the outputs to, can be given several times (default system:playback_.*)
+    --no-connect           don't connect the outputs automatically
+    --no-reconnect         don't connect again when ports appear
+    --no-fanout            don't connect a mono output to both of the first two targets";
+
+/// How the output ports are connected after the client has been activated.
+///
+/// The input ports matching the `targets` regexes are collected in order,
+/// without duplicates. Output port `n` is connected to target `n`. With a single
+/// output and `fanout` it is connected to the first two targets, and with a
+/// single target every output is connected to it.
+#[derive(Debug, Clone)]
+pub struct ConnectionRules {
+    pub targets: Vec<String>,
+    pub auto_connect: bool,
+    /// Apply the rules again when ports are registered. Graph changes alone
+    /// don't reconnect, so ports disconnected by hand stay disconnected.
+    pub reconnect: bool,
+    pub fanout: bool,
+}
+
+impl Default for ConnectionRules {
+    fn default() -> Self {
+        ConnectionRules {
+            targets: vec![DEFAULT_TARGET.to_string()],
+            auto_connect: true,
+            reconnect: true,
+            fanout: true,
+        }
+    }
+}
+
+impl ConnectionRules {
+    /// Take the connection options out of the program arguments.
+    pub fn from_args(args: &mut Vec<String>) -> Result<Self, String> {
+        let mut rules = ConnectionRules::default();
+        let targets = args::take_values(args, "--connect")?;
+        if !targets.is_empty() {
+            rules.targets = targets;
+        }
+        rules.auto_connect = !args::take_flag(args, "--no-connect");
+        rules.reconnect = !args::take_flag(args, "--no-reconnect");
+        rules.fanout = !args::take_flag(args, "--no-fanout");
+        Ok(rules)
+    }
+
+    /// The input ports currently matching the target regexes.
+    pub fn target_ports(&self, client: &jack::Client) -> Vec<String> {
+        let mut ports: Vec<String> = vec![];
+        for pattern in &self.targets {
+            for port in client.ports(Some(pattern), Some("audio"), jack::PortFlags::IS_INPUT) {
+                if !ports.contains(&port) {
+                    ports.push(port);
+                }
+            }
+        }
+        ports
+    }
+
+    /// Pair up the `outputs` with the `targets` according to the rules.
+    pub fn plan(&self, outputs: &[String], targets: &[String]) -> Vec<(String, String)> {
+        if !self.auto_connect || outputs.is_empty() || targets.is_empty() {
+            return vec![];
+        }
+        if outputs.len() == 1 && self.fanout {
+            targets
+                .iter()
+                .take(2)
+                .map(|target| (outputs[0].clone(), target.clone()))
+                .collect()
+        } else if targets.len() == 1 {
+            outputs
+                .iter()
+                .map(|output| (output.clone(), targets[0].clone()))
+                .collect()
+        } else {
+            outputs.iter().cloned().zip(targets.iter().cloned()).collect()
+        }
+    }
+
+    /// Connect `outputs` (full port names) to the current target ports.
+    /// Connections that already exist are left alone, so this can be called
+    /// again when ports appear.
+    pub fn apply(&self, client: &jack::Client, outputs: &[String]) {
+        let targets = self.target_ports(client);
+        if self.auto_connect && targets.is_empty() {
+            println!("No input ports match {:?}, not connecting", self.targets);
+        }
+        for (output, target) in self.plan(outputs, &targets) {
+            match client.connect_ports_by_name(&output, &target) {
+                Ok(_) | Err(jack::Error::PortAlreadyConnected(_, _)) => (),
+                Err(e) => println!("Unable to connect {} to {} with error {:?}", output, target, e),
+            }
+        }
+    }
+}
+
+/// Tells the reconnection thread to apply the rules again when ports are
+/// registered. Connecting is not allowed from the notification callbacks
+/// themselves. Xruns are counted for `rt_audit`.
+pub struct JackNotifications {
+    pub(crate) reconnect: Option<std::sync::mpsc::SyncSender<()>>,
+}
+
+impl JackNotifications {
+    fn request_reconnect(&self) {
+        if let Some(tx) = &self.reconnect {
+            // A full channel means a reconnect is already pending.
+            tx.try_send(()).ok();
+        }
+    }
+}
+
+impl jack::NotificationHandler for JackNotifications {
+    fn port_registration(&mut self, _: &jack::Client, _port_id: jack::PortId, is_registered: bool) {
+        if is_registered {
+            self.request_reconnect();
+        }
+    }
+
+    fn xrun(&mut self, _: &jack::Client) -> jack::Control {
+        crate::rt_audit::xrun();
+        jack::Control::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ports(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(a, b)| (a.to_string(), b.to_string())).collect()
+    }
+
+    #[test]
+    fn outputs_are_paired_with_targets_in_order() {
+        let rules = ConnectionRules::default();
+        let outputs = ports(&["fm:out_1", "fm:out_2", "fm:out_3"]);
+        let targets = ports(&["system:playback_1", "system:playback_2"]);
+        assert_eq!(
+            rules.plan(&outputs, &targets),
+            pairs(&[("fm:out_1", "system:playback_1"), ("fm:out_2", "system:playback_2")])
+        );
+    }
+
+    #[test]
+    fn mono_output_fans_out_to_two_targets() {
+        let outputs = ports(&["fm:out"]);
+        let targets = ports(&["system:playback_1", "system:playback_2", "system:playback_3"]);
+        let rules = ConnectionRules::default();
+        assert_eq!(
+            rules.plan(&outputs, &targets),
+            pairs(&[("fm:out", "system:playback_1"), ("fm:out", "system:playback_2")])
+        );
+        let rules = ConnectionRules { fanout: false, ..ConnectionRules::default() };
+        assert_eq!(rules.plan(&outputs, &targets), pairs(&[("fm:out", "system:playback_1")]));
+    }
+
+    #[test]
+    fn every_output_goes_to_a_single_target() {
+        let rules = ConnectionRules::default();
+        let outputs = ports(&["fm:out_l", "fm:out_r"]);
+        let targets = ports(&["recorder:in"]);
+        assert_eq!(
+            rules.plan(&outputs, &targets),
+            pairs(&[("fm:out_l", "recorder:in"), ("fm:out_r", "recorder:in")])
+        );
+    }
+
+    #[test]
+    fn nothing_is_connected_without_auto_connect_or_ports() {
+        let outputs = ports(&["fm:out_l", "fm:out_r"]);
+        let targets = ports(&["system:playback_1", "system:playback_2"]);
+        let rules = ConnectionRules { auto_connect: false, ..ConnectionRules::default() };
+        assert!(rules.plan(&outputs, &targets).is_empty());
+        let rules = ConnectionRules::default();
+        assert!(rules.plan(&outputs, &[]).is_empty());
+        assert!(rules.plan(&[], &targets).is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+//! Following the JACK transport and acting as its timebase master.
+//!
+//! A client following the transport gets the state, frame and BBT position in
+//! `ProcessInfo::transport`. Only a timebase master fills in the BBT fields,
+//! that is usually a DAW, or this client with `--timebase-master <bpm>`.
+use std::os::raw::{c_int, c_void};
+
+use jack::jack_sys as j;
+
+use super::BackendError;
+use crate::args;
+use crate::transport::{BeatPosition, TransportInfo};
+
+pub const TRANSPORT_USAGE: &str = "    --transport            follow the JACK transport, sequencing is locked to its beats while it is rolling
+    --timebase-master <bpm> be the JACK timebase master at <bpm> beats per minute, implies --transport
+    --beats-per-bar <n>    the beats per bar as timebase master (default 4)";
+
+/// The resolution of the BBT position as timebase master.
+const TICKS_PER_BEAT: f64 = 1920.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tempo {
+    pub bpm: f64,
+    pub beats_per_bar: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransportSettings {
+    pub follow: bool,
+    /// Become timebase master with this tempo.
+    pub master: Option<Tempo>,
+}
+
+impl TransportSettings {
+    /// Take the transport options out of the program arguments.
+    pub fn from_args(args: &mut Vec<String>) -> Result<Self, String> {
+        let follow = args::take_flag(args, "--transport");
+        let bpm = args::take_parsed(args, "--timebase-master", parse_bpm)?;
+        let beats_per_bar = args::take_parsed(args, "--beats-per-bar", parse_beats_per_bar)?;
+        let master = bpm.map(|bpm| Tempo {
+            bpm,
+            beats_per_bar: beats_per_bar.unwrap_or(4),
+        });
+        Ok(TransportSettings {
+            follow: follow || master.is_some(),
+            master,
+        })
+    }
+}
+
+fn parse_bpm(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(bpm) if bpm > 0.0 => Ok(bpm),
+        _ => Err(format!("the tempo has to be a positive number of beats per minute, got \"{}\"", s)),
+    }
+}
+
+fn parse_beats_per_bar(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(beats) if beats > 0 => Ok(beats),
+        _ => Err(format!("the beats per bar have to be a positive integer, got \"{}\"", s)),
+    }
+}
+
+/// The transport at the start of the current cycle, realtime safe.
+pub(crate) fn query(transport: &jack::Transport, sample_rate: usize) -> Option<TransportInfo> {
+    let state = transport.query().ok()?;
+    let position = state.pos.bbt().map(|bbt| {
+        let mut beats = (bbt.bar - 1) as f64 * bbt.sig_num as f64
+            + (bbt.beat - 1) as f64
+            + bbt.tick as f64 / bbt.ticks_per_beat;
+        // The BBT fields can describe a frame before the start of the cycle.
+        if let Some(offset) = state.pos.bbt_offset() {
+            beats += offset as f64 * bbt.bpm / 60.0 / sample_rate as f64;
+        }
+        BeatPosition {
+            bpm: bbt.bpm,
+            beats_per_bar: bbt.sig_num as f64,
+            beats,
+        }
+    });
+    Some(TransportInfo {
+        rolling: state.state == jack::TransportState::Rolling,
+        frame: state.pos.frame() as u64,
+        position,
+    })
+}
+
+/// The state of the timebase callback. It is boxed and owned by `JackRunning`
+/// which releases the timebase before dropping it.
+pub(crate) struct Timebase {
+    tempo: Tempo,
+    sample_rate: f64,
+}
+
+impl Timebase {
+    pub(crate) fn new(tempo: Tempo, sample_rate: usize) -> Self {
+        Timebase {
+            tempo,
+            sample_rate: sample_rate as f64,
+        }
+    }
+
+    /// The BBT position at `frame` on the timeline, the tempo is constant.
+    fn bbt(&self, frame: u32) -> jack::TransportBBT {
+        let beats_per_bar = self.tempo.beats_per_bar as f64;
+        let beats = frame as f64 * self.tempo.bpm / 60.0 / self.sample_rate;
+        let bar = (beats / beats_per_bar).floor();
+        let beat_in_bar = beats - bar * beats_per_bar;
+        let beat = beat_in_bar.floor();
+        jack::TransportBBT {
+            bar: bar as usize + 1,
+            beat: beat as usize + 1,
+            tick: ((beat_in_bar - beat) * TICKS_PER_BEAT) as usize,
+            sig_num: beats_per_bar as f32,
+            sig_denom: 4.0,
+            ticks_per_beat: TICKS_PER_BEAT,
+            bpm: self.tempo.bpm,
+            bar_start_tick: bar * beats_per_bar * TICKS_PER_BEAT,
+        }
+    }
+
+    /// Take over as timebase master, even if another client is.
+    pub(crate) fn register(&self, client: &jack::Client) -> Result<(), BackendError> {
+        let arg = self as *const Timebase as *mut c_void;
+        // The callback only reads the `Timebase`, which outlives the
+        // registration, see `JackRunning`.
+        let result = unsafe { j::jack_set_timebase_callback(client.raw(), 0, Some(timebase_callback), arg) };
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(BackendError::Jack(jack::Error::UnknownError))
+        }
+    }
+
+    pub(crate) fn release(client: &jack::Client) {
+        unsafe {
+            j::jack_release_timebase(client.raw());
+        }
+    }
+}
+
+/// Called by JACK in the process thread to fill in the BBT fields of the
+/// position of the next cycle.
+unsafe extern "C" fn timebase_callback(
+    _state: j::jack_transport_state_t,
+    _nframes: j::jack_nframes_t,
+    pos: *mut j::jack_position_t,
+    _new_pos: c_int,
+    arg: *mut c_void,
+) {
+    let timebase = &*(arg as *const Timebase);
+    // `TransportPosition` is a transparent wrapper of `jack_position_t`.
+    let pos = &mut *(pos as *mut jack::TransportPosition);
+    let bbt = timebase.bbt(pos.frame());
+    pos.set_bbt(Some(bbt)).ok();
+    pos.set_bbt_offset(None).ok();
+}
@@ -0,0 +1,383 @@
+//! A common interface for running a process callback on different audio
+//! backends.
+//!
+//! The fm_synth crates used to talk to JACK directly and the sketches use
+//! `nannou_audio`. With the `AudioBackend` trait a synth only has to implement
+//! a process callback (see `AudioProcess`) and can then be run on JACK,
+//! cpal through `nannou_audio`, rendered to a WAV file or run on the null
+//! backend without any audio device, e.g. for testing.
+use std::fmt;
+
+#[cfg(feature = "jack")]
+mod jack_backend;
+#[cfg(feature = "jack")]
+mod jack_connect;
+#[cfg(feature = "jack")]
+mod jack_transport;
+#[cfg(feature = "nannou")]
+mod nannou_backend;
+mod null_backend;
+mod wav_backend;
+
+#[cfg(feature = "jack")]
+pub use self::jack_backend::{JackBackend, JackRunning};
+#[cfg(feature = "jack")]
+pub use self::jack_connect::{ConnectionRules, JackNotifications, CONNECT_USAGE};
+#[cfg(feature = "jack")]
+pub use self::jack_transport::{Tempo, TransportSettings, TRANSPORT_USAGE};
+#[cfg(feature = "nannou")]
+pub use self::nannou_backend::{NannouBackend, NannouRunning};
+pub use self::null_backend::{NullBackend, NullRunning};
+pub use self::wav_backend::WavBackend;
+
+use crate::args;
+use crate::render::{self, RenderSettings};
+use crate::transport::{BeatPosition, TransportInfo};
+
+/// The maximum number of channels a backend will pass to the process callback.
+pub const MAX_CHANNELS: usize = 16;
+
+/// The audio buffers for one call to the process callback.
+///
+/// JACK gives one slice per channel while cpal gives a single interleaved
+/// slice, the callback gets whichever the backend has without copying.
+pub enum AudioBuffer<'a, 'b> {
+    /// One slice per channel, `[[l0, l1, ..], [r0, r1, ..]]`.
+    NonInterleaved(&'a mut [&'b mut [f32]]),
+    /// All channels in one slice, `[l0, r0, l1, r1, ..]`.
+    Interleaved { samples: &'a mut [f32], channels: usize },
+}
+
+impl<'a, 'b> AudioBuffer<'a, 'b> {
+    pub fn channels(&self) -> usize {
+        match self {
+            AudioBuffer::NonInterleaved(channels) => channels.len(),
+            AudioBuffer::Interleaved { channels, .. } => *channels,
+        }
+    }
+
+    /// The number of frames in this buffer.
+    pub fn frames(&self) -> usize {
+        match self {
+            AudioBuffer::NonInterleaved(channels) => channels.first().map_or(0, |c| c.len()),
+            AudioBuffer::Interleaved { samples, channels } => samples.len() / (*channels).max(1),
+        }
+    }
+
+    pub fn sample_mut(&mut self, channel: usize, frame: usize) -> &mut f32 {
+        match self {
+            AudioBuffer::NonInterleaved(channels) => &mut channels[channel][frame],
+            AudioBuffer::Interleaved { samples, channels } => &mut samples[frame * *channels + channel],
+        }
+    }
+
+    /// Set every sample to 0.0.
+    pub fn clear(&mut self) {
+        match self {
+            AudioBuffer::NonInterleaved(channels) => {
+                for channel in channels.iter_mut() {
+                    for s in channel.iter_mut() {
+                        *s = 0.0;
+                    }
+                }
+            }
+            AudioBuffer::Interleaved { samples, .. } => {
+                for s in samples.iter_mut() {
+                    *s = 0.0;
+                }
+            }
+        }
+    }
+}
+
+/// Information about the current call to the process callback.
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessInfo {
+    pub sample_rate: usize,
+    /// The number of frames in this block.
+    pub buffer_size: usize,
+    /// The number of frames processed before this block since the backend
+    /// was started.
+    pub frames_processed: u64,
+    /// The host transport if the backend follows one, see `transport`.
+    pub transport: Option<TransportInfo>,
+}
+
+impl ProcessInfo {
+    /// The time in seconds of the first frame of this block.
+    pub fn time(&self) -> f64 {
+        self.frames_processed as f64 / self.sample_rate as f64
+    }
+
+    /// The tempo and position of the transport. `None` without a transport
+    /// and also when following one that has no timebase master.
+    pub fn beat_position(&self) -> Option<BeatPosition> {
+        self.transport.and_then(|t| t.position)
+    }
+}
+
+/// A process callback that can be run on any backend.
+pub trait AudioProcess: Send + 'static {
+    fn process(&mut self, buffer: &mut AudioBuffer, info: &ProcessInfo);
+}
+
+impl<F> AudioProcess for F
+where
+    F: FnMut(&mut AudioBuffer, &ProcessInfo) + Send + 'static,
+{
+    fn process(&mut self, buffer: &mut AudioBuffer, info: &ProcessInfo) {
+        (self)(buffer, info)
+    }
+}
+
+/// An audio backend that can be queried for its settings before it is started
+/// so that the synth can be set up for the right sample rate.
+pub trait AudioBackend {
+    /// What is returned by `start`. Audio keeps running until it is dropped.
+    type Running;
+
+    fn sample_rate(&self) -> usize;
+    /// The maximum number of frames passed to the process callback.
+    fn buffer_size(&self) -> usize;
+    fn channels(&self) -> usize;
+    /// `false` for offline backends where `start` returns after all audio
+    /// has been processed.
+    fn is_realtime(&self) -> bool {
+        true
+    }
+    fn start<P: AudioProcess>(self, process: P) -> Result<Self::Running, BackendError>;
+}
+
+#[derive(Debug)]
+pub enum BackendError {
+    #[cfg(feature = "jack")]
+    Jack(jack::Error),
+    Wav(hound::Error),
+    /// Errors from backends that only report a message, e.g. `nannou_audio`.
+    Stream(String),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "jack")]
+            BackendError::Jack(e) => write!(f, "JACK error: {:?}", e),
+            BackendError::Wav(e) => write!(f, "WAV error: {}", e),
+            BackendError::Stream(e) => write!(f, "Audio stream error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+#[cfg(feature = "jack")]
+impl From<jack::Error> for BackendError {
+    fn from(e: jack::Error) -> Self {
+        BackendError::Jack(e)
+    }
+}
+
+impl From<hound::Error> for BackendError {
+    fn from(e: hound::Error) -> Self {
+        BackendError::Wav(e)
+    }
+}
+
+/// Which backend to use, usually chosen on the command line.
+#[derive(Debug, Clone)]
+pub enum BackendKind {
+    #[cfg(feature = "jack")]
+    Jack(ConnectionRules, TransportSettings),
+    #[cfg(feature = "nannou")]
+    Nannou,
+    Null,
+    Wav(RenderSettings),
+}
+
+pub const BACKEND_USAGE: &str = "    --backend <name>       jack, nannou or null (default jack, wav if --render is given)";
+
+impl BackendKind {
+    /// Take `--backend <name>`, the render options from
+    /// `RenderSettings::from_args` and, for JACK, the connection and transport
+    /// options out of the program arguments. `--render` selects the WAV backend.
+    pub fn from_args(args: &mut Vec<String>) -> Result<Self, String> {
+        let name = args::take_value(args, "--backend")?;
+        #[cfg(feature = "jack")]
+        let rules = ConnectionRules::from_args(args)?;
+        #[cfg(feature = "jack")]
+        let transport = TransportSettings::from_args(args)?;
+        if let Some(settings) = RenderSettings::from_args(args)? {
+            return Ok(BackendKind::Wav(settings));
+        }
+        match name.as_ref().map(|n| n.as_str()) {
+            #[cfg(feature = "jack")]
+            None | Some("jack") => Ok(BackendKind::Jack(rules, transport)),
+            #[cfg(not(feature = "jack"))]
+            None => Ok(BackendKind::Null),
+            #[cfg(feature = "nannou")]
+            Some("nannou") | Some("cpal") => Ok(BackendKind::Nannou),
+            Some("null") => Ok(BackendKind::Null),
+            Some(other) => Err(format!("Unknown backend {}\n{}", other, usage())),
+        }
+    }
+}
+
+/// Usage of all the options taken by `BackendKind::from_args`.
+pub fn usage() -> String {
+    let mut usage = format!("Options:\n{}\n{}", BACKEND_USAGE, render::USAGE);
+    #[cfg(feature = "jack")]
+    {
+        usage.push('\n');
+        usage.push_str(CONNECT_USAGE);
+        usage.push('\n');
+        usage.push_str(TRANSPORT_USAGE);
+    }
+    usage
+}
+
+/// Any of the backends in this module, so that the backend can be chosen at
+/// runtime while still using the generic `AudioBackend::start`.
+pub enum AnyBackend {
+    #[cfg(feature = "jack")]
+    Jack(JackBackend),
+    #[cfg(feature = "nannou")]
+    Nannou(NannouBackend),
+    Null(NullBackend),
+    Wav(WavBackend),
+}
+
+pub enum AnyRunning {
+    #[cfg(feature = "jack")]
+    Jack(JackRunning),
+    #[cfg(feature = "nannou")]
+    Nannou(NannouRunning),
+    Null(NullRunning),
+    /// Offline backends have already finished when `start` returns.
+    Finished,
+}
+
+impl AnyBackend {
+    /// Open the backend. `name` is used as the JACK client name and
+    /// `port_names` as the names of the output ports, their number decides
+    /// the number of channels.
+    pub fn open(kind: BackendKind, name: &str, port_names: &[&str]) -> Result<Self, BackendError> {
+        let channels = port_names.len();
+        let backend = match kind {
+            #[cfg(feature = "jack")]
+            BackendKind::Jack(rules, transport) => AnyBackend::Jack(
+                JackBackend::new(name, port_names)?
+                    .connection_rules(rules)
+                    .transport(transport),
+            ),
+            #[cfg(feature = "nannou")]
+            BackendKind::Nannou => AnyBackend::Nannou(NannouBackend::new(channels)),
+            BackendKind::Null => AnyBackend::Null(NullBackend::new(
+                render::DEFAULT_SAMPLE_RATE,
+                render::DEFAULT_BUFFER_SIZE,
+                channels,
+            )),
+            BackendKind::Wav(settings) => AnyBackend::Wav(WavBackend::new(settings, channels)),
+        };
+        Ok(backend)
+    }
+}
+
+impl AudioBackend for AnyBackend {
+    type Running = AnyRunning;
+
+    fn sample_rate(&self) -> usize {
+        match self {
+            #[cfg(feature = "jack")]
+            AnyBackend::Jack(b) => b.sample_rate(),
+            #[cfg(feature = "nannou")]
+            AnyBackend::Nannou(b) => b.sample_rate(),
+            AnyBackend::Null(b) => b.sample_rate(),
+            AnyBackend::Wav(b) => b.sample_rate(),
+        }
+    }
+
+    fn buffer_size(&self) -> usize {
+        match self {
+            #[cfg(feature = "jack")]
+            AnyBackend::Jack(b) => b.buffer_size(),
+            #[cfg(feature = "nannou")]
+            AnyBackend::Nannou(b) => b.buffer_size(),
+            AnyBackend::Null(b) => b.buffer_size(),
+            AnyBackend::Wav(b) => b.buffer_size(),
+        }
+    }
+
+    fn channels(&self) -> usize {
+        match self {
+            #[cfg(feature = "jack")]
+            AnyBackend::Jack(b) => b.channels(),
+            #[cfg(feature = "nannou")]
+            AnyBackend::Nannou(b) => b.channels(),
+            AnyBackend::Null(b) => b.channels(),
+            AnyBackend::Wav(b) => b.channels(),
+        }
+    }
+
+    fn is_realtime(&self) -> bool {
+        match self {
+            AnyBackend::Wav(b) => b.is_realtime(),
+            _ => true,
+        }
+    }
+
+    fn start<P: AudioProcess>(self, process: P) -> Result<AnyRunning, BackendError> {
+        let running = match self {
+            #[cfg(feature = "jack")]
+            AnyBackend::Jack(b) => AnyRunning::Jack(b.start(process)?),
+            #[cfg(feature = "nannou")]
+            AnyBackend::Nannou(b) => AnyRunning::Nannou(b.start(process)?),
+            AnyBackend::Null(b) => AnyRunning::Null(b.start(process)?),
+            AnyBackend::Wav(b) => {
+                b.start(process)?;
+                AnyRunning::Finished
+            }
+        };
+        Ok(running)
+    }
+}
+
+/// Adapt a stereo callback taking separate left and right slices to
+/// `AudioProcess`. Interleaved buffers are deinterleaved through scratch
+/// buffers that are allocated up front for `max_frames`.
+pub fn stereo<F>(max_frames: usize, mut process: F) -> impl AudioProcess
+where
+    F: FnMut(&mut [f32], &mut [f32], &ProcessInfo) + Send + 'static,
+{
+    let mut scratch_l = vec![0.0_f32; max_frames];
+    let mut scratch_r = vec![0.0_f32; max_frames];
+    move |buffer: &mut AudioBuffer, info: &ProcessInfo| match buffer {
+        AudioBuffer::NonInterleaved(channels) => match &mut channels[..] {
+            [] => (),
+            [mono] => {
+                let frames = mono.len();
+                if scratch_r.len() < frames {
+                    scratch_r.resize(frames, 0.0);
+                }
+                process(mono, &mut scratch_r[..frames], info);
+            }
+            [l, r, ..] => process(l, r, info),
+        },
+        AudioBuffer::Interleaved { channels: 0, .. } => (),
+        AudioBuffer::Interleaved { samples, channels } => {
+            let channels = *channels;
+            let frames = samples.len() / channels;
+            if scratch_l.len() < frames {
+                // Only happens if the backend lied about its buffer size.
+                scratch_l.resize(frames, 0.0);
+                scratch_r.resize(frames, 0.0);
+            }
+            let (l, r) = (&mut scratch_l[..frames], &mut scratch_r[..frames]);
+            process(l, r, info);
+            for (i, frame) in samples.chunks_mut(channels).enumerate() {
+                for (ch, s) in frame.iter_mut().enumerate() {
+                    *s = if ch % 2 == 0 { l[i] } else { r[i] };
+                }
+            }
+        }
+    }
+}
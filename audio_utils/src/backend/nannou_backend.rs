@@ -0,0 +1,97 @@
+use nannou_audio as audio;
+
+use super::{AudioBackend, AudioBuffer, AudioProcess, BackendError, ProcessInfo};
+use crate::rt_audit;
+
+pub const DEFAULT_SAMPLE_RATE: usize = 44_100;
+pub const DEFAULT_FRAMES_PER_BUFFER: usize = 512;
+
+/// Runs the process callback on the default output device through
+/// `nannou_audio` (cpal), the same way the sketches play their sines.
+pub struct NannouBackend {
+    sample_rate: usize,
+    frames_per_buffer: usize,
+    channels: usize,
+}
+
+impl NannouBackend {
+    pub fn new(channels: usize) -> Self {
+        NannouBackend {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            frames_per_buffer: DEFAULT_FRAMES_PER_BUFFER,
+            channels,
+        }
+    }
+
+    /// Request a sample rate, the device may not support it.
+    pub fn sample_rate(mut self, sample_rate: usize) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn frames_per_buffer(mut self, frames_per_buffer: usize) -> Self {
+        self.frames_per_buffer = frames_per_buffer;
+        self
+    }
+}
+
+/// The state living on the audio thread.
+pub struct NannouModel {
+    process: Box<dyn AudioProcess>,
+    frames_processed: u64,
+}
+
+fn render(model: &mut NannouModel, buffer: &mut audio::Buffer) {
+    let _audit = rt_audit::callback(buffer.len_frames(), buffer.sample_rate() as usize);
+    let info = ProcessInfo {
+        sample_rate: buffer.sample_rate() as usize,
+        buffer_size: buffer.len_frames(),
+        frames_processed: model.frames_processed,
+        transport: None,
+    };
+    let channels = buffer.channels();
+    let mut buffer = AudioBuffer::Interleaved {
+        samples: &mut buffer[..],
+        channels,
+    };
+    model.process.process(&mut buffer, &info);
+    model.frames_processed += info.buffer_size as u64;
+}
+
+impl AudioBackend for NannouBackend {
+    type Running = NannouRunning;
+
+    fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    fn buffer_size(&self) -> usize {
+        self.frames_per_buffer
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn start<P: AudioProcess>(self, process: P) -> Result<NannouRunning, BackendError> {
+        let audio_host = audio::Host::new();
+        let model = NannouModel {
+            process: Box::new(process),
+            frames_processed: 0,
+        };
+        let stream = audio_host
+            .new_output_stream(model)
+            .render(render)
+            .channels(self.channels)
+            .sample_rate(self.sample_rate as u32)
+            .frames_per_buffer(self.frames_per_buffer)
+            .build()
+            .map_err(|e| BackendError::Stream(format!("{:?}", e)))?;
+        Ok(NannouRunning { _stream: stream })
+    }
+}
+
+/// The running output stream, stopped when dropped.
+pub struct NannouRunning {
+    _stream: audio::Stream<NannouModel>,
+}
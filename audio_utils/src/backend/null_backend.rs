@@ -0,0 +1,145 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{AudioBackend, AudioBuffer, AudioProcess, BackendError, ProcessInfo};
+use crate::rt_audit;
+
+/// A backend without an audio device. The output is thrown away.
+///
+/// `start` calls the process callback from a thread at the pace of a real
+/// device. For tests, `process_blocks` runs a number of blocks directly and
+/// lets you look at the output.
+pub struct NullBackend {
+    sample_rate: usize,
+    buffer_size: usize,
+    channels: usize,
+}
+
+impl NullBackend {
+    pub fn new(sample_rate: usize, buffer_size: usize, channels: usize) -> Self {
+        NullBackend {
+            sample_rate,
+            buffer_size,
+            channels,
+        }
+    }
+
+    /// Run `blocks` blocks of the process callback on the calling thread and
+    /// return the non-interleaved output, one `Vec` per channel.
+    pub fn process_blocks<P: AudioProcess>(&self, process: &mut P, blocks: usize) -> Vec<Vec<f32>> {
+        let mut output = vec![Vec::with_capacity(blocks * self.buffer_size); self.channels];
+        let mut buffers = vec![vec![0.0_f32; self.buffer_size]; self.channels];
+        for block in 0..blocks {
+            let info = ProcessInfo {
+                sample_rate: self.sample_rate,
+                buffer_size: self.buffer_size,
+                frames_processed: (block * self.buffer_size) as u64,
+                transport: None,
+            };
+            {
+                let mut slices: Vec<&mut [f32]> = buffers.iter_mut().map(|b| &mut b[..]).collect();
+                process.process(&mut AudioBuffer::NonInterleaved(&mut slices), &info);
+            }
+            for (out, buffer) in output.iter_mut().zip(buffers.iter()) {
+                out.extend_from_slice(buffer);
+            }
+        }
+        output
+    }
+}
+
+impl AudioBackend for NullBackend {
+    type Running = NullRunning;
+
+    fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn start<P: AudioProcess>(self, mut process: P) -> Result<NullRunning, BackendError> {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = Arc::clone(&running);
+        let period = Duration::from_secs_f64(self.buffer_size as f64 / self.sample_rate as f64);
+        let thread = thread::spawn(move || {
+            let mut buffers = vec![vec![0.0_f32; self.buffer_size]; self.channels];
+            let mut frames_processed = 0;
+            let mut next_block = Instant::now();
+            while thread_running.load(Ordering::Relaxed) {
+                let info = ProcessInfo {
+                    sample_rate: self.sample_rate,
+                    buffer_size: self.buffer_size,
+                    frames_processed,
+                    transport: None,
+                };
+                {
+                    let mut slices: Vec<&mut [f32]> =
+                        buffers.iter_mut().map(|b| &mut b[..]).collect();
+                    let _audit = rt_audit::callback(self.buffer_size, self.sample_rate);
+                    process.process(&mut AudioBuffer::NonInterleaved(&mut slices), &info);
+                }
+                frames_processed += self.buffer_size as u64;
+                next_block += period;
+                let now = Instant::now();
+                if next_block > now {
+                    thread::sleep(next_block - now);
+                } else {
+                    // A real device would have run out of audio.
+                    rt_audit::xrun();
+                }
+            }
+        });
+        Ok(NullRunning {
+            running,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// The thread calling the process callback, stopped and joined when dropped.
+pub struct NullRunning {
+    running: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for NullRunning {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend;
+
+    #[test]
+    fn process_blocks_returns_every_frame() {
+        let backend = NullBackend::new(48000, 64, 2);
+        let mut calls = 0;
+        let mut process = backend::stereo(backend.buffer_size(), move |l, r, info| {
+            assert_eq!(info.frames_processed, (calls * 64) as u64);
+            calls += 1;
+            for (l, r) in l.iter_mut().zip(r.iter_mut()) {
+                *l = 0.5;
+                *r = -0.5;
+            }
+        });
+        let output = backend.process_blocks(&mut process, 10);
+        assert_eq!(output.len(), 2);
+        assert!(output.iter().all(|channel| channel.len() == 640));
+        assert!(output[0].iter().all(|&s| s == 0.5));
+        assert!(output[1].iter().all(|&s| s == -0.5));
+    }
+}
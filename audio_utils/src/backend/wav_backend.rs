@@ -0,0 +1,77 @@
+use super::{AudioBackend, AudioBuffer, AudioProcess, BackendError, ProcessInfo};
+use crate::rt_audit;
+use crate::render::RenderSettings;
+
+/// Renders the process callback offline to a 32 bit float WAV file.
+/// `start` returns when `settings.duration` has been rendered.
+pub struct WavBackend {
+    settings: RenderSettings,
+    channels: usize,
+}
+
+impl WavBackend {
+    pub fn new(settings: RenderSettings, channels: usize) -> Self {
+        WavBackend { settings, channels }
+    }
+}
+
+impl AudioBackend for WavBackend {
+    type Running = ();
+
+    fn sample_rate(&self) -> usize {
+        self.settings.sample_rate
+    }
+
+    fn buffer_size(&self) -> usize {
+        self.settings.buffer_size
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn is_realtime(&self) -> bool {
+        false
+    }
+
+    fn start<P: AudioProcess>(self, mut process: P) -> Result<(), BackendError> {
+        let settings = &self.settings;
+        let spec = hound::WavSpec {
+            channels: self.channels as u16,
+            sample_rate: settings.sample_rate as u32,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&settings.path, spec)?;
+
+        let mut buffers = vec![vec![0.0_f32; settings.buffer_size]; self.channels];
+        let total_frames = settings.total_frames();
+        let mut frames_rendered = 0;
+        while frames_rendered < total_frames {
+            // The last block may be shorter than the buffer size.
+            let block_size = settings.buffer_size.min(total_frames - frames_rendered);
+            let info = ProcessInfo {
+                sample_rate: settings.sample_rate,
+                buffer_size: block_size,
+                frames_processed: frames_rendered as u64,
+                transport: None,
+            };
+            {
+                let mut slices: Vec<&mut [f32]> =
+                    buffers.iter_mut().map(|b| &mut b[..block_size]).collect();
+                // Rendering is offline, but the callback should still be
+                // real-time safe. Overruns don't matter here.
+                let _audit = rt_audit::callback(block_size, settings.sample_rate);
+                process.process(&mut AudioBuffer::NonInterleaved(&mut slices), &info);
+            }
+            for i in 0..block_size {
+                for buffer in &buffers {
+                    writer.write_sample(buffer[i])?;
+                }
+            }
+            frames_rendered += block_size;
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+}
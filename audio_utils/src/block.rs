@@ -0,0 +1,158 @@
+//! Bridging per-channel host buffers and fixed size interleaved blocks.
+//!
+//! Hosts like JACK hand out one slice per channel with a buffer size that is
+//! set by the server, while a `dsp::Graph` renders interleaved frames
+//! (`[[l0, r0], [l1, r1], ..]`) and is simplest to run with a fixed block size.
+//! The `BlockAdapter` collects input frames into a block, renders it once it is
+//! full and plays the result back during the next block. This works for any
+//! host buffer size, also ones that aren't a multiple of the block size, at a
+//! constant latency of exactly one block.
+
+/// Interleaves inputs into fixed size blocks and deinterleaves the rendered
+/// blocks into the outputs.
+pub struct BlockAdapter {
+    input_channels: usize,
+    output_channels: usize,
+    block_size: usize,
+    input: Vec<f32>,
+    output: Vec<f32>,
+    /// The current frame in both blocks.
+    index: usize,
+}
+
+impl BlockAdapter {
+    /// The buffers are allocated here, `process` doesn't allocate.
+    pub fn new(input_channels: usize, output_channels: usize, block_size: usize) -> Self {
+        assert!(block_size > 0, "the block size has to be at least 1");
+        BlockAdapter {
+            input_channels,
+            output_channels,
+            block_size,
+            input: vec![0.0; input_channels * block_size],
+            output: vec![0.0; output_channels * block_size],
+            index: 0,
+        }
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// The delay from the inputs (and from `render`) to the outputs, in frames.
+    pub fn latency(&self) -> usize {
+        self.block_size
+    }
+
+    /// Clear the buffers, e.g. after the host was stopped.
+    pub fn reset(&mut self) {
+        for s in self.input.iter_mut().chain(self.output.iter_mut()) {
+            *s = 0.0;
+        }
+        self.index = 0;
+    }
+
+    /// Copy `inputs` into the current block and fill `outputs` from the
+    /// previously rendered block. Every time a block is full `render` is
+    /// called with the interleaved input block and a zeroed interleaved output
+    /// block to write to. All channels have to be the same length, missing
+    /// input channels read as silence.
+    pub fn process<F>(&mut self, inputs: &[&[f32]], outputs: &mut [&mut [f32]], mut render: F)
+    where
+        F: FnMut(&[f32], &mut [f32]),
+    {
+        let frames = match (outputs.first(), inputs.first()) {
+            (Some(out), _) => out.len(),
+            (None, Some(input)) => input.len(),
+            (None, None) => return,
+        };
+        for i in 0..frames {
+            let frame = self.index;
+            for ch in 0..self.input_channels {
+                self.input[frame * self.input_channels + ch] =
+                    inputs.get(ch).map_or(0.0, |input| input[i]);
+            }
+            for (ch, output) in outputs.iter_mut().enumerate() {
+                output[i] = if ch < self.output_channels {
+                    self.output[frame * self.output_channels + ch]
+                } else {
+                    0.0
+                };
+            }
+            self.index += 1;
+            if self.index == self.block_size {
+                for s in self.output.iter_mut() {
+                    *s = 0.0;
+                }
+                render(&self.input, &mut self.output);
+                self.index = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `frames` frames of a ramp on every channel through an adapter that
+    /// copies its input to its output, in host buffers of `buffer_size`.
+    fn pass_through(channels: usize, block_size: usize, buffer_size: usize, frames: usize) -> Vec<Vec<f32>> {
+        let signal = |ch: usize, frame: usize| (frame * 8 + ch + 1) as f32;
+        let mut adapter = BlockAdapter::new(channels, channels, block_size);
+        let mut outputs = vec![Vec::with_capacity(frames); channels];
+        let mut input = vec![vec![0.0; buffer_size]; channels];
+        let mut output = vec![vec![0.0; buffer_size]; channels];
+        let mut start = 0;
+        while start < frames {
+            let len = buffer_size.min(frames - start);
+            for (ch, input) in input.iter_mut().enumerate() {
+                for (i, s) in input[..len].iter_mut().enumerate() {
+                    *s = signal(ch, start + i);
+                }
+            }
+            let inputs: Vec<&[f32]> = input.iter().map(|b| &b[..len]).collect();
+            let mut outs: Vec<&mut [f32]> = output.iter_mut().map(|b| &mut b[..len]).collect();
+            adapter.process(&inputs, &mut outs, |input, output| {
+                assert_eq!(input.len(), channels * block_size);
+                output.copy_from_slice(input);
+            });
+            for (all, out) in outputs.iter_mut().zip(outs.iter()) {
+                all.extend_from_slice(out);
+            }
+            start += len;
+        }
+        for (ch, output) in outputs.iter().enumerate() {
+            for (frame, &s) in output.iter().enumerate() {
+                let expected = if frame < adapter.latency() {
+                    0.0
+                } else {
+                    signal(ch, frame - adapter.latency())
+                };
+                assert_eq!(
+                    s, expected,
+                    "{} channels, buffer size {}: channel {} frame {}",
+                    channels, buffer_size, ch, frame
+                );
+            }
+        }
+        outputs
+    }
+
+    #[test]
+    fn output_is_delayed_by_one_block() {
+        for &channels in &[1, 2, 5] {
+            for &buffer_size in &[1, 7, 16, 23, 64, 100] {
+                let outputs = pass_through(channels, 16, buffer_size, 1000);
+                assert!(outputs.iter().all(|o| o.len() == 1000));
+            }
+        }
+    }
+
+    #[test]
+    fn latency_is_the_block_size() {
+        for &block_size in &[1, 16, 64] {
+            assert_eq!(BlockAdapter::new(2, 2, block_size).latency(), block_size);
+            pass_through(2, block_size, 48, 500);
+        }
+    }
+}
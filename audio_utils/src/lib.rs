@@ -0,0 +1,10 @@
+//! Shared audio helpers for the synths in this workspace.
+
+pub mod args;
+pub mod backend;
+pub mod block;
+pub mod oversample;
+pub mod param;
+pub mod render;
+pub mod rt_audit;
+pub mod transport;
@@ -0,0 +1,135 @@
+//! Decimation for synths running at a multiple of the output sample rate.
+//!
+//! Running e.g. FM at 2x or 4x the device rate moves the sidebands that would
+//! fold back into the audible range above the output Nyquist frequency, where
+//! the decimation filter removes them before the sample rate is reduced.
+use std::f64::consts::PI;
+
+/// The oversampling factors that are supported.
+pub const FACTORS: [usize; 3] = [1, 2, 4];
+pub const MAX_FACTOR: usize = 4;
+
+/// A windowed-sinc lowpass FIR filter followed by downsampling by `factor`.
+pub struct Decimator {
+    factor: usize,
+    taps: Vec<f64>,
+    history: Vec<f64>,
+    pos: usize,
+}
+
+impl Decimator {
+    /// Create a decimator for `factor` input samples per output sample. The
+    /// cutoff is at 45% of the output rate so that the transition band ends
+    /// at the output Nyquist frequency. From there on the Blackman window
+    /// attenuates by more than 70 dB, the passband reaches about 40% of the
+    /// output rate, i.e. 19 kHz at 48 kHz.
+    pub fn new(factor: usize) -> Self {
+        assert!(factor >= 1, "the oversampling factor has to be at least 1");
+        let taps = if factor == 1 {
+            vec![1.0]
+        } else {
+            lowpass_taps(64 * factor - 1, 0.45 / factor as f64)
+        };
+        Decimator {
+            factor,
+            history: vec![0.0; taps.len()],
+            taps,
+            pos: 0,
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+
+    /// The delay of the filter in output samples.
+    pub fn latency(&self) -> f64 {
+        (self.taps.len() - 1) as f64 / 2.0 / self.factor as f64
+    }
+
+    /// Feed `factor` samples at the oversampled rate and get one sample at
+    /// the output rate.
+    pub fn process(&mut self, input: &[f64]) -> f64 {
+        debug_assert_eq!(input.len(), self.factor);
+        let len = self.history.len();
+        for s in input {
+            self.pos = (self.pos + 1) % len;
+            self.history[self.pos] = *s;
+        }
+        // Only the samples that are kept after downsampling are filtered.
+        let mut out = 0.0;
+        for (k, tap) in self.taps.iter().enumerate() {
+            out += tap * self.history[(self.pos + len - k) % len];
+        }
+        out
+    }
+
+    pub fn reset(&mut self) {
+        for s in self.history.iter_mut() {
+            *s = 0.0;
+        }
+    }
+}
+
+/// Blackman windowed sinc lowpass with `num_taps` taps and a cutoff at
+/// `cutoff` times the sample rate, normalised to unity gain at DC.
+pub fn lowpass_taps(num_taps: usize, cutoff: f64) -> Vec<f64> {
+    let m = (num_taps - 1) as f64;
+    let mut taps: Vec<f64> = (0..num_taps)
+        .map(|n| {
+            let n = n as f64;
+            let x = n - m / 2.0;
+            let sinc = if x == 0.0 {
+                2.0 * cutoff
+            } else {
+                (2.0 * PI * cutoff * x).sin() / (PI * x)
+            };
+            let window =
+                0.42 - 0.5 * (2.0 * PI * n / m).cos() + 0.08 * (4.0 * PI * n / m).cos();
+            sinc * window
+        })
+        .collect();
+    let sum: f64 = taps.iter().sum();
+    for tap in taps.iter_mut() {
+        *tap /= sum;
+    }
+    taps
+}
+
+/// Parse an oversampling factor from the command line.
+pub fn parse_factor(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(factor) if FACTORS.contains(&factor) => Ok(factor),
+        _ => Err(format!("oversampling has to be one of {:?}, got \"{}\"", FACTORS, s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The gain in dB of `taps` at `freq` times the sample rate.
+    fn gain_db(taps: &[f64], freq: f64) -> f64 {
+        let (mut re, mut im) = (0.0, 0.0);
+        for (n, tap) in taps.iter().enumerate() {
+            let w = 2.0 * PI * freq * n as f64;
+            re += tap * w.cos();
+            im -= tap * w.sin();
+        }
+        20.0 * (re * re + im * im).sqrt().log10()
+    }
+
+    #[test]
+    fn decimator_stops_above_output_nyquist() {
+        for &factor in &FACTORS[1..] {
+            let decimator = Decimator::new(factor);
+            let nyquist = 0.5 / factor as f64;
+            for i in 0..100 {
+                let freq = nyquist + (0.5 - nyquist) * i as f64 / 100.0;
+                let gain = gain_db(&decimator.taps, freq);
+                assert!(gain < -70.0, "{}x: {} dB at {}", factor, gain, freq);
+            }
+            assert!(gain_db(&decimator.taps, 0.4 / factor as f64).abs() < 0.1);
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Parameters that can be shared between the main thread and the audio thread.
+//!
+//! A `Param` is a handle to an atomic f64, so it is `Send + Sync`, can be cloned
+//! into any number of signal closures and set from anywhere without locking.
+//! `Smoothed` glides towards new values so that changing a parameter doesn't
+//! click, every synth parameter that can change while sounding goes through one.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The smoothing time used for synth parameters unless there is a reason for
+/// another one.
+pub const DEFAULT_SMOOTHING_MS: f64 = 5.0;
+
+#[derive(Clone, Debug)]
+pub struct Param {
+    value: Arc<AtomicU64>,
+}
+
+impl Param {
+    pub fn new(value: f64) -> Self {
+        Param {
+            value: Arc::new(AtomicU64::new(value.to_bits())),
+        }
+    }
+
+    pub fn set(&self, value: f64) {
+        self.value.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.value.load(Ordering::Relaxed))
+    }
+
+    /// A reader of this parameter for the audio thread that glides towards
+    /// new values with a one-pole lowpass reaching about 63% of a change
+    /// after `time_ms`. Every reader smooths on its own.
+    pub fn smoothed(&self, sample_rate: f64, time_ms: f64) -> SmoothedParam {
+        self.smoothed_with(sample_rate, time_ms, Smoothing::OnePole)
+    }
+
+    /// Like `smoothed` with the given kind of smoothing.
+    pub fn smoothed_with(&self, sample_rate: f64, time_ms: f64, smoothing: Smoothing) -> SmoothedParam {
+        SmoothedParam {
+            param: self.clone(),
+            smoothed: Smoothed::new(self.get(), sample_rate, time_ms, smoothing),
+        }
+    }
+}
+
+/// Created by `Param::smoothed`, call `next` once per sample.
+#[derive(Clone, Debug)]
+pub struct SmoothedParam {
+    param: Param,
+    smoothed: Smoothed,
+}
+
+impl SmoothedParam {
+    pub fn next(&mut self) -> f64 {
+        let target = self.param.get();
+        if target != self.smoothed.target() {
+            self.smoothed.set(target);
+        }
+        self.smoothed.next()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Smoothing {
+    /// Reach the new value in a straight line after exactly the smoothing
+    /// time. Good for amplitudes and for changes that have to be finished in
+    /// a known time.
+    Linear,
+    /// Exponential approach with a one-pole lowpass, reaching about 63% of a
+    /// change after the smoothing time. Good for frequencies and ratios.
+    OnePole,
+}
+
+/// A value that moves towards its target over a time in milliseconds. Call
+/// `next` once per sample at the sample rate it was created with.
+#[derive(Clone, Copy, Debug)]
+pub struct Smoothed {
+    current: f64,
+    target: f64,
+    smoothing: Smoothing,
+    time_ms: f64,
+    sample_rate: f64,
+    /// The one-pole coefficient.
+    coeff: f64,
+    /// The linear ramp increment and the number of samples left.
+    step: f64,
+    remaining: u32,
+}
+
+impl Smoothed {
+    pub fn new(value: f64, sample_rate: f64, time_ms: f64, smoothing: Smoothing) -> Self {
+        let mut smoothed = Smoothed {
+            current: value,
+            target: value,
+            smoothing,
+            time_ms,
+            sample_rate,
+            coeff: 0.0,
+            step: 0.0,
+            remaining: 0,
+        };
+        smoothed.update_coeff();
+        smoothed
+    }
+
+    fn time_samples(&self) -> f64 {
+        self.time_ms * 0.001 * self.sample_rate
+    }
+
+    fn update_coeff(&mut self) {
+        let samples = self.time_samples();
+        self.coeff = if samples > 0.0 { (-1.0 / samples).exp() } else { 0.0 };
+    }
+
+    /// Change the sample rate `next` is called at, a change in progress
+    /// keeps going at the new rate and ends at the same time.
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        if sample_rate != self.sample_rate {
+            if self.remaining > 0 {
+                let samples = (self.remaining as f64 * sample_rate / self.sample_rate).round().max(1.0);
+                self.remaining = samples as u32;
+                self.step = (self.target - self.current) / samples;
+            }
+            self.sample_rate = sample_rate;
+            self.update_coeff();
+        }
+    }
+
+    pub fn set_time_ms(&mut self, time_ms: f64) {
+        self.time_ms = time_ms;
+        self.update_coeff();
+    }
+
+    /// Start moving towards `target`.
+    pub fn set(&mut self, target: f64) {
+        self.target = target;
+        if let Smoothing::Linear = self.smoothing {
+            let samples = self.time_samples().round().max(1.0);
+            self.remaining = samples as u32;
+            self.step = (target - self.current) / samples;
+        }
+    }
+
+    /// Jump to `value` without smoothing.
+    pub fn reset(&mut self, value: f64) {
+        self.current = value;
+        self.target = value;
+        self.remaining = 0;
+    }
+
+    pub fn next(&mut self) -> f64 {
+        match self.smoothing {
+            Smoothing::Linear => {
+                if self.remaining > 0 {
+                    self.remaining -= 1;
+                    self.current = if self.remaining == 0 {
+                        self.target
+                    } else {
+                        self.current + self.step
+                    };
+                }
+            }
+            Smoothing::OnePole => {
+                self.current = self.target + (self.current - self.target) * self.coeff;
+            }
+        }
+        self.current
+    }
+
+    /// The current value, without advancing.
+    pub fn value(&self) -> f64 {
+        self.current
+    }
+
+    pub fn target(&self) -> f64 {
+        self.target
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 48000.0;
+
+    /// The largest change between two samples while moving to `target`, and
+    /// the number of samples until it was reached.
+    fn max_step(smoothed: &mut Smoothed, target: f64) -> (f64, usize) {
+        let mut previous = smoothed.value();
+        let mut max_step: f64 = 0.0;
+        for i in 0..(SAMPLE_RATE as usize) {
+            let value = smoothed.next();
+            max_step = max_step.max((value - previous).abs());
+            previous = value;
+            if (value - target).abs() < 1e-9 {
+                return (max_step, i + 1);
+            }
+        }
+        panic!("{:?} didn't reach {}", smoothed, target);
+    }
+
+    #[test]
+    fn linear_jump_is_spread_over_the_smoothing_time() {
+        let mut smoothed = Smoothed::new(0.0, SAMPLE_RATE, 5.0, Smoothing::Linear);
+        smoothed.set(1.0);
+        let (step, samples) = max_step(&mut smoothed, 1.0);
+        assert_eq!(samples, 240);
+        assert!(step <= 1.0 / 240.0 + 1e-12, "step {}", step);
+        assert_eq!(smoothed.next(), 1.0);
+    }
+
+    #[test]
+    fn one_pole_jump_is_smoothed() {
+        let mut smoothed = Smoothed::new(100.0, SAMPLE_RATE, DEFAULT_SMOOTHING_MS, Smoothing::OnePole);
+        smoothed.set(1100.0);
+        let (step, _) = max_step(&mut smoothed, 1100.0);
+        // The first step is the largest, 1 - e^(-1/n) of the jump.
+        assert!(step <= 1000.0 / 240.0, "step {}", step);
+        assert!(step > 1000.0 / 241.0, "step {}", step);
+    }
+
+    #[test]
+    fn steps_stay_bounded_when_the_sample_rate_changes() {
+        let mut smoothed = Smoothed::new(0.0, SAMPLE_RATE, 5.0, Smoothing::Linear);
+        smoothed.set(1.0);
+        for _ in 0..120 {
+            smoothed.next();
+        }
+        // Oversampling by 4 while halfway, the other half takes as long.
+        smoothed.set_sample_rate(SAMPLE_RATE * 4.0);
+        let (step, samples) = max_step(&mut smoothed, 1.0);
+        assert_eq!(samples, 480);
+        assert!(step <= 0.5 / 480.0 + 1e-12, "step {}", step);
+    }
+
+    #[test]
+    fn smoothed_param_follows_the_param() {
+        let param = Param::new(0.0);
+        let mut smoothed = param.smoothed_with(SAMPLE_RATE, 5.0, Smoothing::Linear);
+        param.set(2.0);
+        let mut previous = 0.0;
+        for _ in 0..240 {
+            let value = smoothed.next();
+            assert!(value - previous <= 2.0 / 240.0 + 1e-12);
+            previous = value;
+        }
+        assert_eq!(previous, 2.0);
+    }
+}
@@ -0,0 +1,196 @@
+//! Settings for rendering a process callback offline to a WAV file.
+//!
+//! The synths normally need a running JACK server before they make any sound.
+//! With `--render out.wav --duration 10s` the same process callback is instead
+//! called on in-memory buffers and the result is written to disk (see
+//! `backend::WavBackend`), which is useful for auditioning patches, regression
+//! tests and video renders.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::args;
+
+pub const DEFAULT_SAMPLE_RATE: usize = 48_000;
+pub const DEFAULT_BUFFER_SIZE: usize = 256;
+pub const DEFAULT_DURATION: f64 = 10.0;
+
+pub const USAGE: &str = "    --render <file.wav>    render offline to a WAV file instead of running on JACK
+    --duration <time>      length of the render, e.g. 10s, 500ms, 2m (default 10s)
+    --sample-rate <hz>     sample rate of the render (default 48000)
+    --buffer-size <frames> frames per process call (default 256)
+    --events <file>        lines of `<time> <message>` fed to the synth while rendering";
+
+/// Settings for an offline render, usually parsed from the command line.
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    pub path: PathBuf,
+    /// Length of the render in seconds.
+    pub duration: f64,
+    pub sample_rate: usize,
+    pub buffer_size: usize,
+    /// Optional file with timed parameter messages.
+    pub events: Option<PathBuf>,
+}
+
+impl RenderSettings {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        RenderSettings {
+            path: path.into(),
+            duration: DEFAULT_DURATION,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            events: None,
+        }
+    }
+
+    /// Take the render options out of the program arguments. Returns
+    /// `Ok(None)` if `--render` wasn't given, in which case the program should
+    /// run in real time as usual. The other render options are an error
+    /// without `--render`, so a mistyped render doesn't start a live session.
+    pub fn from_args(args: &mut Vec<String>) -> Result<Option<Self>, String> {
+        let path = args::take_value(args, "--render")?;
+        let mut settings = RenderSettings::new(path.clone().unwrap_or_default());
+        let mut given = vec![];
+        if let Some(duration) = args::take_parsed(args, "--duration", parse_duration)? {
+            settings.duration = duration;
+            given.push("--duration");
+        }
+        if let Some(sample_rate) = args::take_parsed(args, "--sample-rate", parse_positive)? {
+            settings.sample_rate = sample_rate;
+            given.push("--sample-rate");
+        }
+        if let Some(buffer_size) = args::take_parsed(args, "--buffer-size", parse_positive)? {
+            settings.buffer_size = buffer_size;
+            given.push("--buffer-size");
+        }
+        settings.events = args::take_value(args, "--events")?.map(PathBuf::from);
+        if settings.events.is_some() {
+            given.push("--events");
+        }
+        match (path, given.first()) {
+            (None, Some(name)) => Err(format!("{} requires --render", name)),
+            (None, None) => Ok(None),
+            (Some(_), _) => Ok(Some(settings)),
+        }
+    }
+
+    /// The total number of frames that will be rendered.
+    pub fn total_frames(&self) -> usize {
+        (self.duration * self.sample_rate as f64).round() as usize
+    }
+}
+
+fn parse_positive(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(v) if v > 0 => Ok(v),
+        _ => Err(format!("expected a positive integer, got \"{}\"", value)),
+    }
+}
+
+/// Parse a duration like `10s`, `500ms`, `2m` or a plain number of seconds.
+pub fn parse_duration(s: &str) -> Result<f64, String> {
+    let s = s.trim();
+    let (number, scale) = if let Some(n) = s.strip_suffix("ms") {
+        (n, 0.001)
+    } else if let Some(n) = s.strip_suffix('s') {
+        (n, 1.0)
+    } else if let Some(n) = s.strip_suffix('m') {
+        (n, 60.0)
+    } else {
+        (s, 1.0)
+    };
+    match number.trim().parse::<f64>() {
+        Ok(v) if v >= 0.0 && v.is_finite() => Ok(v * scale),
+        _ => Err(format!("Invalid duration \"{}\"", s)),
+    }
+}
+
+/// Read a file of timed messages, one per line in the form `<time> <message>`.
+/// The time accepts the same formats as `--duration`, the rest of the line is
+/// passed to `parse`. Empty lines and lines starting with `#` are skipped.
+/// The result is sorted by time.
+pub fn read_events<T, F>(path: &Path, parse: F) -> Result<Vec<(f64, T)>, String>
+where
+    F: Fn(&str) -> Option<T>,
+{
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read events file {:?}: {}", path, e))?;
+    let mut events = vec![];
+    for (line_nr, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(2, char::is_whitespace);
+        let time = parse_duration(parts.next().unwrap_or(""))
+            .map_err(|e| format!("{:?}:{}: {}", path, line_nr + 1, e))?;
+        let message = parse(parts.next().unwrap_or("").trim())
+            .ok_or_else(|| format!("{:?}:{}: invalid message", path, line_nr + 1))?;
+        events.push((time, message));
+    }
+    events.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    Ok(events)
+}
+
+/// Timed messages, e.g. from `read_events`, handed out as time passes.
+pub struct EventQueue<T> {
+    events: std::iter::Peekable<std::vec::IntoIter<(f64, T)>>,
+}
+
+impl<T> EventQueue<T> {
+    /// `events` have to be sorted by time.
+    pub fn new(events: Vec<(f64, T)>) -> Self {
+        EventQueue {
+            events: events.into_iter().peekable(),
+        }
+    }
+
+    /// Load the events file from the settings, if any.
+    pub fn load<F>(settings: &RenderSettings, parse: F) -> Result<Self, String>
+    where
+        F: Fn(&str) -> Option<T>,
+    {
+        let events = match &settings.events {
+            Some(path) => read_events(path, parse)?,
+            None => vec![],
+        };
+        Ok(EventQueue::new(events))
+    }
+
+    /// Return the next message that is due at `time`, call it until it
+    /// returns `None` at the start of every block.
+    pub fn pop_due(&mut self, time: f64) -> Option<T> {
+        if self.events.peek().map_or(false, |(t, _)| *t <= time) {
+            self.events.next().map(|(_, message)| message)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn render_options_require_render() {
+        let mut live = args("--connect auto");
+        assert!(RenderSettings::from_args(&mut live).unwrap().is_none());
+        assert_eq!(live, args("--connect auto"));
+
+        let mut mistyped = args("--rendr out.wav --duration 5s");
+        let error = RenderSettings::from_args(&mut mistyped).unwrap_err();
+        assert_eq!(error, "--duration requires --render");
+
+        let mut render = args("--render out.wav --duration 5s --sample-rate 44100");
+        let settings = RenderSettings::from_args(&mut render).unwrap().unwrap();
+        assert!(render.is_empty());
+        assert_eq!(settings.path, PathBuf::from("out.wav"));
+        assert_eq!(settings.duration, 5.0);
+        assert_eq!(settings.sample_rate, 44100);
+    }
+}
@@ -0,0 +1,268 @@
+//! Auditing the process callbacks for real-time safety, a debug aid enabled
+//! with the `rt_audit` feature of `audio_utils` (the synths and sketches have
+//! an `rt_audit` feature that turns it on).
+//!
+//! A process callback must not allocate, deallocate, lock or take longer than
+//! the buffer period. The synths only talk to their callbacks through
+//! `try_recv` and `try_send`, so there are no locks to count. With the
+//! feature on:
+//!
+//! - `CountingAllocator` counts every allocation and deallocation made by a
+//!   thread that is inside a callback. A library can't choose the allocator
+//!   for the programs linking it, so every binary that wants allocations
+//!   counted declares it itself:
+//!
+//!   ```ignore
+//!   #[cfg(feature = "rt_audit")]
+//!   #[global_allocator]
+//!   static ALLOCATOR: rt_audit::CountingAllocator = rt_audit::CountingAllocator;
+//!   ```
+//! - Every backend wraps its calls to the process callback in `callback`, the
+//!   sketches do the same in their render functions. The guard marks the
+//!   thread as being in a callback and measures how long the callback took
+//!   compared to the buffer period. Other code running on the audio thread
+//!   is marked with `enter`.
+//!
+//! The audio thread only bumps atomic counters. A `Monitor` thread logs new
+//! violations once a second and prints the timing statistics and the number
+//! of xruns when it is dropped, usually at the end of `main`.
+//!
+//! Without the feature `callback` and `Monitor` do nothing.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ENABLED: bool = cfg!(feature = "rt_audit");
+
+/// How often the monitor looks for new violations.
+const MONITOR_INTERVAL: Duration = Duration::from_secs(1);
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static DEALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+/// Callbacks that allocated or deallocated at least once.
+static BAD_CALLBACKS: AtomicU64 = AtomicU64::new(0);
+
+static CALLBACKS: AtomicU64 = AtomicU64::new(0);
+static TOTAL_NANOS: AtomicU64 = AtomicU64::new(0);
+static MAX_NANOS: AtomicU64 = AtomicU64::new(0);
+static TOTAL_PERIOD_NANOS: AtomicU64 = AtomicU64::new(0);
+/// Callbacks that took longer than their buffer period.
+static OVERRUNS: AtomicU64 = AtomicU64::new(0);
+static XRUNS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static IN_CALLBACK: Cell<bool> = Cell::new(false);
+}
+
+fn in_callback() -> bool {
+    // `try_with` because the allocator is also called while the thread
+    // local is being torn down.
+    IN_CALLBACK.try_with(|c| c.get()).unwrap_or(false)
+}
+
+fn count_violation(counter: &AtomicU64) {
+    if in_callback() {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn violations() -> u64 {
+    ALLOCATIONS.load(Ordering::Relaxed) + DEALLOCATIONS.load(Ordering::Relaxed)
+}
+
+/// The system allocator, counting what is done from inside a callback.
+/// Declare it as the `#[global_allocator]` of a binary to use it.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_violation(&ALLOCATIONS);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count_violation(&ALLOCATIONS);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_violation(&ALLOCATIONS);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        count_violation(&DEALLOCATIONS);
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Marks the current thread as running a process callback until it is
+/// dropped, created by `callback` or `enter`.
+pub struct CallbackGuard {
+    active: bool,
+    was_in_callback: bool,
+    start: Option<Instant>,
+    period_nanos: u64,
+    violations: u64,
+}
+
+/// Call at the start of a process callback rendering `frames` frames at
+/// `sample_rate` and keep the guard until the callback returns.
+pub fn callback(frames: usize, sample_rate: usize) -> CallbackGuard {
+    let mut guard = enter();
+    if guard.active {
+        guard.period_nanos = frames as u64 * 1_000_000_000 / sample_rate.max(1) as u64;
+        guard.start = Some(Instant::now());
+    }
+    guard
+}
+
+/// Audit code running on the audio thread outside of the process callback,
+/// e.g. the closures sent to a `nannou_audio` stream. It isn't timed.
+pub fn enter() -> CallbackGuard {
+    if !ENABLED {
+        return CallbackGuard {
+            active: false,
+            was_in_callback: false,
+            start: None,
+            period_nanos: 0,
+            violations: 0,
+        };
+    }
+    CallbackGuard {
+        active: true,
+        was_in_callback: IN_CALLBACK.with(|c| c.replace(true)),
+        start: None,
+        period_nanos: 0,
+        violations: violations(),
+    }
+}
+
+impl Drop for CallbackGuard {
+    fn drop(&mut self) {
+        if !self.active {
+            return;
+        }
+        let nanos = self.start.map(|start| start.elapsed().as_nanos() as u64);
+        IN_CALLBACK.with(|c| c.set(self.was_in_callback));
+        // Nested guards leave the counting to the outermost one.
+        if !self.was_in_callback && violations() != self.violations {
+            BAD_CALLBACKS.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(nanos) = nanos {
+            CALLBACKS.fetch_add(1, Ordering::Relaxed);
+            TOTAL_NANOS.fetch_add(nanos, Ordering::Relaxed);
+            MAX_NANOS.fetch_max(nanos, Ordering::Relaxed);
+            TOTAL_PERIOD_NANOS.fetch_add(self.period_nanos, Ordering::Relaxed);
+            if nanos > self.period_nanos {
+                OVERRUNS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Count an xrun reported by the backend.
+pub fn xrun() {
+    XRUNS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Logs violations from its own thread and prints a report when dropped.
+pub struct Monitor {
+    running: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Monitor {
+    /// Start the monitor thread, does nothing without the `rt_audit` feature.
+    pub fn start() -> Self {
+        if !ENABLED {
+            return Monitor {
+                running: Arc::new(AtomicBool::new(false)),
+                thread: None,
+            };
+        }
+        println!("rt_audit: auditing the process callbacks");
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = Arc::clone(&running);
+        let thread = thread::spawn(move || {
+            let mut reported = Counts::load();
+            while thread_running.load(Ordering::Relaxed) {
+                thread::sleep(MONITOR_INTERVAL);
+                let counts = Counts::load();
+                if counts.callbacks != reported.callbacks {
+                    println!(
+                        "rt_audit: {} allocations and {} deallocations in {} callbacks",
+                        counts.allocations - reported.allocations,
+                        counts.deallocations - reported.deallocations,
+                        counts.callbacks - reported.callbacks,
+                    );
+                }
+                reported = counts;
+            }
+        });
+        Monitor {
+            running,
+            thread: Some(thread),
+        }
+    }
+
+    /// Print the totals and the timing statistics.
+    pub fn report(&self) {
+        let callbacks = CALLBACKS.load(Ordering::Relaxed);
+        let counts = Counts::load();
+        println!("rt_audit report:");
+        println!(
+            "    {} of {} callbacks allocated or deallocated ({} allocations, {} deallocations)",
+            counts.callbacks, callbacks, counts.allocations, counts.deallocations
+        );
+        if callbacks > 0 {
+            let micros = |nanos: u64| nanos as f64 / 1000.0;
+            let mean = micros(TOTAL_NANOS.load(Ordering::Relaxed) / callbacks);
+            let period = micros(TOTAL_PERIOD_NANOS.load(Ordering::Relaxed) / callbacks);
+            println!(
+                "    callback duration: mean {:.1} us, max {:.1} us, buffer period {:.1} us (mean load {:.1}%)",
+                mean,
+                micros(MAX_NANOS.load(Ordering::Relaxed)),
+                period,
+                100.0 * mean / period.max(std::f64::EPSILON),
+            );
+        }
+        println!(
+            "    {} callbacks took longer than the buffer period, {} xruns",
+            OVERRUNS.load(Ordering::Relaxed),
+            XRUNS.load(Ordering::Relaxed)
+        );
+    }
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.running.store(false, Ordering::Relaxed);
+            thread.join().ok();
+            self.report();
+        }
+    }
+}
+
+/// A snapshot of the violation counters, `callbacks` counts the callbacks
+/// with violations.
+#[derive(Clone, Copy)]
+struct Counts {
+    allocations: u64,
+    deallocations: u64,
+    callbacks: u64,
+}
+
+impl Counts {
+    fn load() -> Self {
+        Counts {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+            callbacks: BAD_CALLBACKS.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! The musical position of the host transport, for synths that sequence or
+//! modulate in time with it.
+//!
+//! Backends that follow a transport (JACK with `--transport`) put a
+//! `TransportInfo` in `ProcessInfo::transport`. Synths fall back to their own
+//! free running timing when there is no beat position, either because there
+//! is no transport or because nobody is timebase master and the transport
+//! only knows its frame (see `ProcessInfo::beat_position`).
+use crate::backend::ProcessInfo;
+
+/// The state of the transport at the first frame of a block.
+#[derive(Debug, Clone, Copy)]
+pub struct TransportInfo {
+    pub rolling: bool,
+    /// The frame on the transport timeline.
+    pub frame: u64,
+    /// Tempo and position in beats, only known if there is a timebase master.
+    pub position: Option<BeatPosition>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BeatPosition {
+    pub bpm: f64,
+    pub beats_per_bar: f64,
+    /// Beats since the start of the timeline, assuming the time signature
+    /// didn't change.
+    pub beats: f64,
+}
+
+impl BeatPosition {
+    pub fn beats_per_frame(&self, sample_rate: usize) -> f64 {
+        self.bpm / 60.0 / sample_rate as f64
+    }
+
+    /// The position `frame` frames into the block.
+    pub fn beats_at(&self, frame: usize, sample_rate: usize) -> f64 {
+        self.beats + frame as f64 * self.beats_per_frame(sample_rate)
+    }
+}
+
+/// A step of a `BeatClock` starting in the current block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Step {
+    /// Steps since the start of the timeline.
+    pub index: i64,
+    /// The frame in the block it starts at.
+    pub frame: usize,
+}
+
+/// Divides the beats of a rolling transport into steps for sequencing.
+pub struct BeatClock {
+    steps_per_beat: f64,
+    last_step: Option<i64>,
+}
+
+impl BeatClock {
+    pub fn new(steps_per_beat: usize) -> Self {
+        BeatClock {
+            steps_per_beat: steps_per_beat.max(1) as f64,
+            last_step: None,
+        }
+    }
+
+    /// The first step starting in the block described by `info`. `None` if
+    /// there is none or the transport isn't rolling with a known tempo. With
+    /// steps shorter than a block only one of them is returned.
+    pub fn next_step(&mut self, info: &ProcessInfo) -> Option<Step> {
+        let position = match info.transport {
+            Some(TransportInfo {
+                rolling: true,
+                position: Some(position),
+                ..
+            }) => position,
+            _ => {
+                self.last_step = None;
+                return None;
+            }
+        };
+        let start = position.beats * self.steps_per_beat;
+        let end = position.beats_at(info.buffer_size, info.sample_rate) * self.steps_per_beat;
+        let mut step = start.ceil() as i64;
+        if let Some(last) = self.last_step {
+            if step <= last && start + 1.0 > last as f64 {
+                // The BBT position is quantized to ticks, so the start of this
+                // block can be a little before a step that was already played.
+                step = last + 1;
+            }
+        }
+        if step as f64 >= end {
+            return None;
+        }
+        self.last_step = Some(step);
+        let steps_per_frame = position.beats_per_frame(info.sample_rate) * self.steps_per_beat;
+        let frame = ((step as f64 - start) / steps_per_frame).max(0.0) as usize;
+        Some(Step {
+            index: step,
+            frame: frame.min(info.buffer_size.saturating_sub(1)),
+        })
+    }
+}
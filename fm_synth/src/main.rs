@@ -1,30 +1,75 @@
 //! Sine wave generator with frequency configuration exposed through standard
 //! input.
 extern crate crossbeam_channel;
-extern crate jack;
 extern crate sample;
+extern crate audio_utils;
 
-use crossbeam_channel::bounded;
+use audio_utils::args;
+use audio_utils::backend::{self, AnyBackend, AudioBackend, BackendKind, ProcessInfo};
+use audio_utils::oversample::{self, Decimator};
+use audio_utils::param::{Smoothed, Smoothing, DEFAULT_SMOOTHING_MS};
+use audio_utils::render::EventQueue;
+use audio_utils::rt_audit;
+use audio_utils::transport::{BeatClock, TransportInfo};
+use crossbeam_channel::{bounded, Receiver};
+use std::f64::consts::PI;
 use std::io;
 use std::str::FromStr;
 
 use sample::{signal, Signal};
 
+/// Counts the allocations made in the process callback.
+#[cfg(feature = "rt_audit")]
+#[global_allocator]
+static ALLOCATOR: rt_audit::CountingAllocator = rt_audit::CountingAllocator;
+
+const TAU: f64 = 2.0 * PI;
+
+/// How the carrier frequency is computed from the modulator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FMMode {
+    /// Textbook FM: the carrier runs at `freq * c_ratio`, the modulator at
+    /// `freq * m_ratio` and the peak frequency deviation is
+    /// `m_index * modulator frequency`. The sidebands are then at
+    /// `carrier ± n * modulator` with amplitudes given by the Bessel functions
+    /// `J_n(m_index)`.
+    Standard,
+    /// The original computation, kept so that old patches and recordings can
+    /// be reproduced. The deviation is `m_index * freq` regardless of
+    /// `m_ratio` and the carrier frequency including the modulation is
+    /// multiplied by `c_ratio` once more, so any `c_ratio != 1` both
+    /// transposes the carrier by `c_ratio²` and scales the modulation depth.
+    /// Parameters change immediately and a trigger resets the phases like
+    /// they used to.
+    Legacy,
+}
+
+/// How long the attack of a trigger ramps up, short enough to stay percussive.
+const ATTACK_MS: f64 = 2.0;
+
 struct FMSynth {
     sample_rate: f64,
-    freq: f64,
-    m_ratio: f64,
-    c_ratio: f64,
+    freq: Smoothed,
+    m_ratio: Smoothed,
+    c_ratio: Smoothed,
     m_index: f64,
     c_phase: f64,
     c_phase_step: f64,
     m_phase: f64,
     m_phase_step: f64,
-    lfo_freq: f64,
+    /// In Hz, or in cycles per beat while the LFO follows the transport.
+    lfo_freq: Smoothed,
+    /// The beats per second while following the transport.
+    lfo_beat_hz: Option<f64>,
     lfo_amp: f64,
     lfo_add: f64,
     lfo_phase: f64,
-    amp: f64,
+    amp: Smoothed,
+    mode: FMMode,
+    /// Number of samples computed per output sample to reduce aliasing.
+    oversampling: usize,
+    decimator: Decimator,
+    oversampled: [f64; oversample::MAX_FACTOR],
 }
 
 impl FMSynth {
@@ -35,144 +80,295 @@ impl FMSynth {
         // let car_freq = signal::gen(|| [freq * c_ratio]).add_amp(modulator);
         // let carrier = signal::rate(sample_rate).hz(car_freq).sine();
 
-        let mut synth = FMSynth {
+        let smoothed = |value| Smoothed::new(value, sample_rate, DEFAULT_SMOOTHING_MS, Smoothing::OnePole);
+        FMSynth {
             sample_rate,
-            freq,
-            m_ratio,
-            c_ratio,
+            freq: smoothed(freq),
+            m_ratio: smoothed(m_ratio),
+            c_ratio: smoothed(c_ratio),
             m_index,
             c_phase: 0.0,
             c_phase_step: 0.0,
             m_phase: 0.0,
             m_phase_step: 0.0,
-            lfo_freq: 3.0,
+            lfo_freq: smoothed(3.0),
+            lfo_beat_hz: None,
             lfo_amp: 4.0,
             lfo_add: 5.0,
             lfo_phase: 0.0,
-            amp,
-        };
-        synth
+            amp: Smoothed::new(amp, sample_rate, ATTACK_MS, Smoothing::Linear),
+            mode: FMMode::Standard,
+            oversampling: 1,
+            decimator: Decimator::new(1),
+            oversampled: [0.0; oversample::MAX_FACTOR],
+        }
+    }
+    /// Compute `factor` samples for every output sample and filter out
+    /// everything above the output Nyquist frequency before decimating.
+    /// The LFO driving `m_index` up to 9 creates sidebands far above it that
+    /// would otherwise fold back.
+    fn set_oversampling(&mut self, factor: usize) {
+        self.oversampling = factor.max(1).min(oversample::MAX_FACTOR);
+        self.decimator = Decimator::new(self.oversampling);
+        // The parameters are smoothed at the rate the samples are computed at.
+        let rate = self.sample_rate * self.oversampling as f64;
+        let mut params = [
+            &mut self.freq,
+            &mut self.m_ratio,
+            &mut self.c_ratio,
+            &mut self.lfo_freq,
+            &mut self.amp,
+        ];
+        for param in params.iter_mut() {
+            param.set_sample_rate(rate);
+        }
+    }
+    /// Lock the LFO phase to the beats of the transport at the start of a
+    /// block, `lfo_freq` is then in cycles per beat. Without a transport
+    /// position the LFO runs free.
+    fn sync_lfo(&mut self, transport: Option<&TransportInfo>) {
+        match transport.and_then(|t| t.position.map(|position| (t.rolling, position))) {
+            Some((rolling, position)) => {
+                let cycles_per_beat = self.lfo_freq.value();
+                self.lfo_phase = wrap_phase(TAU * position.beats * cycles_per_beat);
+                // A stopped transport holds the LFO where it is.
+                self.lfo_beat_hz = Some(if rolling { position.bpm / 60.0 } else { 0.0 });
+            }
+            None => self.lfo_beat_hz = None,
+        }
+    }
+    fn set_mode(&mut self, mode: FMMode) {
+        self.mode = mode;
+        let time_ms = |time_ms| if mode == FMMode::Legacy { 0.0 } else { time_ms };
+        self.freq.set_time_ms(time_ms(DEFAULT_SMOOTHING_MS));
+        self.m_ratio.set_time_ms(time_ms(DEFAULT_SMOOTHING_MS));
+        self.c_ratio.set_time_ms(time_ms(DEFAULT_SMOOTHING_MS));
+        self.lfo_freq.set_time_ms(time_ms(DEFAULT_SMOOTHING_MS));
+        self.amp.set_time_ms(time_ms(ATTACK_MS));
     }
     fn next_stereo(&mut self) -> [f64; 2] {
+        let c_sample = if self.oversampling == 1 {
+            self.next_sample(self.sample_rate)
+        } else {
+            let rate = self.sample_rate * self.oversampling as f64;
+            for i in 0..self.oversampling {
+                self.oversampled[i] = self.next_sample(rate);
+            }
+            self.decimator.process(&self.oversampled[..self.oversampling])
+        };
+
+        [c_sample, c_sample]
+    }
+    /// Compute one sample at the given rate, which is the device rate times
+    /// the oversampling factor.
+    fn next_sample(&mut self, rate: f64) -> f64 {
+        // All phases are wrapped to [0, 2PI) so that they don't lose precision
+        // by growing without bound.
+        let freq = self.freq.next();
+        let c_ratio = self.c_ratio.next();
+        let m_ratio = self.m_ratio.next();
+        let amp = self.amp.next();
+
         // LFO
-        self.lfo_phase += (2.0 * std::f64::consts::PI * self.lfo_freq) / self.sample_rate;
+        let lfo_freq = self.lfo_freq.next() * self.lfo_beat_hz.unwrap_or(1.0);
+        self.lfo_phase = wrap_phase(self.lfo_phase + (TAU * lfo_freq) / rate);
         let lfo = self.lfo_phase.sin() * self.lfo_amp + self.lfo_add;
         self.m_index = lfo;
 
         // Modulator
-        self.m_phase_step = (2.0 * std::f64::consts::PI * self.freq * self.m_ratio) / self.sample_rate;
-        self.m_phase += self.m_phase_step;
-        let m_sample = self.m_phase.sin() * self.freq * self.m_index;
+        let m_freq = freq * m_ratio;
+        self.m_phase_step = (TAU * m_freq) / rate;
+        self.m_phase = wrap_phase(self.m_phase + self.m_phase_step);
 
         // Carrier
         // The frequency depends on the modulator so the phase step has to be calculated every step
-        let c_freq = self.freq * self.c_ratio + m_sample;
-        self.c_phase_step = (2.0 * std::f64::consts::PI * c_freq * self.c_ratio) / self.sample_rate;
-        self.c_phase += self.c_phase_step;
+        let c_freq = match self.mode {
+            FMMode::Standard => {
+                let deviation = self.m_index * m_freq;
+                freq * c_ratio + self.m_phase.sin() * deviation
+            }
+            FMMode::Legacy => {
+                let m_sample = self.m_phase.sin() * freq * self.m_index;
+                (freq * c_ratio + m_sample) * c_ratio
+            }
+        };
+        self.c_phase_step = (TAU * c_freq) / rate;
+        self.c_phase = wrap_phase(self.c_phase + self.c_phase_step);
 
         // The carrier output is the output of the synth
-        let c_sample = self.c_phase.sin() * self.amp;
-        
-        [c_sample, c_sample]
+        self.c_phase.sin() * amp
     }
     fn set_freq(&mut self, freq: f64) {
-        self.freq = freq;
+        self.freq.set(freq);
     }
     fn control_rate_update(&mut self) {
-        self.amp *= 0.98;
+        let amp = self.amp.target() * 0.98;
+        self.amp.set(amp);
     }
     fn trigger(&mut self, freq: f64) {
         // Set the new frequency
-        self.freq = freq;
+        self.freq.set(freq);
         // Setting the amplitude triggers an attack
-        self.amp = 0.5;
-        // Reset all phases
-        // self.lfo_phase = 0.0; // You may or may not want to reset the lfo phase based on how you use it
-        self.m_phase = 0.0;
-        self.c_phase = 0.0;
+        self.amp.set(0.5);
+        match self.mode {
+            FMMode::Legacy => {
+                // Reset all phases
+                // self.lfo_phase = 0.0; // You may or may not want to reset the lfo phase based on how you use it
+                self.m_phase = 0.0;
+                self.c_phase = 0.0;
+            }
+            // Jumping to phase 0 while the previous note is still sounding
+            // would click.
+            FMMode::Standard => (),
+        }
     }
 }
 
+/// Wrap a phase in radians to [0, 2PI). The carrier phase step can be
+/// negative when the modulation is deeper than the carrier frequency.
+fn wrap_phase(phase: f64) -> f64 {
+    phase.rem_euclid(TAU)
+}
+
 fn main() {
-    // 1. open a client
-    let (client, _status) =
-        jack::Client::new("rust_jack_sine", jack::ClientOptions::NO_START_SERVER).unwrap();
-
-    // 2. register port
-    let mut out_port_l = client
-        .register_port("sine_out_l", jack::AudioOut::default())
-        .unwrap();
-    let mut out_port_r = client
-        .register_port("sine_out_r", jack::AudioOut::default())
-        .unwrap();
-
-    // 3. define process callback handler
+    // Reports on the process callback when main returns, with the rt_audit feature.
+    let _audit = rt_audit::Monitor::start();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let backend_kind = match BackendKind::from_args(&mut args) {
+        Ok(kind) => kind,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let oversampling = match args::take_parsed(&mut args, "--oversampling", oversample::parse_factor) {
+        Ok(factor) => factor.unwrap_or(1),
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let fm_mode = if args::take_flag(&mut args, "--legacy-fm") {
+        FMMode::Legacy
+    } else {
+        FMMode::Standard
+    };
+    if let Err(e) = args::finish(&args, &usage()) {
+        println!("{}", e);
+        return;
+    }
+    // Timed messages from the events file are only used when rendering.
+    let mut events = match &backend_kind {
+        BackendKind::Wav(settings) => match EventQueue::load(settings, parse_freq) {
+            Ok(events) => events,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        },
+        _ => EventQueue::new(vec![]),
+    };
+
+    // 1. open the backend, for JACK this opens a client and registers the ports.
+    // The ports are connected according to the --connect rules once it is started.
+    let backend = AnyBackend::open(backend_kind, "rust_jack_sine", &["sine_out_l", "sine_out_r"]).unwrap();
+    let is_realtime = backend.is_realtime();
+
+    // 2. define the process callback
+    let (tx, rx) = bounded::<[f64; 4]>(1_000_000);
+    let event_tx = tx.clone();
+    let mut process_block = fm_process(backend.sample_rate(), oversampling, fm_mode, rx);
+    let process = backend::stereo(backend.buffer_size(), move |out_l, out_r, info| {
+        while let Some(f) = events.pop_due(info.time()) {
+            event_tx.try_send(f).ok();
+        }
+        process_block(out_l, out_r, info);
+    });
+
+    // 3. start processing, offline backends are done when this returns
+    let running = backend.start(process).unwrap();
+    if !is_realtime {
+        println!("Done rendering");
+        return;
+    }
+
+    // 4. wait or do some processing while your handler is running in real time.
+    println!("Enter freq c_ratio m_ratio lfo_freq");
+    while let Some(f) = read_freq() {
+        tx.send(f).unwrap();
+    }
+
+    // 5. Optional stop. Not required since the backend stops on drop.
+    drop(running);
+}
+
+/// Create the process logic of the synth: parameter messages from `rx`, the
+/// FMSynth and the trigger counter. It is run by whichever audio
+/// backend was chosen on the command line. When the backend follows a
+/// transport with a tempo notes are triggered on its beats instead of by the
+/// counter.
+fn fm_process(
+    sample_rate: usize,
+    oversampling: usize,
+    fm_mode: FMMode,
+    rx: Receiver<[f64; 4]>,
+) -> impl FnMut(&mut [f32], &mut [f32], &ProcessInfo) {
     let mut frequency = 220.0;
-    let sample_rate = client.sample_rate();
     let frame_t = 1.0 / sample_rate as f64;
     let mut time = 0.0;
-    let (tx, rx) = bounded::<[f64; 4]>(1_000_000);
 
     // FMSynth setup
     let mut fm_synth = FMSynth::new(sample_rate as f64, frequency, 1.0, 2.0, 1.0, 4.0);
+    fm_synth.set_oversampling(oversampling);
+    fm_synth.set_mode(fm_mode);
     let mut counter = 0;
+    let mut beat_clock = BeatClock::new(1);
 
-    let process = jack::ClosureProcessHandler::new(
-        move |_: &jack::Client, ps: &jack::ProcessScope| -> jack::Control {
-            // Get output buffer
-            let out_l = out_port_l.as_mut_slice(ps);
-            let out_r = out_port_r.as_mut_slice(ps);
-
-            // Check frequency requests
-            while let Ok(f) = rx.try_recv() {
-                time = 0.0;
-                frequency = f[0];
-                fm_synth.set_freq(f[0]);
-                fm_synth.c_ratio = f[1];
-                fm_synth.m_ratio = f[2];
-                fm_synth.lfo_freq = f[3];
-                fm_synth.trigger(f[0]);
-            }
-
-            // Write output
-            for (l, r) in out_l.iter_mut().zip(out_r.iter_mut()) {
-                let frame = fm_synth.next_stereo();
-                *l = frame[0] as f32;
-                *r = frame[1] as f32;
-                time += frame_t;
-            }
+    move |out_l: &mut [f32], out_r: &mut [f32], info: &ProcessInfo| {
+        // Check frequency requests
+        while let Ok(f) = rx.try_recv() {
+            time = 0.0;
+            frequency = f[0];
+            fm_synth.set_freq(f[0]);
+            fm_synth.c_ratio.set(f[1]);
+            fm_synth.m_ratio.set(f[2]);
+            fm_synth.lfo_freq.set(f[3]);
+            fm_synth.trigger(f[0]);
+        }
 
-            fm_synth.control_rate_update();
+        // Trigger on the beats of the transport, at the start of the block
+        fm_synth.sync_lfo(info.transport.as_ref());
+        if beat_clock.next_step(info).is_some() {
+            fm_synth.trigger(frequency);
+        }
 
-            // Trigger based on counter
-            const COUNTER_STEP: usize = 80;
-            for i in 0..16 {
-                if counter == i * COUNTER_STEP {
-                    fm_synth.trigger(frequency)
-                }
-            }
+        // Write output
+        for (l, r) in out_l.iter_mut().zip(out_r.iter_mut()) {
+            let frame = fm_synth.next_stereo();
+            *l = frame[0] as f32;
+            *r = frame[1] as f32;
+            time += frame_t;
+        }
 
-            counter = (counter+1) % (COUNTER_STEP*16);
+        fm_synth.control_rate_update();
 
-            // Continue as normal
-            jack::Control::Continue
-        },
-    );
-
-    // 4. activate the client
-    let active_client = client.activate_async((), process).unwrap();
-    // processing starts here
+        // Trigger based on counter
+        const COUNTER_STEP: usize = 80;
+        for i in 0..16 {
+            if counter == i * COUNTER_STEP && info.beat_position().is_none() {
+                fm_synth.trigger(frequency)
+            }
+        }
 
-    // 5. wait or do some processing while your handler is running in real time.
-    println!("Enter freq c_ratio m_ratio lfo_freq");
-    while let Some(f) = read_freq() {
-        tx.send(f).unwrap();
+        counter = (counter+1) % (COUNTER_STEP*16);
     }
+}
+
+const FM_USAGE: &str = "    --oversampling <n>     run the FM at 1, 2 or 4 times the sample rate (default 1)
+    --legacy-fm            use the old carrier frequency computation to reproduce old recordings";
 
-    // 6. Optional deactivate. Not required since active_client will deactivate on
-    // drop, though explicit deactivate may help you identify errors in
-    // deactivate.
-    active_client.deactivate().unwrap();
+fn usage() -> String {
+    format!("{}\n{}", backend::usage(), FM_USAGE)
 }
 
 /// Attempt to read a frequency from standard in. Will block until there is
@@ -181,14 +377,154 @@ fn main() {
 fn read_freq() -> Option<[f64; 4]> {
     let mut user_input = String::new();
     match io::stdin().read_line(&mut user_input) {
-        Ok(_) => {
-            let mut values: [f64; 4] = [220.0, 1.0, 1.0, 1.0];
-            let strings: Vec<&str> = user_input.split(" ").collect();
-            for (i, string) in strings.into_iter().enumerate() {
-                values[i] = f64::from_str(string.trim()).unwrap();
-            }
-            Some(values)
-        },
+        Ok(_) => parse_freq(&user_input),
         Err(_) => None,
     }
 }
+
+/// Parse a line of `freq c_ratio m_ratio lfo_freq`, missing values get a default.
+fn parse_freq(line: &str) -> Option<[f64; 4]> {
+    let mut values: [f64; 4] = [220.0, 1.0, 1.0, 1.0];
+    for (value, string) in values.iter_mut().zip(line.split(" ")) {
+        *value = f64::from_str(string.trim()).ok()?;
+    }
+    Some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 48000.0;
+    /// The number of samples analysed, 10 Hz per DFT bin at 48 kHz. All the
+    /// frequencies in these tests are multiples of 10 Hz so that every
+    /// component falls exactly on a bin.
+    const N: usize = 4800;
+
+    /// A synth holding `m_index` still instead of sweeping it with the LFO.
+    fn steady_synth(freq: f64, c_ratio: f64, m_ratio: f64, m_index: f64) -> FMSynth {
+        let mut synth = FMSynth::new(SAMPLE_RATE, freq, 1.0, m_ratio, c_ratio, m_index);
+        synth.lfo_amp = 0.0;
+        synth.lfo_add = m_index;
+        synth
+    }
+
+    /// The amplitude of the sinusoid in every DFT bin up to the Nyquist
+    /// frequency, after letting the decimator settle.
+    fn spectrum(synth: &mut FMSynth) -> Vec<f64> {
+        for _ in 0..N {
+            synth.next_stereo();
+        }
+        let samples: Vec<f64> = (0..N).map(|_| synth.next_stereo()[0]).collect();
+        let table: Vec<(f64, f64)> = (0..N)
+            .map(|i| {
+                let w = TAU * i as f64 / N as f64;
+                (w.cos(), w.sin())
+            })
+            .collect();
+        (0..=N / 2)
+            .map(|k| {
+                let (mut re, mut im) = (0.0, 0.0);
+                for (n, s) in samples.iter().enumerate() {
+                    let (cos, sin) = table[(k * n) % N];
+                    re += s * cos;
+                    im -= s * sin;
+                }
+                2.0 * (re * re + im * im).sqrt() / N as f64
+            })
+            .collect()
+    }
+
+    /// The share of the energy that has folded back from above the Nyquist
+    /// frequency. Carrier and modulator both run at `freq`, so everything
+    /// that isn't a harmonic of it is aliasing.
+    fn folded_energy(oversampling: usize) -> f64 {
+        // 48 kHz is not a multiple of 3330 Hz so folded components land
+        // between the harmonics.
+        let mut synth = steady_synth(3330.0, 1.0, 1.0, 8.0);
+        synth.set_oversampling(oversampling);
+        let spectrum = spectrum(&mut synth);
+        let total: f64 = spectrum.iter().map(|a| a * a).sum();
+        let folded: f64 = spectrum
+            .iter()
+            .enumerate()
+            .filter(|(k, _)| k % 333 != 0)
+            .map(|(_, a)| a * a)
+            .sum();
+        folded / total
+    }
+
+    /// The Bessel function of the first kind `J_n(x)` from its power series.
+    fn bessel_j(n: usize, x: f64) -> f64 {
+        let mut term = (x / 2.0).powi(n as i32) / (1..=n).map(|i| i as f64).product::<f64>();
+        let mut sum = 0.0;
+        for k in 1..30 {
+            sum += term;
+            term *= -(x / 2.0).powi(2) / (k * (k + n)) as f64;
+        }
+        sum
+    }
+
+    /// Check that the sideband at `carrier ± n * modulator` has the
+    /// amplitude `|J_n(m_index)|`.
+    fn check_sidebands(freq: f64, c_ratio: f64, m_ratio: f64, m_index: f64) {
+        let mut synth = steady_synth(freq, c_ratio, m_ratio, m_index);
+        synth.set_mode(FMMode::Standard);
+        let spectrum = spectrum(&mut synth);
+        let bin = |hz: f64| (hz / 10.0).round() as usize;
+        let (carrier, modulator) = (freq * c_ratio, freq * m_ratio);
+        for n in 0..6 {
+            let expected = bessel_j(n, m_index).abs();
+            let offset = n as f64 * modulator;
+            for &sideband in &[carrier + offset, carrier - offset] {
+                let amp = spectrum[bin(sideband)];
+                assert!(
+                    (amp - expected).abs() < 1e-3,
+                    "ratio {}:{}, index {}: {} at {} Hz, expected J_{} = {}",
+                    c_ratio, m_ratio, m_index, amp, sideband, n, expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn standard_sidebands_follow_bessel_functions() {
+        check_sidebands(1000.0, 8.0, 1.0, 2.0);
+        check_sidebands(1000.0, 5.0, 0.7, 1.0);
+        check_sidebands(400.0, 20.0, 3.0, 1.5);
+    }
+
+    #[test]
+    fn legacy_trigger_is_immediate() {
+        let mut synth = steady_synth(220.0, 1.0, 2.0, 4.0);
+        synth.set_mode(FMMode::Legacy);
+        synth.amp.reset(0.1);
+        for _ in 0..100 {
+            synth.next_stereo();
+        }
+        synth.trigger(440.0);
+        assert_eq!((synth.m_phase, synth.c_phase), (0.0, 0.0));
+        synth.next_stereo();
+        assert_eq!(synth.freq.value(), 440.0);
+        assert_eq!(synth.amp.value(), 0.5);
+
+        // Standard mode glides and keeps the phases.
+        synth.set_mode(FMMode::Standard);
+        synth.next_stereo();
+        let phases = (synth.m_phase, synth.c_phase);
+        synth.trigger(220.0);
+        assert_eq!((synth.m_phase, synth.c_phase), phases);
+        synth.next_stereo();
+        assert!(synth.freq.value() > 220.0);
+    }
+
+    #[test]
+    fn oversampling_removes_folded_sidebands() {
+        let db = |ratio: f64| 10.0 * ratio.log10();
+        let (x1, x2, x4) = (folded_energy(1), folded_energy(2), folded_energy(4));
+        // Without oversampling a large part of the spectrum is folded.
+        assert!(db(x1) > -20.0, "1x: {} dB", db(x1));
+        assert!(db(x2) < -60.0, "2x: {} dB", db(x2));
+        assert!(db(x4) < -60.0, "4x: {} dB", db(x4));
+    }
+}
@@ -0,0 +1,145 @@
+//! Editing the patch while the synth is running.
+//!
+//! Commands are applied to the `PatchDesc` on the main thread. Changes of live
+//! parameters are sent to the audio thread as they are. Anything else builds a
+//! new `Patch`, which the audio thread swaps in at the start of the next
+//! buffer, taking over the state of the nodes that are still there. Nothing
+//! is allocated or deallocated in the audio thread.
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::nodes::node_type;
+use crate::patch::{Patch, PatchDesc, PatchError, PatchNode};
+
+pub const EDIT_USAGE: &str = "Commands:
+    <freq> [c_ratio] [m_ratio] [m_index]   set the frequency and FM parameters and trigger a note
+    set <node> <param> <value>             change a parameter of a node
+    add <name> <type>                      add a node (mixer, operator, adsr, svf, gain_pan, delay, noise)
+    remove <name>                          remove a node with its connections
+    connect <from> <to>                    connect the output of <from> to the input of <to>
+    disconnect <from> <to>                 remove a connection
+    reload                                 load the patch file again";
+
+/// A message to the audio thread.
+pub enum Command {
+    /// A `freq c_ratio m_ratio m_index` message.
+    Params([f64; 4]),
+    SetParam {
+        node: PatchNode,
+        param: &'static str,
+        value: f64,
+    },
+    /// Replace the patch. With `keep_state` the new patch takes over the
+    /// state of the nodes of the old one, except for `except`.
+    Swap {
+        patch: Box<Patch>,
+        keep_state: bool,
+        except: Option<PatchNode>,
+    },
+}
+
+/// Keeps the description of the patch that is playing.
+pub struct Editor {
+    path: Option<PathBuf>,
+    default: &'static str,
+    sample_hz: f64,
+    desc: PatchDesc,
+    names: HashMap<String, PatchNode>,
+}
+
+impl Editor {
+    /// Load the patch at `path`, or `default` if there is none.
+    pub fn new(path: Option<PathBuf>, default: &'static str, sample_hz: f64) -> Result<(Self, Patch), PatchError> {
+        let desc = load_desc(&path, default)?;
+        let patch = desc.build(sample_hz)?;
+        let editor = Editor {
+            path,
+            default,
+            sample_hz,
+            desc,
+            names: patch.names().clone(),
+        };
+        Ok((editor, patch))
+    }
+
+    /// Apply the edit command in `line`, `None` if it isn't one. If the
+    /// command fails the patch stays as it was.
+    pub fn command(&mut self, line: &str) -> Option<Result<Command, PatchError>> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words.as_slice() {
+            ["reload"] => self.reload(),
+            ["set", node, param, value] => self.set(node, param, value),
+            ["add", name, kind] => self.edit(None, |desc| desc.add_node(name, kind)),
+            ["remove", name] => self.edit(None, |desc| desc.remove_node(name)),
+            ["connect", from, to] => self.edit(None, |desc| {
+                desc.connect(from, to);
+                Ok(())
+            }),
+            ["disconnect", from, to] => self.edit(None, |desc| desc.disconnect(from, to)),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    fn reload(&mut self) -> Result<Command, PatchError> {
+        let desc = load_desc(&self.path, self.default)?;
+        let patch = desc.build(self.sample_hz)?;
+        self.desc = desc;
+        Ok(self.swap(patch, false, None))
+    }
+
+    fn set(&mut self, node: &str, param: &str, value: &str) -> Result<Command, PatchError> {
+        let value = match f64::from_str(value) {
+            Ok(value) => toml::Value::Float(value),
+            Err(_) => toml::Value::String(value.to_string()),
+        };
+        let kind = match self.desc.node_kind(node) {
+            Some(kind) => kind,
+            None => return Err(PatchError::MissingNode(node.to_string())),
+        };
+        let live_param = node_type(kind).and_then(|t| t.live_params.iter().find(|p| **p == param));
+        match (live_param, &value) {
+            (Some(live_param), toml::Value::Float(number)) => {
+                let number = *number;
+                self.desc.set_param(node, param, value)?;
+                Ok(Command::SetParam {
+                    node: self.names[node],
+                    param: *live_param,
+                    value: number,
+                })
+            }
+            // Other parameters need the node to be built again.
+            _ => self.edit(Some(node), |desc| desc.set_param(node, param, value)),
+        }
+    }
+
+    /// Change a copy of the description with `f` and build it.
+    fn edit<F>(&mut self, except: Option<&str>, f: F) -> Result<Command, PatchError>
+    where
+        F: FnOnce(&mut PatchDesc) -> Result<(), PatchError>,
+    {
+        let mut desc = self.desc.clone();
+        f(&mut desc)?;
+        let patch = desc.build(self.sample_hz)?;
+        self.desc = desc;
+        let except = except.and_then(|name| patch.names().get(name).cloned());
+        Ok(self.swap(patch, true, except))
+    }
+
+    fn swap(&mut self, patch: Patch, keep_state: bool, except: Option<PatchNode>) -> Command {
+        self.names = patch.names().clone();
+        Command::Swap {
+            patch: Box::new(patch),
+            keep_state,
+            except,
+        }
+    }
+}
+
+fn load_desc(path: &Option<PathBuf>, default: &str) -> Result<PatchDesc, PatchError> {
+    match path {
+        Some(path) => PatchDesc::load(path),
+        None => PatchDesc::parse(default),
+    }
+}
@@ -0,0 +1,322 @@
+//! Audio-rate modulation between nodes.
+//!
+//! The dsp graph sums every input into the buffer of its parent, which is
+//! right for audio but not for a signal that should change a parameter. A
+//! `ModGraph` keeps modulation sources next to the dsp graph and routes their
+//! output into the named `ModInput`s of other nodes, block by block, before the
+//! graph is rendered. Sources can modulate other sources as long as the routes
+//! don't form a cycle.
+use std::fmt;
+
+use dsp::{Graph, Node, NodeIndex};
+
+use crate::nodes::{DspNode, StereoFrame, CHANNELS};
+
+/// The largest number of frames the modulation buffers hold. Longer buffers
+/// are rendered in several blocks.
+pub const MAX_BLOCK_SIZE: usize = 64;
+
+/// The parameters that can be modulated. Which ones a node reads, and how, is
+/// documented on the node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModInput {
+    Frequency,
+    Amplitude,
+    Phase,
+}
+
+const NUM_MOD_INPUTS: usize = 3;
+
+impl ModInput {
+    fn index(self) -> usize {
+        match self {
+            ModInput::Frequency => 0,
+            ModInput::Amplitude => 1,
+            ModInput::Phase => 2,
+        }
+    }
+}
+
+/// The modulation input buffers of a node. Inputs that nothing is routed to
+/// read as 0.0.
+#[derive(Debug, Clone)]
+pub struct ModInputs {
+    values: [Vec<f32>; NUM_MOD_INPUTS],
+}
+
+impl Default for ModInputs {
+    fn default() -> Self {
+        ModInputs {
+            values: [
+                vec![0.0; MAX_BLOCK_SIZE],
+                vec![0.0; MAX_BLOCK_SIZE],
+                vec![0.0; MAX_BLOCK_SIZE],
+            ],
+        }
+    }
+}
+
+impl ModInputs {
+    /// The value of `input` at `frame` of the current block.
+    pub fn get(&self, input: ModInput, frame: usize) -> f32 {
+        self.values[input.index()][frame]
+    }
+
+    fn clear(&mut self, input: ModInput) {
+        for value in self.values[input.index()].iter_mut() {
+            *value = 0.0;
+        }
+    }
+
+    /// Clear every input. Only the inputs that routes go to are cleared
+    /// before each block, so the others keep what they hold until then.
+    pub fn clear_all(&mut self) {
+        for &input in &[ModInput::Frequency, ModInput::Amplitude, ModInput::Phase] {
+            self.clear(input);
+        }
+    }
+
+    fn add(&mut self, input: ModInput, source: &[StereoFrame], depth: f32) {
+        for (value, frame) in self.values[input.index()].iter_mut().zip(source) {
+            *value += frame[0] * depth;
+        }
+    }
+}
+
+/// A modulation source created with `ModGraph::add_source`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceIndex(usize);
+
+/// Where a modulation route goes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModTarget {
+    Node(NodeIndex),
+    Source(SourceIndex),
+}
+
+#[derive(Debug)]
+struct ModRoute {
+    source: SourceIndex,
+    target: ModTarget,
+    input: ModInput,
+    depth: f32,
+}
+
+#[derive(Debug)]
+pub enum ModError {
+    /// The route would make a source modulate itself, directly or through
+    /// other sources.
+    WouldCycle,
+    /// The target node doesn't read this modulation input.
+    UnsupportedInput(ModInput),
+}
+
+impl fmt::Display for ModError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModError::WouldCycle => write!(f, "the modulation route would create a cycle"),
+            ModError::UnsupportedInput(input) => {
+                write!(f, "the target node has no {:?} modulation input", input)
+            }
+        }
+    }
+}
+
+/// A dsp graph together with its modulation sources and routes.
+pub struct ModGraph {
+    pub graph: Graph<StereoFrame, DspNode>,
+    sources: Vec<DspNode>,
+    buffers: Vec<Vec<StereoFrame>>,
+    routes: Vec<ModRoute>,
+    /// The sources in the order they have to be rendered.
+    order: Vec<usize>,
+}
+
+impl ModGraph {
+    pub fn new(graph: Graph<StereoFrame, DspNode>) -> Self {
+        ModGraph {
+            graph,
+            sources: vec![],
+            buffers: vec![],
+            routes: vec![],
+            order: vec![],
+        }
+    }
+
+    /// Add a node that is only used as a modulation source. It is rendered
+    /// on its own, without audio inputs.
+    pub fn add_source(&mut self, node: DspNode) -> SourceIndex {
+        self.sources.push(node);
+        self.buffers.push(vec![[0.0; CHANNELS]; MAX_BLOCK_SIZE]);
+        self.order.push(self.sources.len() - 1);
+        SourceIndex(self.sources.len() - 1)
+    }
+
+    pub fn source_mut(&mut self, source: SourceIndex) -> &mut DspNode {
+        &mut self.sources[source.0]
+    }
+
+    /// Route the output of `source`, scaled by `depth`, into `input` of
+    /// `target`. Several routes to the same input are summed.
+    pub fn add_modulation(
+        &mut self,
+        source: SourceIndex,
+        target: ModTarget,
+        input: ModInput,
+        depth: f32,
+    ) -> Result<(), ModError> {
+        let accepts = match target {
+            ModTarget::Node(node) => self.graph[node].accepts(input),
+            ModTarget::Source(target) => self.sources[target.0].accepts(input),
+        };
+        if !accepts {
+            return Err(ModError::UnsupportedInput(input));
+        }
+        self.routes.push(ModRoute {
+            source,
+            target,
+            input,
+            depth,
+        });
+        match self.render_order() {
+            Some(order) => {
+                self.order = order;
+                Ok(())
+            }
+            None => {
+                self.routes.pop();
+                Err(ModError::WouldCycle)
+            }
+        }
+    }
+
+    /// Sort the sources so every source comes after the sources modulating
+    /// it, `None` if there is a cycle.
+    fn render_order(&self) -> Option<Vec<usize>> {
+        let mut num_modulators = vec![0; self.sources.len()];
+        for route in &self.routes {
+            if let ModTarget::Source(target) = route.target {
+                num_modulators[target.0] += 1;
+            }
+        }
+        let mut ready: Vec<usize> = (0..self.sources.len())
+            .filter(|&s| num_modulators[s] == 0)
+            .collect();
+        let mut order = vec![];
+        while let Some(source) = ready.pop() {
+            order.push(source);
+            for route in self.routes.iter().filter(|r| r.source.0 == source) {
+                if let ModTarget::Source(target) = route.target {
+                    num_modulators[target.0] -= 1;
+                    if num_modulators[target.0] == 0 {
+                        ready.push(target.0);
+                    }
+                }
+            }
+        }
+        if order.len() == self.sources.len() {
+            Some(order)
+        } else {
+            None
+        }
+    }
+
+    /// Render the sources, route them to their targets and render the graph
+    /// into `buffer`.
+    pub fn audio_requested(&mut self, buffer: &mut [StereoFrame], sample_hz: f64) {
+        for block in buffer.chunks_mut(MAX_BLOCK_SIZE) {
+            self.render_block(block, sample_hz);
+        }
+    }
+
+    fn render_block(&mut self, buffer: &mut [StereoFrame], sample_hz: f64) {
+        let frames = buffer.len();
+        for i in 0..self.routes.len() {
+            let (target, input) = (self.routes[i].target, self.routes[i].input);
+            self.mod_inputs_mut(target).clear(input);
+        }
+        for i in 0..self.order.len() {
+            let source = self.order[i];
+            // All routes into this source have been added by now.
+            let output = &mut self.buffers[source][..frames];
+            dsp::slice::equilibrium(output);
+            self.sources[source].audio_requested(output, sample_hz);
+            for route in self.routes.iter().filter(|r| r.source.0 == source) {
+                let mods = match route.target {
+                    ModTarget::Node(node) => self.graph[node].mod_inputs_mut(),
+                    ModTarget::Source(target) => self.sources[target.0].mod_inputs_mut(),
+                };
+                if let Some(mods) = mods {
+                    mods.add(route.input, &self.buffers[source][..frames], route.depth);
+                }
+            }
+        }
+        self.graph.audio_requested(buffer, sample_hz);
+    }
+
+    fn mod_inputs_mut(&mut self, target: ModTarget) -> &mut ModInputs {
+        let node = match target {
+            ModTarget::Node(node) => &mut self.graph[node],
+            ModTarget::Source(source) => &mut self.sources[source.0],
+        };
+        node.mod_inputs_mut()
+            .expect("routes are only added to nodes with modulation inputs")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nodes::{Mixer, Operator};
+
+    fn operator() -> DspNode {
+        DspNode::Operator(Operator::new(44100.0, 220.0, 1.0, 1.0, 1.0))
+    }
+
+    fn position(graph: &ModGraph, source: SourceIndex) -> usize {
+        graph.order.iter().position(|&s| s == source.0).unwrap()
+    }
+
+    #[test]
+    fn modulation_cycle_is_rejected() {
+        let mut graph = ModGraph::new(Graph::new());
+        let a = graph.add_source(operator());
+        let b = graph.add_source(operator());
+        let c = graph.add_source(operator());
+        graph.add_modulation(a, ModTarget::Source(b), ModInput::Frequency, 1.0).unwrap();
+        graph.add_modulation(b, ModTarget::Source(c), ModInput::Frequency, 1.0).unwrap();
+        match graph.add_modulation(c, ModTarget::Source(a), ModInput::Phase, 1.0) {
+            Err(ModError::WouldCycle) => (),
+            other => panic!("expected a cycle, got {:?}", other),
+        }
+        match graph.add_modulation(a, ModTarget::Source(a), ModInput::Phase, 1.0) {
+            Err(ModError::WouldCycle) => (),
+            other => panic!("expected a cycle, got {:?}", other),
+        }
+        // The rejected routes are not kept.
+        assert_eq!(graph.routes.len(), 2);
+        assert!(position(&graph, a) < position(&graph, b));
+        assert!(position(&graph, b) < position(&graph, c));
+    }
+
+    #[test]
+    fn modulators_are_rendered_before_their_targets() {
+        let mut graph = ModGraph::new(Graph::new());
+        let out = graph.graph.add_node(DspNode::Mixer(Mixer::new(44100.0, 1.0)));
+        let carrier = graph.graph.add_node(operator());
+        graph.graph.add_connection(carrier, out).unwrap();
+        let c = graph.add_source(operator());
+        let b = graph.add_source(operator());
+        let a = graph.add_source(operator());
+        graph.add_modulation(a, ModTarget::Source(b), ModInput::Frequency, 1.0).unwrap();
+        graph.add_modulation(a, ModTarget::Source(c), ModInput::Amplitude, 1.0).unwrap();
+        graph.add_modulation(b, ModTarget::Source(c), ModInput::Frequency, 1.0).unwrap();
+        graph.add_modulation(c, ModTarget::Node(carrier), ModInput::Frequency, 1.0).unwrap();
+        assert!(position(&graph, a) < position(&graph, b));
+        assert!(position(&graph, b) < position(&graph, c));
+        match graph.add_modulation(a, ModTarget::Node(out), ModInput::Frequency, 1.0) {
+            Err(ModError::UnsupportedInput(ModInput::Frequency)) => (),
+            other => panic!("the mixer has no modulation inputs, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,514 @@
+//! The node types the dsp graph is built from.
+//!
+//! The graph sums the output of all inputs of a node into its buffer before
+//! `audio_requested` is called. Effects process that buffer in place, while
+//! generators overwrite it. Parameters are changed at audio rate through the
+//! `ModInput`s, see `modulation`.
+use std::f64::consts::PI;
+
+use audio_utils::param::{Smoothed, Smoothing, DEFAULT_SMOOTHING_MS};
+use dsp::Node;
+
+use crate::modulation::{ModInput, ModInputs};
+
+pub type Output = f32;
+pub const CHANNELS: usize = 2;
+pub type StereoFrame = [Output; CHANNELS];
+
+const TAU: f64 = 2.0 * PI;
+
+/// The name of a node type and its parameters as used in patches.
+pub struct NodeType {
+    pub name: &'static str,
+    pub params: &'static [&'static str],
+    /// The parameters that `DspNode::set_param` can change while running.
+    pub live_params: &'static [&'static str],
+}
+
+pub const NODE_TYPES: &[NodeType] = &[
+    NodeType {
+        name: "mixer",
+        params: &["volume"],
+        live_params: &["volume"],
+    },
+    NodeType {
+        name: "operator",
+        params: &["freq", "ratio", "index", "volume"],
+        live_params: &["freq", "ratio", "index", "volume"],
+    },
+    NodeType {
+        name: "adsr",
+        params: &["attack", "decay", "sustain", "release"],
+        live_params: &["attack", "decay", "sustain", "release"],
+    },
+    NodeType {
+        name: "svf",
+        params: &["mode", "cutoff", "q"],
+        live_params: &["cutoff", "q"],
+    },
+    NodeType {
+        name: "gain_pan",
+        params: &["gain", "pan"],
+        live_params: &["gain", "pan"],
+    },
+    NodeType {
+        name: "delay",
+        params: &["max_time", "time", "feedback", "mix"],
+        live_params: &["time", "feedback", "mix"],
+    },
+    NodeType {
+        name: "noise",
+        params: &["volume"],
+        live_params: &["volume"],
+    },
+];
+
+pub fn node_type(name: &str) -> Option<&'static NodeType> {
+    NODE_TYPES.iter().find(|t| t.name == name)
+}
+
+/// Every kind of node in the graph.
+#[derive(Debug)]
+pub enum DspNode {
+    Mixer(Mixer),
+    Operator(Operator),
+    Adsr(Adsr),
+    Svf(Svf),
+    GainPan(GainPan),
+    Delay(Delay),
+    Noise(Noise),
+}
+
+impl DspNode {
+    /// Whether the node reads the modulation `input`.
+    pub fn accepts(&self, input: ModInput) -> bool {
+        match self {
+            DspNode::Operator(_) => true,
+            DspNode::Svf(_) => input == ModInput::Frequency,
+            DspNode::GainPan(_) | DspNode::Noise(_) => input == ModInput::Amplitude,
+            DspNode::Mixer(_) | DspNode::Adsr(_) | DspNode::Delay(_) => false,
+        }
+    }
+
+    /// Set one of the `live_params` of the node type, returns false if the
+    /// node has no such parameter. The value is smoothed, except for the
+    /// envelope times. Doesn't allocate.
+    pub fn set_param(&mut self, param: &str, value: f64) -> bool {
+        match (self, param) {
+            (DspNode::Mixer(node), "volume") => node.volume.set(value),
+            (DspNode::Operator(node), "freq") => node.freq.set(value),
+            (DspNode::Operator(node), "ratio") => node.ratio.set(value),
+            (DspNode::Operator(node), "index") => node.index.set(value),
+            (DspNode::Operator(node), "volume") => node.volume.set(value),
+            (DspNode::Adsr(node), "attack") => node.attack = value,
+            (DspNode::Adsr(node), "decay") => node.decay = value,
+            (DspNode::Adsr(node), "sustain") => node.sustain.set(value),
+            (DspNode::Adsr(node), "release") => node.release = value,
+            (DspNode::Svf(node), "cutoff") => node.cutoff.set(value),
+            (DspNode::Svf(node), "q") => node.q.set(value),
+            (DspNode::GainPan(node), "gain") => node.gain.set(value),
+            (DspNode::GainPan(node), "pan") => node.pan.set(value),
+            (DspNode::Delay(node), "time") => node.time.set(value),
+            (DspNode::Delay(node), "feedback") => node.feedback.set(value),
+            (DspNode::Delay(node), "mix") => node.mix.set(value),
+            (DspNode::Noise(node), "volume") => node.volume.set(value),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn mod_inputs_mut(&mut self) -> Option<&mut ModInputs> {
+        match self {
+            DspNode::Operator(node) => Some(&mut node.mods),
+            DspNode::Svf(node) => Some(&mut node.mods),
+            DspNode::GainPan(node) => Some(&mut node.mods),
+            DspNode::Noise(node) => Some(&mut node.mods),
+            DspNode::Mixer(_) | DspNode::Adsr(_) | DspNode::Delay(_) => None,
+        }
+    }
+}
+
+impl Node<StereoFrame> for DspNode {
+    fn audio_requested(&mut self, buffer: &mut [StereoFrame], sample_hz: f64) {
+        match self {
+            DspNode::Mixer(node) => node.process(buffer),
+            DspNode::Operator(node) => node.process(buffer, sample_hz),
+            DspNode::Adsr(node) => node.process(buffer, sample_hz),
+            DspNode::Svf(node) => node.process(buffer, sample_hz),
+            DspNode::GainPan(node) => node.process(buffer),
+            DspNode::Delay(node) => node.process(buffer),
+            DspNode::Noise(node) => node.process(buffer),
+        }
+    }
+}
+
+/// A parameter smoothed with the default time, see `audio_utils::param`.
+fn smoothed(value: f64, sample_hz: f64) -> Smoothed {
+    Smoothed::new(value, sample_hz, DEFAULT_SMOOTHING_MS, Smoothing::OnePole)
+}
+
+/// Sums its inputs and scales the sum by `volume`. Used as the master node.
+#[derive(Debug)]
+pub struct Mixer {
+    pub volume: Smoothed,
+}
+
+impl Mixer {
+    pub fn new(sample_hz: f64, volume: f64) -> Self {
+        Mixer {
+            volume: smoothed(volume, sample_hz),
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [StereoFrame]) {
+        for frame in buffer.iter_mut() {
+            let volume = self.volume.next() as f32;
+            for sample in frame.iter_mut() {
+                *sample *= volume;
+            }
+        }
+    }
+}
+
+/// A sine FM operator at `freq * ratio` Hz, its audio inputs are ignored.
+///
+/// - `Frequency` modulation of 1.0 deviates the frequency by `index * freq` Hz.
+/// - `Amplitude` modulation scales the volume by `1.0 + modulation`.
+/// - `Phase` modulation is added to the phase, in cycles.
+#[derive(Debug)]
+pub struct Operator {
+    pub phase: f64,
+    pub freq: Smoothed,
+    pub ratio: Smoothed,
+    pub index: Smoothed,
+    pub volume: Smoothed,
+    pub mods: ModInputs,
+}
+
+impl Operator {
+    pub fn new(sample_hz: f64, freq: f64, ratio: f64, index: f64, volume: f64) -> Self {
+        Operator {
+            phase: 0.0,
+            freq: smoothed(freq, sample_hz),
+            ratio: smoothed(ratio, sample_hz),
+            index: smoothed(index, sample_hz),
+            volume: smoothed(volume, sample_hz),
+            mods: ModInputs::default(),
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [StereoFrame], sample_hz: f64) {
+        for (i, frame) in buffer.iter_mut().enumerate() {
+            let (freq, ratio, index) = (self.freq.next(), self.ratio.next(), self.index.next());
+            let phase = self.phase + self.mods.get(ModInput::Phase, i) as f64;
+            let volume = self.volume.next() as f32 * (1.0 + self.mods.get(ModInput::Amplitude, i));
+            let value = (phase * TAU).sin() as f32 * volume;
+            let modulation = self.mods.get(ModInput::Frequency, i) as f64;
+            let freq = freq * ratio + modulation * index * freq;
+            self.phase = (self.phase + freq / sample_hz).rem_euclid(1.0);
+            *frame = [value; CHANNELS];
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// A linear ADSR envelope multiplied with its inputs. Times are in seconds.
+#[derive(Debug)]
+pub struct Adsr {
+    pub attack: f64,
+    pub decay: f64,
+    pub sustain: Smoothed,
+    pub release: f64,
+    stage: Stage,
+    level: f64,
+}
+
+impl Adsr {
+    pub fn new(sample_hz: f64, attack: f64, decay: f64, sustain: f64, release: f64) -> Self {
+        Adsr {
+            attack,
+            decay,
+            sustain: smoothed(sustain, sample_hz),
+            release,
+            stage: Stage::Idle,
+            level: 0.0,
+        }
+    }
+
+    /// Start the attack from the current level.
+    pub fn note_on(&mut self) {
+        self.stage = Stage::Attack;
+    }
+
+    pub fn note_off(&mut self) {
+        if self.stage != Stage::Idle {
+            self.stage = Stage::Release;
+        }
+    }
+
+    fn next_level(&mut self, sample_hz: f64) -> f64 {
+        // Each stage moves with the slope that takes it across the full range in its time.
+        let step = |time: f64| if time > 0.0 { 1.0 / (time * sample_hz) } else { 1.0 };
+        let sustain = self.sustain.next();
+        match self.stage {
+            Stage::Idle => self.level = 0.0,
+            Stage::Attack => {
+                self.level += step(self.attack);
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.level -= step(self.decay) * (1.0 - sustain);
+                if self.level <= sustain {
+                    self.level = sustain;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain => self.level = sustain,
+            Stage::Release => {
+                self.level -= step(self.release);
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.stage = Stage::Idle;
+                }
+            }
+        }
+        self.level
+    }
+
+    fn process(&mut self, buffer: &mut [StereoFrame], sample_hz: f64) {
+        for frame in buffer.iter_mut() {
+            let level = self.next_level(sample_hz) as f32;
+            for sample in frame.iter_mut() {
+                *sample *= level;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum FilterMode {
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+}
+
+/// A state variable filter (the trapezoidal integrated version by Andrew
+/// Simper) with the cutoff in Hz. `Frequency` modulation moves the cutoff,
+/// in octaves.
+#[derive(Debug)]
+pub struct Svf {
+    pub mode: FilterMode,
+    pub cutoff: Smoothed,
+    pub q: Smoothed,
+    pub mods: ModInputs,
+    ic1eq: [f64; CHANNELS],
+    ic2eq: [f64; CHANNELS],
+}
+
+impl Svf {
+    pub fn new(sample_hz: f64, mode: FilterMode, cutoff: f64, q: f64) -> Self {
+        Svf {
+            mode,
+            cutoff: smoothed(cutoff, sample_hz),
+            q: smoothed(q, sample_hz),
+            mods: ModInputs::default(),
+            ic1eq: [0.0; CHANNELS],
+            ic2eq: [0.0; CHANNELS],
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [StereoFrame], sample_hz: f64) {
+        for (i, frame) in buffer.iter_mut().enumerate() {
+            let k = 1.0 / self.q.next();
+            let octaves = self.mods.get(ModInput::Frequency, i) as f64;
+            let cutoff = (self.cutoff.next() * octaves.exp2()).min(sample_hz * 0.49);
+            let g = (PI * cutoff / sample_hz).tan();
+            let a1 = 1.0 / (1.0 + g * (g + k));
+            let a2 = g * a1;
+            let a3 = g * a2;
+            for ch in 0..CHANNELS {
+                let v0 = frame[ch] as f64;
+                let v3 = v0 - self.ic2eq[ch];
+                let v1 = a1 * self.ic1eq[ch] + a2 * v3;
+                let v2 = self.ic2eq[ch] + a2 * self.ic1eq[ch] + a3 * v3;
+                self.ic1eq[ch] = 2.0 * v1 - self.ic1eq[ch];
+                self.ic2eq[ch] = 2.0 * v2 - self.ic2eq[ch];
+                let out = match self.mode {
+                    FilterMode::LowPass => v2,
+                    FilterMode::HighPass => v0 - k * v1 - v2,
+                    FilterMode::BandPass => v1,
+                    FilterMode::Notch => v0 - k * v1,
+                };
+                frame[ch] = out as f32;
+            }
+        }
+    }
+}
+
+/// Gain and equal power panning, `pan` goes from -1.0 (left) to 1.0 (right).
+/// `Amplitude` modulation scales the gain by `1.0 + modulation`.
+#[derive(Debug)]
+pub struct GainPan {
+    pub gain: Smoothed,
+    pub pan: Smoothed,
+    pub mods: ModInputs,
+}
+
+impl GainPan {
+    pub fn new(sample_hz: f64, gain: f64, pan: f64) -> Self {
+        GainPan {
+            gain: smoothed(gain, sample_hz),
+            pan: smoothed(pan, sample_hz),
+            mods: ModInputs::default(),
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [StereoFrame]) {
+        for (i, frame) in buffer.iter_mut().enumerate() {
+            let angle = (self.pan.next().max(-1.0).min(1.0) + 1.0) * PI / 4.0;
+            let gain = self.gain.next() * (1.0 + self.mods.get(ModInput::Amplitude, i) as f64);
+            let gains = [angle.cos() * gain, angle.sin() * gain];
+            for (sample, gain) in frame.iter_mut().zip(gains.iter()) {
+                *sample *= *gain as f32;
+            }
+        }
+    }
+}
+
+/// A feedback delay. The memory is allocated up front for `max_time`
+/// seconds so `time` can be changed without allocating. The delayed signal
+/// is interpolated so `time` can glide.
+#[derive(Debug)]
+pub struct Delay {
+    pub time: Smoothed,
+    pub feedback: Smoothed,
+    pub mix: Smoothed,
+    sample_hz: f64,
+    memory: Vec<StereoFrame>,
+    pos: usize,
+}
+
+impl Delay {
+    pub fn new(sample_hz: f64, max_time: f64, time: f64, feedback: f64, mix: f64) -> Self {
+        Delay {
+            time: smoothed(time, sample_hz),
+            feedback: smoothed(feedback, sample_hz),
+            mix: smoothed(mix, sample_hz),
+            sample_hz,
+            memory: vec![[0.0; CHANNELS]; (max_time * sample_hz) as usize + 2],
+            pos: 0,
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [StereoFrame]) {
+        let len = self.memory.len();
+        for frame in buffer.iter_mut() {
+            let delay = (self.time.next() * self.sample_hz).max(1.0).min((len - 2) as f64);
+            let whole = delay as usize;
+            let frac = (delay - whole as f64) as f32;
+            let a = self.memory[(self.pos + len - whole) % len];
+            let b = self.memory[(self.pos + len - whole - 1) % len];
+            let (feedback, mix) = (self.feedback.next() as f32, self.mix.next() as f32);
+            for ch in 0..CHANNELS {
+                let delayed = a[ch] + (b[ch] - a[ch]) * frac;
+                self.memory[self.pos][ch] = frame[ch] + delayed * feedback;
+                frame[ch] += delayed * mix;
+            }
+            self.pos = (self.pos + 1) % len;
+        }
+    }
+}
+
+/// White noise from a xorshift generator, the same on both channels.
+/// `Amplitude` modulation scales the volume by `1.0 + modulation`.
+#[derive(Debug)]
+pub struct Noise {
+    pub volume: Smoothed,
+    pub mods: ModInputs,
+    state: u32,
+}
+
+impl Noise {
+    pub fn new(sample_hz: f64, volume: f64) -> Self {
+        Noise {
+            volume: smoothed(volume, sample_hz),
+            mods: ModInputs::default(),
+            state: 0x9e37_79b9,
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [StereoFrame]) {
+        for (i, frame) in buffer.iter_mut().enumerate() {
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 17;
+            self.state ^= self.state << 5;
+            let value = (self.state as f32 / std::u32::MAX as f32) * 2.0 - 1.0;
+            let volume = self.volume.next() as f32 * (1.0 + self.mods.get(ModInput::Amplitude, i));
+            *frame = [value * volume; CHANNELS];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modulation::MAX_BLOCK_SIZE;
+
+    const SAMPLE_HZ: f64 = 48000.0;
+
+    /// Render `operator` for `frames`, calling `change` on it halfway.
+    fn render<F: FnOnce(&mut DspNode)>(operator: Operator, frames: usize, change: F) -> Vec<f32> {
+        let mut node = DspNode::Operator(operator);
+        let mut buffer = vec![[0.0; CHANNELS]; frames];
+        let (first, second) = buffer.split_at_mut(frames / 2);
+        for block in first.chunks_mut(MAX_BLOCK_SIZE) {
+            node.audio_requested(block, SAMPLE_HZ);
+        }
+        change(&mut node);
+        for block in second.chunks_mut(MAX_BLOCK_SIZE) {
+            node.audio_requested(block, SAMPLE_HZ);
+        }
+        buffer.iter().map(|frame| frame[0]).collect()
+    }
+
+    fn max_abs(values: impl Iterator<Item = f32>) -> f32 {
+        values.fold(0.0, |max, v| max.max(v.abs()))
+    }
+
+    #[test]
+    fn frequency_change_is_smooth() {
+        let output = render(Operator::new(SAMPLE_HZ, 200.0, 1.0, 0.0, 1.0), 4001, |node| {
+            node.set_param("freq", 800.0);
+        });
+        // The curvature of a sine at 800 Hz. A jump in frequency would add a
+        // jump in slope of up to 2π * 600 / SAMPLE_HZ, about 0.08, at once.
+        let slope = (TAU * 800.0 / SAMPLE_HZ) as f32;
+        let curvature = max_abs(output.windows(3).map(|w| w[2] - 2.0 * w[1] + w[0]));
+        assert!(curvature <= slope * slope * 1.05, "curvature {}", curvature);
+    }
+
+    #[test]
+    fn volume_change_is_smooth() {
+        // Cut the volume at a peak of the sine.
+        let output = render(Operator::new(SAMPLE_HZ, 100.0, 1.0, 0.0, 1.0), 240, |node| {
+            node.set_param("volume", 0.0);
+        });
+        let slope = (TAU * 100.0 / SAMPLE_HZ) as f32;
+        let step = max_abs(output.windows(2).map(|w| w[1] - w[0]));
+        // The sine moves by at most `slope` per sample, the smoothed volume
+        // by at most 1/240 of the jump.
+        assert!(step <= slope + 1.0 / 240.0, "step {}", step);
+    }
+}
@@ -0,0 +1,780 @@
+//! Loading the graph from a patch description.
+//!
+//! A patch is a TOML file listing the nodes with their parameters, the audio
+//! connections between them and the modulation routes:
+//!
+//! ```toml
+//! master = "out"
+//!
+//! [[node]]
+//! name = "out"
+//! type = "mixer"
+//! volume = 0.5
+//!
+//! [[node]]
+//! name = "carrier"
+//! type = "operator"
+//! index = 15.0
+//!
+//! [[node]]
+//! name = "modulator"
+//! type = "operator"
+//! ratio = 2.5
+//!
+//! [[connection]]
+//! from = "carrier"
+//! to = "out"
+//!
+//! [[modulation]]
+//! from = "modulator"
+//! to = "carrier"
+//! input = "frequency"
+//! depth = 1.0
+//! ```
+//!
+//! Nodes that are the `from` of a modulation route are modulation sources and
+//! can't have audio connections. Parameters that are left out get a default.
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::path::Path;
+
+use dsp::{Graph, NodeIndex};
+use serde::Deserialize;
+
+use crate::modulation::{ModError, ModGraph, ModInput, ModTarget, SourceIndex};
+use crate::nodes::{
+    node_type, Adsr, Delay, DspNode, FilterMode, GainPan, Mixer, Noise, Operator, Svf,
+};
+
+/// The parsed patch file. It can be edited and built into a `Patch` again.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PatchDesc {
+    master: String,
+    #[serde(default)]
+    node: Vec<NodeDesc>,
+    #[serde(default)]
+    connection: Vec<ConnectionDesc>,
+    #[serde(default)]
+    modulation: Vec<ModulationDesc>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct NodeDesc {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(flatten)]
+    params: toml::value::Table,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ConnectionDesc {
+    from: String,
+    to: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ModulationDesc {
+    from: String,
+    to: String,
+    input: String,
+    #[serde(default = "default_depth")]
+    depth: f32,
+}
+
+fn default_depth() -> f32 {
+    1.0
+}
+
+#[derive(Debug)]
+pub enum PatchError {
+    Io(String),
+    Parse(String),
+    UnknownNodeType { node: String, kind: String },
+    UnknownParam { node: String, param: String },
+    BadParam { node: String, param: String },
+    DuplicateNode(String),
+    MissingNode(String),
+    MissingConnection { from: String, to: String },
+    UnknownInput(String),
+    UnsupportedInput { node: String, input: String },
+    /// A modulation source that also has audio connections.
+    SourceConnected(String),
+    Cycle { from: String, to: String },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::Io(e) => write!(f, "unable to read the patch: {}", e),
+            PatchError::Parse(e) => write!(f, "unable to parse the patch: {}", e),
+            PatchError::UnknownNodeType { node, kind } => {
+                write!(f, "node \"{}\" has the unknown type \"{}\"", node, kind)
+            }
+            PatchError::UnknownParam { node, param } => {
+                write!(f, "node \"{}\" has no parameter \"{}\"", node, param)
+            }
+            PatchError::BadParam { node, param } => {
+                write!(f, "parameter \"{}\" of node \"{}\" has the wrong type", param, node)
+            }
+            PatchError::DuplicateNode(node) => write!(f, "node \"{}\" is defined twice", node),
+            PatchError::MissingNode(node) => write!(f, "there is no node \"{}\"", node),
+            PatchError::MissingConnection { from, to } => {
+                write!(f, "\"{}\" isn't connected to \"{}\"", from, to)
+            }
+            PatchError::UnknownInput(input) => write!(f, "unknown modulation input \"{}\"", input),
+            PatchError::UnsupportedInput { node, input } => {
+                write!(f, "node \"{}\" has no {} modulation input", node, input)
+            }
+            PatchError::SourceConnected(node) => write!(
+                f,
+                "node \"{}\" is a modulation source and can't have audio connections",
+                node
+            ),
+            PatchError::Cycle { from, to } => {
+                write!(f, "connecting \"{}\" to \"{}\" would create a cycle", from, to)
+            }
+        }
+    }
+}
+
+/// Where a named node of the patch ended up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatchNode {
+    Node(NodeIndex),
+    Source(SourceIndex),
+}
+
+/// A graph built from a patch, with the names of its nodes.
+pub struct Patch {
+    pub graph: ModGraph,
+    names: HashMap<String, PatchNode>,
+}
+
+impl PatchDesc {
+    pub fn load(path: &Path) -> Result<Self, PatchError> {
+        let text = fs::read_to_string(path).map_err(|e| PatchError::Io(e.to_string()))?;
+        PatchDesc::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, PatchError> {
+        toml::from_str(text).map_err(|e| PatchError::Parse(e.to_string()))
+    }
+
+    /// The type of the node called `name`.
+    pub fn node_kind(&self, name: &str) -> Option<&str> {
+        self.node.iter().find(|n| n.name == name).map(|n| n.kind.as_str())
+    }
+
+    /// Add a node without parameters, they get their defaults.
+    pub fn add_node(&mut self, name: &str, kind: &str) -> Result<(), PatchError> {
+        if self.node_kind(name).is_some() {
+            return Err(PatchError::DuplicateNode(name.to_string()));
+        }
+        if node_type(kind).is_none() {
+            return Err(PatchError::UnknownNodeType {
+                node: name.to_string(),
+                kind: kind.to_string(),
+            });
+        }
+        self.node.push(NodeDesc {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            params: toml::value::Table::new(),
+        });
+        Ok(())
+    }
+
+    /// Remove a node together with its connections and modulation routes.
+    pub fn remove_node(&mut self, name: &str) -> Result<(), PatchError> {
+        if self.node_kind(name).is_none() {
+            return Err(PatchError::MissingNode(name.to_string()));
+        }
+        self.node.retain(|n| n.name != name);
+        self.connection.retain(|c| c.from != name && c.to != name);
+        self.modulation.retain(|m| m.from != name && m.to != name);
+        Ok(())
+    }
+
+    /// Connect the output of `from` to the input of `to`. Missing nodes and
+    /// cycles are found when the patch is built.
+    pub fn connect(&mut self, from: &str, to: &str) {
+        self.connection.push(ConnectionDesc {
+            from: from.to_string(),
+            to: to.to_string(),
+        });
+    }
+
+    pub fn disconnect(&mut self, from: &str, to: &str) -> Result<(), PatchError> {
+        let len = self.connection.len();
+        self.connection.retain(|c| c.from != from || c.to != to);
+        if self.connection.len() == len {
+            return Err(PatchError::MissingConnection {
+                from: from.to_string(),
+                to: to.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn set_param(&mut self, node: &str, param: &str, value: toml::Value) -> Result<(), PatchError> {
+        let desc = match self.node.iter_mut().find(|n| n.name == node) {
+            Some(desc) => desc,
+            None => return Err(PatchError::MissingNode(node.to_string())),
+        };
+        let known = node_type(&desc.kind).map_or(false, |t| t.params.contains(&param));
+        if !known {
+            return Err(PatchError::UnknownParam {
+                node: node.to_string(),
+                param: param.to_string(),
+            });
+        }
+        desc.params.insert(param.to_string(), value);
+        Ok(())
+    }
+
+    /// Build the graph, checking the description on the way.
+    pub fn build(&self, sample_hz: f64) -> Result<Patch, PatchError> {
+        let sources: Vec<&str> = self.modulation.iter().map(|m| m.from.as_str()).collect();
+        let mut graph = ModGraph::new(Graph::new());
+        let mut names = HashMap::new();
+        for node in &self.node {
+            if names.contains_key(&node.name) {
+                return Err(PatchError::DuplicateNode(node.name.clone()));
+            }
+            let dsp_node = build_node(node, sample_hz)?;
+            let index = if sources.contains(&node.name.as_str()) {
+                PatchNode::Source(graph.add_source(dsp_node))
+            } else {
+                PatchNode::Node(graph.graph.add_node(dsp_node))
+            };
+            names.insert(node.name.clone(), index);
+        }
+
+        let find = |names: &HashMap<String, PatchNode>, name: &str| {
+            names
+                .get(name)
+                .cloned()
+                .ok_or_else(|| PatchError::MissingNode(name.to_string()))
+        };
+        for connection in &self.connection {
+            let from = find(&names, &connection.from)?;
+            let to = find(&names, &connection.to)?;
+            match (from, to) {
+                (PatchNode::Node(from), PatchNode::Node(to)) => {
+                    if graph.graph.add_connection(from, to).is_err() {
+                        return Err(PatchError::Cycle {
+                            from: connection.from.clone(),
+                            to: connection.to.clone(),
+                        });
+                    }
+                }
+                (PatchNode::Source(_), _) => {
+                    return Err(PatchError::SourceConnected(connection.from.clone()))
+                }
+                (_, PatchNode::Source(_)) => {
+                    return Err(PatchError::SourceConnected(connection.to.clone()))
+                }
+            }
+        }
+        match find(&names, &self.master)? {
+            PatchNode::Node(master) => graph.graph.set_master(Some(master)),
+            PatchNode::Source(_) => return Err(PatchError::SourceConnected(self.master.clone())),
+        }
+
+        for modulation in &self.modulation {
+            let source = match find(&names, &modulation.from)? {
+                PatchNode::Source(source) => source,
+                PatchNode::Node(_) => unreachable!("every modulation source was added as a source"),
+            };
+            let target = match find(&names, &modulation.to)? {
+                PatchNode::Node(node) => ModTarget::Node(node),
+                PatchNode::Source(source) => ModTarget::Source(source),
+            };
+            let input = parse_input(&modulation.input)?;
+            match graph.add_modulation(source, target, input, modulation.depth) {
+                Ok(()) => (),
+                Err(ModError::WouldCycle) => {
+                    return Err(PatchError::Cycle {
+                        from: modulation.from.clone(),
+                        to: modulation.to.clone(),
+                    })
+                }
+                Err(ModError::UnsupportedInput(_)) => {
+                    return Err(PatchError::UnsupportedInput {
+                        node: modulation.to.clone(),
+                        input: modulation.input.clone(),
+                    })
+                }
+            }
+        }
+
+        Ok(Patch { graph, names })
+    }
+}
+
+impl Patch {
+    /// Build the graph described by the TOML in `text`.
+    pub fn parse(text: &str, sample_hz: f64) -> Result<Self, PatchError> {
+        PatchDesc::parse(text)?.build(sample_hz)
+    }
+
+    pub fn names(&self) -> &HashMap<String, PatchNode> {
+        &self.names
+    }
+
+    pub fn node_at_mut(&mut self, node: PatchNode) -> Option<&mut DspNode> {
+        node_in(&mut self.graph, node)
+    }
+
+    /// Take over the state (phases, envelopes, delay memory..) of the nodes
+    /// in `old` with the same name and type, except for `except`. The nodes
+    /// are swapped, so this doesn't allocate. Their modulation inputs are
+    /// cleared, an input whose route was removed would otherwise keep adding
+    /// the last block of the modulator.
+    pub fn keep_state_from(&mut self, old: &mut Patch, except: Option<PatchNode>) {
+        for (name, &node) in self.names.iter() {
+            if except == Some(node) {
+                continue;
+            }
+            let old_node = match old.names.get(name) {
+                Some(&old_node) => old_node,
+                None => continue,
+            };
+            if let (Some(new_node), Some(old_node)) =
+                (node_in(&mut self.graph, node), node_in(&mut old.graph, old_node))
+            {
+                if mem::discriminant(new_node) == mem::discriminant(old_node) {
+                    mem::swap(new_node, old_node);
+                    if let Some(mods) = new_node.mod_inputs_mut() {
+                        mods.clear_all();
+                    }
+                }
+            }
+        }
+    }
+
+    /// The node called `name`, in the graph or among the modulation sources.
+    pub fn node_mut(&mut self, name: &str) -> Option<&mut DspNode> {
+        let node = *self.names.get(name)?;
+        node_in(&mut self.graph, node)
+    }
+
+    /// Call `f` with every node of the patch.
+    pub fn for_each_node_mut<F: FnMut(&mut DspNode)>(&mut self, mut f: F) {
+        for &node in self.names.values() {
+            if let Some(node) = node_in(&mut self.graph, node) {
+                f(node);
+            }
+        }
+    }
+}
+
+fn node_in(graph: &mut ModGraph, node: PatchNode) -> Option<&mut DspNode> {
+    match node {
+        PatchNode::Node(node) => graph.graph.node_mut(node),
+        PatchNode::Source(source) => Some(graph.source_mut(source)),
+    }
+}
+
+fn parse_input(input: &str) -> Result<ModInput, PatchError> {
+    match input {
+        "frequency" => Ok(ModInput::Frequency),
+        "amplitude" => Ok(ModInput::Amplitude),
+        "phase" => Ok(ModInput::Phase),
+        _ => Err(PatchError::UnknownInput(input.to_string())),
+    }
+}
+
+/// Reads the parameters of a node description, checking their names and types.
+struct Params<'a> {
+    node: &'a NodeDesc,
+}
+
+impl<'a> Params<'a> {
+    fn new(node: &'a NodeDesc) -> Result<Self, PatchError> {
+        let allowed = match node_type(&node.kind) {
+            Some(node_type) => node_type.params,
+            None => {
+                return Err(PatchError::UnknownNodeType {
+                    node: node.name.clone(),
+                    kind: node.kind.clone(),
+                })
+            }
+        };
+        for param in node.params.keys() {
+            if !allowed.contains(&param.as_str()) {
+                return Err(PatchError::UnknownParam {
+                    node: node.name.clone(),
+                    param: param.clone(),
+                });
+            }
+        }
+        Ok(Params { node })
+    }
+
+    fn bad_param(&self, param: &str) -> PatchError {
+        PatchError::BadParam {
+            node: self.node.name.clone(),
+            param: param.to_string(),
+        }
+    }
+
+    fn f64(&self, param: &str, default: f64) -> Result<f64, PatchError> {
+        match self.node.params.get(param) {
+            None => Ok(default),
+            Some(toml::Value::Float(value)) => Ok(*value),
+            Some(toml::Value::Integer(value)) => Ok(*value as f64),
+            Some(_) => Err(self.bad_param(param)),
+        }
+    }
+
+    fn str(&self, param: &str, default: &'a str) -> Result<&'a str, PatchError> {
+        match self.node.params.get(param) {
+            None => Ok(default),
+            Some(toml::Value::String(value)) => Ok(value),
+            Some(_) => Err(self.bad_param(param)),
+        }
+    }
+}
+
+fn build_node(node: &NodeDesc, sample_hz: f64) -> Result<DspNode, PatchError> {
+    let p = Params::new(node)?;
+    let dsp_node = match node.kind.as_str() {
+        "mixer" => DspNode::Mixer(Mixer::new(sample_hz, p.f64("volume", 1.0)?)),
+        "operator" => DspNode::Operator(Operator::new(
+            sample_hz,
+            p.f64("freq", 220.0)?,
+            p.f64("ratio", 1.0)?,
+            p.f64("index", 0.0)?,
+            p.f64("volume", 1.0)?,
+        )),
+        "adsr" => DspNode::Adsr(Adsr::new(
+            sample_hz,
+            p.f64("attack", 0.01)?,
+            p.f64("decay", 0.1)?,
+            p.f64("sustain", 1.0)?,
+            p.f64("release", 0.1)?,
+        )),
+        "svf" => {
+            let mode = match p.str("mode", "lowpass")? {
+                "lowpass" => FilterMode::LowPass,
+                "highpass" => FilterMode::HighPass,
+                "bandpass" => FilterMode::BandPass,
+                "notch" => FilterMode::Notch,
+                _ => return Err(p.bad_param("mode")),
+            };
+            DspNode::Svf(Svf::new(sample_hz, mode, p.f64("cutoff", 1000.0)?, p.f64("q", 0.707)?))
+        }
+        "gain_pan" => DspNode::GainPan(GainPan::new(sample_hz, p.f64("gain", 1.0)?, p.f64("pan", 0.0)?)),
+        "delay" => DspNode::Delay(Delay::new(
+            sample_hz,
+            p.f64("max_time", 1.0)?,
+            p.f64("time", 0.25)?,
+            p.f64("feedback", 0.3)?,
+            p.f64("mix", 0.25)?,
+        )),
+        "noise" => DspNode::Noise(Noise::new(sample_hz, p.f64("volume", 1.0)?)),
+        _ => unreachable!("the node type is checked by Params::new"),
+    };
+    Ok(dsp_node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modulation::MAX_BLOCK_SIZE;
+
+    const SAMPLE_HZ: f64 = 44100.0;
+
+    fn expect_cycle(text: &str, from: &str, to: &str) {
+        match Patch::parse(text, SAMPLE_HZ) {
+            Err(PatchError::Cycle { from: f, to: t }) => {
+                assert_eq!((f.as_str(), t.as_str()), (from, to));
+            }
+            Err(e) => panic!("expected a cycle, got: {}", e),
+            Ok(_) => panic!("expected a cycle, the patch was built"),
+        }
+    }
+
+    fn expect_error(text: &str) -> PatchError {
+        match Patch::parse(text, SAMPLE_HZ) {
+            Err(e) => e,
+            Ok(_) => panic!("expected an error, the patch was built"),
+        }
+    }
+
+    fn expect_missing_node(text: &str, node: &str) {
+        match expect_error(text) {
+            PatchError::MissingNode(n) => assert_eq!(n, node),
+            e => panic!("expected a missing node, got: {}", e),
+        }
+    }
+
+    #[test]
+    fn removed_route_stops_modulating() {
+        let text = r#"
+            master = "out"
+
+            [[node]]
+            name = "out"
+            type = "mixer"
+
+            [[node]]
+            name = "carrier"
+            type = "operator"
+
+            [[node]]
+            name = "modulator"
+            type = "operator"
+
+            [[connection]]
+            from = "carrier"
+            to = "out"
+
+            [[modulation]]
+            from = "modulator"
+            to = "carrier"
+            input = "frequency"
+        "#;
+        let mut desc = PatchDesc::parse(text).unwrap();
+        let mut old = desc.build(SAMPLE_HZ).unwrap();
+        let mut buffer = vec![[0.0; 2]; 256];
+        old.graph.audio_requested(&mut buffer, SAMPLE_HZ);
+
+        desc.remove_node("modulator").unwrap();
+        let mut patch = desc.build(SAMPLE_HZ).unwrap();
+        patch.keep_state_from(&mut old, None);
+        patch.graph.audio_requested(&mut buffer, SAMPLE_HZ);
+        let mods = patch.node_mut("carrier").unwrap().mod_inputs_mut().unwrap();
+        for &input in &[ModInput::Frequency, ModInput::Amplitude, ModInput::Phase] {
+            assert!((0..MAX_BLOCK_SIZE).all(|frame| mods.get(input, frame) == 0.0));
+        }
+    }
+
+    #[test]
+    fn unknown_node_type_is_rejected() {
+        let text = r#"
+            master = "out"
+
+            [[node]]
+            name = "out"
+            type = "reverb"
+        "#;
+        match expect_error(text) {
+            PatchError::UnknownNodeType { node, kind } => {
+                assert_eq!((node.as_str(), kind.as_str()), ("out", "reverb"));
+            }
+            e => panic!("expected an unknown node type, got: {}", e),
+        }
+    }
+
+    #[test]
+    fn unknown_param_is_rejected() {
+        let text = r#"
+            master = "out"
+
+            [[node]]
+            name = "out"
+            type = "mixer"
+            cutoff = 500.0
+        "#;
+        match expect_error(text) {
+            PatchError::UnknownParam { node, param } => {
+                assert_eq!((node.as_str(), param.as_str()), ("out", "cutoff"));
+            }
+            e => panic!("expected an unknown parameter, got: {}", e),
+        }
+    }
+
+    #[test]
+    fn missing_connection_node_is_rejected() {
+        let text = r#"
+            master = "out"
+
+            [[node]]
+            name = "out"
+            type = "mixer"
+
+            [[connection]]
+            from = "osc"
+            to = "out"
+        "#;
+        expect_missing_node(text, "osc");
+    }
+
+    #[test]
+    fn missing_master_is_rejected() {
+        let text = r#"
+            master = "main"
+
+            [[node]]
+            name = "out"
+            type = "mixer"
+        "#;
+        expect_missing_node(text, "main");
+    }
+
+    #[test]
+    fn missing_modulation_node_is_rejected() {
+        let text = r#"
+            master = "out"
+
+            [[node]]
+            name = "out"
+            type = "mixer"
+
+            [[node]]
+            name = "modulator"
+            type = "operator"
+
+            [[modulation]]
+            from = "modulator"
+            to = "carrier"
+            input = "frequency"
+        "#;
+        expect_missing_node(text, "carrier");
+    }
+
+    #[test]
+    fn connection_cycle_is_rejected() {
+        let text = r#"
+            master = "out"
+
+            [[node]]
+            name = "out"
+            type = "mixer"
+
+            [[node]]
+            name = "osc"
+            type = "operator"
+
+            [[connection]]
+            from = "osc"
+            to = "out"
+
+            [[connection]]
+            from = "out"
+            to = "osc"
+        "#;
+        expect_cycle(text, "out", "osc");
+    }
+
+    #[test]
+    fn modulation_cycle_is_rejected() {
+        let text = r#"
+            master = "out"
+
+            [[node]]
+            name = "out"
+            type = "mixer"
+
+            [[node]]
+            name = "carrier"
+            type = "operator"
+
+            [[node]]
+            name = "a"
+            type = "operator"
+
+            [[node]]
+            name = "b"
+            type = "operator"
+
+            [[connection]]
+            from = "carrier"
+            to = "out"
+
+            [[modulation]]
+            from = "a"
+            to = "carrier"
+            input = "frequency"
+
+            [[modulation]]
+            from = "a"
+            to = "b"
+            input = "frequency"
+
+            [[modulation]]
+            from = "b"
+            to = "a"
+            input = "phase"
+        "#;
+        expect_cycle(text, "b", "a");
+    }
+
+    #[test]
+    fn acyclic_patches_are_built() {
+        let text = r#"
+            master = "out"
+
+            [[node]]
+            name = "out"
+            type = "mixer"
+
+            [[node]]
+            name = "filter"
+            type = "svf"
+
+            [[node]]
+            name = "carrier"
+            type = "operator"
+
+            [[node]]
+            name = "noise"
+            type = "noise"
+
+            [[node]]
+            name = "a"
+            type = "operator"
+
+            [[node]]
+            name = "b"
+            type = "operator"
+
+            [[connection]]
+            from = "carrier"
+            to = "filter"
+
+            [[connection]]
+            from = "noise"
+            to = "filter"
+
+            [[connection]]
+            from = "filter"
+            to = "out"
+
+            [[connection]]
+            from = "carrier"
+            to = "out"
+
+            [[modulation]]
+            from = "a"
+            to = "b"
+            input = "frequency"
+
+            [[modulation]]
+            from = "a"
+            to = "carrier"
+            input = "amplitude"
+
+            [[modulation]]
+            from = "b"
+            to = "carrier"
+            input = "frequency"
+
+            [[modulation]]
+            from = "b"
+            to = "filter"
+            input = "frequency"
+        "#;
+        if let Err(e) = Patch::parse(text, SAMPLE_HZ) {
+            panic!("{}", e);
+        }
+        if let Err(e) = Patch::parse(include_str!("../patches/fm.toml"), SAMPLE_HZ) {
+            panic!("{}", e);
+        }
+    }
+}
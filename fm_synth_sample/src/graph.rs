@@ -0,0 +1,124 @@
+//! A small builder for FM signal graphs on top of the `sample` crate.
+//!
+//! Instead of writing out the nested signal type by hand, a graph is described
+//! with `Node`s and turned into a boxed signal with `build`:
+//!
+//! ```ignore
+//! let freq = param(&freq_param);
+//! let carrier = osc(freq.clone()).fm(osc(freq.clone() * 2.5) * freq * 6.0);
+//! let signal = carrier.build(48_000.0, 5.0);
+//! ```
+//!
+//! `Node`s are plain values, so a graph can be put together and changed at
+//! runtime and built again.
+use std::ops::{Add, Mul};
+
+use audio_utils::param::Param;
+use sample::{signal, Signal};
+
+/// A mono `sample` signal that can be moved to the audio thread.
+pub type MonoSignal = Box<dyn Signal<Frame = [f64; 1]> + Send>;
+
+/// The description of a signal graph, see the module docs.
+#[derive(Clone, Debug)]
+pub enum Node {
+    /// A constant value.
+    Const(f64),
+    /// The smoothed value of a `Param`, it can be changed from any thread.
+    Param(Param),
+    /// A sine oscillator at frequency `freq` plus the output of `fm`.
+    Osc { freq: Box<Node>, fm: Option<Box<Node>> },
+    Add(Box<Node>, Box<Node>),
+    Mul(Box<Node>, Box<Node>),
+}
+
+/// A sine oscillator at `freq` Hz.
+pub fn osc<N: Into<Node>>(freq: N) -> Node {
+    Node::Osc {
+        freq: Box::new(freq.into()),
+        fm: None,
+    }
+}
+
+/// A node following the value of `param`.
+pub fn param(param: &Param) -> Node {
+    Node::Param(param.clone())
+}
+
+impl Node {
+    /// Modulate the frequency of this oscillator with `modulator`, in Hz.
+    /// Several calls add up. On nodes that aren't oscillators the modulator
+    /// is added to the output.
+    pub fn fm<N: Into<Node>>(self, modulator: N) -> Node {
+        let modulator = modulator.into();
+        match self {
+            Node::Osc { freq, fm } => Node::Osc {
+                freq,
+                fm: Some(Box::new(match fm {
+                    Some(fm) => *fm + modulator,
+                    None => modulator,
+                })),
+            },
+            node => node + modulator,
+        }
+    }
+
+    /// Create the signal for this graph. Every `Param` node gets its own
+    /// reader smoothed over `smoothing_ms`.
+    pub fn build(&self, sample_rate: f64, smoothing_ms: f64) -> MonoSignal {
+        match self {
+            Node::Const(value) => {
+                let value = *value;
+                Box::new(signal::gen(move || [value]))
+            }
+            Node::Param(param) => {
+                let mut param = param.smoothed(sample_rate, smoothing_ms);
+                Box::new(signal::gen_mut(move || [param.next()]))
+            }
+            Node::Osc { freq, fm } => {
+                let freq = freq.build(sample_rate, smoothing_ms);
+                match fm {
+                    Some(fm) => {
+                        let freq = freq.add_amp(fm.build(sample_rate, smoothing_ms));
+                        Box::new(signal::rate(sample_rate).hz(freq).sine())
+                    }
+                    None => Box::new(signal::rate(sample_rate).hz(freq).sine()),
+                }
+            }
+            Node::Add(a, b) => Box::new(
+                a.build(sample_rate, smoothing_ms)
+                    .add_amp(b.build(sample_rate, smoothing_ms)),
+            ),
+            Node::Mul(a, b) => Box::new(
+                a.build(sample_rate, smoothing_ms)
+                    .mul_amp(b.build(sample_rate, smoothing_ms)),
+            ),
+        }
+    }
+}
+
+impl From<f64> for Node {
+    fn from(value: f64) -> Self {
+        Node::Const(value)
+    }
+}
+
+impl From<&Param> for Node {
+    fn from(p: &Param) -> Self {
+        param(p)
+    }
+}
+
+impl<N: Into<Node>> Add<N> for Node {
+    type Output = Node;
+    fn add(self, other: N) -> Node {
+        Node::Add(Box::new(self), Box::new(other.into()))
+    }
+}
+
+impl<N: Into<Node>> Mul<N> for Node {
+    type Output = Node;
+    fn mul(self, other: N) -> Node {
+        Node::Mul(Box::new(self), Box::new(other.into()))
+    }
+}
@@ -1,203 +1,202 @@
 //! Sine wave generator with frequency configuration exposed through standard
 //! input.
 extern crate crossbeam_channel;
-extern crate jack;
 extern crate sample;
-
-use crossbeam_channel::bounded;
+extern crate audio_utils;
+
+mod graph;
+
+use audio_utils::args;
+use audio_utils::backend::{self, AnyBackend, AudioBackend, BackendKind, ProcessInfo};
+use audio_utils::param::{Param, Smoothed, Smoothing, DEFAULT_SMOOTHING_MS};
+use audio_utils::render::EventQueue;
+use audio_utils::rt_audit;
+use audio_utils::transport::BeatClock;
+use crossbeam_channel::{bounded, Receiver};
+use graph::{osc, param, MonoSignal};
 use std::io;
 use std::str::FromStr;
-use std::rc::Rc;
-use std::sync::{Arc, Mutex};
-use std::cell::Cell;
-
-use sample::{signal, Signal};
-use sample::frame::Frame;
-
-// struct FMSynth {
-//     sample_rate: f64,
-//     freq: Arc<Mutex<f64>>,
-//     m_ratio: f64,
-//     c_ratio: f64,
-//     m_index: f64,
-//     // modulator: Box<dyn Signal<Frame = sample::frame::Mono<f64>>>,
-//     carrier: Box<dyn Signal<Frame = sample::frame::Mono<f64>> + Send + Sync>,
-//     amp: f64,
-// }
-
-// impl FMSynth {
-//     fn new(sample_rate: f64, freq: f64, amp: f64, m_ratio: f64, c_ratio: f64, m_index: f64) -> Self {
-//         let sync_freq = Arc::new(Mutex::new(freq));
-//         let freq_sig1 = signal::gen(move || [*sync_freq.lock().unwrap()]);
-//         let freq_sig2 = signal::gen(move || [*sync_freq.lock().unwrap()]);
-//         let freq_sig3 = signal::gen(move || [*sync_freq.lock().unwrap()]);
-//         let index_sig = signal::gen(move || [m_index]);
-//         let mut mod_freq = signal::gen(move || [m_ratio]).mul_amp(freq_sig1);
-//         let mut modulator = signal::rate(sample_rate as f64).hz(mod_freq).sine().mul_amp(freq_sig2).mul_amp(index_sig);
-//         let mut car_freq = signal::gen(move || [c_ratio]).mul_amp(freq_sig3).add_amp(modulator);
-//         let mut carrier = signal::rate(sample_rate as f64).hz(car_freq).sine();
-
-//         let mut synth = FMSynth {
-//             sample_rate,
-//             freq: sync_freq,
-//             m_ratio,
-//             c_ratio,
-//             m_index,
-//             // modulator: Box::new(modulator), // cannot be used here because the modulator is moved
-//             carrier: Box::new(carrier),
-//             amp,
-//         };
-//         synth
-//     }
-//     fn next_stereo(&mut self) -> [f64; 2] {
-//         let sample = self.carrier.next();
-        
-//         [sample[0], sample[0]]
-//     }
-//     fn set_freq(&mut self, freq: f64) {
-//         *self.freq.lock().unwrap() = freq;
-//     }
-//     fn control_rate_update(&mut self) {
-//         self.amp *= 0.98;
-//     }
-//     fn trigger(&mut self, freq: f64) {
-//         // Set the new frequency
-//         *self.freq.lock().unwrap() = freq;
-//         // Setting the amplitude triggers an attack
-//         self.amp = 0.5;
-//         // Reset all phases
-//         // self.lfo_phase = 0.0; // You may or may not want to reset the lfo phase based on how you use it
-//     }
-// }
 
-fn main() {
-    // 1. open a client
-    let (client, _status) =
-        jack::Client::new("rust_jack_fm", jack::ClientOptions::NO_START_SERVER).unwrap();
-
-    // 2. register port
-    let mut out_port_l = client
-        .register_port("out_l", jack::AudioOut::default())
-        .unwrap();
-    let mut out_port_r = client
-        .register_port("out_r", jack::AudioOut::default())
-        .unwrap();
-
-    // Get the system ports
-    let ports = client.ports(Some("system:playback_.*"), None, jack::PortFlags::empty());
-
-    // 3. define process callback handler
-    let mut frequency = 220.0;
-    let sample_rate = client.sample_rate();
-    let frame_t = 1.0 / sample_rate as f64;
-    let mut time = 0.0;
-    let (tx, rx) = bounded::<[f64; 4]>(1_000_000);
+use sample::Signal;
 
-    // FMSynth setup
-    // let mut fm_synth = FMSynth::new(sample_rate as f64, frequency, 1.0, 2.0, 1.0, 4.0);
-    let m_ratio = 2.5;
-    let c_ratio = 1.0;
-    let m_index = 6.0;
-    // Question: How can I use one freq_sig that I can later change to influence all the places where it should be?
-    // If I try to do that now I get a "use of moved value" error
-    let sync_freq1 = Arc::new(Mutex::new(frequency));
-    let sync_freq2 = Arc::clone(&sync_freq1);
-    let sync_freq3 = Arc::clone(&sync_freq1);
-    let freq_param = Arc::clone(&sync_freq1);
-    let freq_sig1 = signal::gen(move || [*sync_freq1.lock().unwrap()]);
-    let freq_sig2 = signal::gen(move || [*sync_freq2.lock().unwrap()]);
-    let freq_sig3 = signal::gen(move || [*sync_freq3.lock().unwrap()]);
-    let index_sig = signal::gen(move || [m_index]);
-    let mut mod_freq = signal::gen(move || [m_ratio]).mul_amp(freq_sig1);
-    let mut modulator = signal::rate(sample_rate as f64).hz(mod_freq).sine().mul_amp(freq_sig2).mul_amp(index_sig);
-    let mut car_freq = signal::gen(move || [c_ratio]).mul_amp(freq_sig3).add_amp(modulator);
-    let mut carrier = signal::rate(sample_rate as f64).hz(car_freq).sine();
+/// Counts the allocations made in the process callback.
+#[cfg(feature = "rt_audit")]
+#[global_allocator]
+static ALLOCATOR: rt_audit::CountingAllocator = rt_audit::CountingAllocator;
 
-    let mut counter = 0;
+/// How long the attack of a trigger ramps up, short enough to stay percussive.
+const ATTACK_MS: f64 = 2.0;
 
-    // Trying to use the FMSynth struct in the jack client process results in an error:
-    //     |
-    // 155 |     let active_client = client.activate_async((), process).unwrap();
-    // |                                ^^^^^^^^^^^^^^ `(dyn sample::Signal<Frame = [f64; 1]> + 'static)` cannot be shared between threads safely
-    // |
-    // = help: the trait `std::marker::Sync` is not implemented for `(dyn sample::Signal<Frame = [f64; 1]> + 'static)`
-    // = note: required because of the requirements on the impl of `std::marker::Sync` for `std::ptr::Unique<(dyn sample::Signal<Frame = [f64; 1]> + 'static)>`
-    // = note: required because it appears within the type `std::boxed::Box<(dyn sample::Signal<Frame = [f64; 1]> + 'static)>`
-    // = note: required because it appears within the type `FMSynth`
-    // = note: required because it appears within the type `[closure@fm_synth_sample/src/main.rs:111:9: 151:10 out_port_l:jack::Port<jack::AudioOut>, out_port_r:jack::Port<jack::AudioOut>, rx:crossbeam_channel::Receiver<[f64; 4]>, time:f64, frequency:f64, carrier:sample::signal::Sine<sample::signal::Hz<sample::signal::AddAmp<sample::signal::MulAmp<sample::signal::Gen<[closure@fm_synth_sample/src/main.rs:105:36: 105:53 c_ratio:f64], [f64; 1]>, sample::signal::Gen<[closure@fm_synth_sample/src/main.rs:101:33: 101:52 frequency:f64], [f64; 1]>>, sample::signal::MulAmp<sample::signal::MulAmp<sample::signal::Sine<sample::signal::Hz<sample::signal::MulAmp<sample::signal::Gen<[closure@fm_synth_sample/src/main.rs:103:36: 103:53 m_ratio:f64], [f64; 1]>, sample::signal::Gen<[closure@fm_synth_sample/src/main.rs:100:33: 100:52 frequency:f64], [f64; 1]>>>>, sample::signal::Gen<[closure@fm_synth_sample/src/main.rs:100:33: 100:52 frequency:f64], [f64; 1]>>, sample::signal::Gen<[closure@fm_synth_sample/src/main.rs:102:33: 102:50 m_index:f64], [f64; 1]>>>>>, fm_synth:FMSynth, frame_t:f64, counter:usize]`
-    // = note: required because it appears within the type `jack::ClosureProcessHandler<[closure@fm_synth_sample/src/main.rs:111:9: 151:10 out_port_l:jack::Port<jack::AudioOut>, out_port_r:jack::Port<jack::AudioOut>, rx:crossbeam_channel::Receiver<[f64; 4]>, time:f64, frequency:f64, carrier:sample::signal::Sine<sample::signal::Hz<sample::signal::AddAmp<sample::signal::MulAmp<sample::signal::Gen<[closure@fm_synth_sample/src/main.rs:105:36: 105:53 c_ratio:f64], [f64; 1]>, sample::signal::Gen<[closure@fm_synth_sample/src/main.rs:101:33: 101:52 frequency:f64], [f64; 1]>>, sample::signal::MulAmp<sample::signal::MulAmp<sample::signal::Sine<sample::signal::Hz<sample::signal::MulAmp<sample::signal::Gen<[closure@fm_synth_sample/src/main.rs:103:36: 103:53 m_ratio:f64], [f64; 1]>, sample::signal::Gen<[closure@fm_synth_sample/src/main.rs:100:33: 100:52 frequency:f64], [f64; 1]>>>>, sample::signal::Gen<[closure@fm_synth_sample/src/main.rs:100:33: 100:52 frequency:f64], [f64; 1]>>, sample::signal::Gen<[closure@fm_synth_sample/src/main.rs:102:33: 102:50 m_index:f64], [f64; 1]>>>>>, fm_synth:FMSynth, frame_t:f64, counter:usize]>`
-    let process = jack::ClosureProcessHandler::new(
-        move |_: &jack::Client, ps: &jack::ProcessScope| -> jack::Control {
-            // Get output buffer
-            let out_l = out_port_l.as_mut_slice(ps);
-            let out_r = out_port_r.as_mut_slice(ps);
-
-            // Check frequency requests
-            while let Ok(f) = rx.try_recv() {
-                time = 0.0;
-                frequency = f[0];
-                *freq_param.lock().unwrap() = f[0];
-                // Question: How can I change the parameters of the synth here, e.g. frequency?
-                // fm_synth.set_freq(f[0]);
-                // fm_synth.c_ratio = f[1];
-                // fm_synth.m_ratio = f[2];
-                // fm_synth.lfo_freq = f[3];
-                // fm_synth.trigger(f[0]);
-            }
+/// Handles to the parameters of an `FMSynth`. They can be cloned and set from
+/// any thread while the synth is running.
+#[derive(Clone)]
+struct FMParams {
+    freq: Param,
+    m_ratio: Param,
+    c_ratio: Param,
+    m_index: Param,
+}
 
-            // Write output
-            for (l, r) in out_l.iter_mut().zip(out_r.iter_mut()) {
-                let frame = carrier.next();
-                // let frame = fm_synth.next_stereo();
-                *l = frame[0] as f32;
-                *r = frame[0] as f32;
-                time += frame_t;
-            }
+struct FMSynth {
+    params: FMParams,
+    carrier: MonoSignal,
+    amp: Smoothed,
+}
 
-            // fm_synth.control_rate_update();
+impl FMSynth {
+    fn new(sample_rate: f64, freq: f64, amp: f64, m_ratio: f64, c_ratio: f64, m_index: f64) -> Self {
+        let params = FMParams {
+            freq: Param::new(freq),
+            m_ratio: Param::new(m_ratio),
+            c_ratio: Param::new(c_ratio),
+            m_index: Param::new(m_index),
+        };
+        let freq = param(&params.freq);
+        let modulator = osc(freq.clone() * &params.m_ratio) * freq.clone() * &params.m_index;
+        let carrier = osc(freq * &params.c_ratio).fm(modulator);
+
+        FMSynth {
+            params,
+            carrier: carrier.build(sample_rate, DEFAULT_SMOOTHING_MS),
+            amp: Smoothed::new(amp, sample_rate, ATTACK_MS, Smoothing::Linear),
+        }
+    }
+    fn params(&self) -> FMParams {
+        self.params.clone()
+    }
+    fn next_stereo(&mut self) -> [f64; 2] {
+        let sample = self.carrier.next()[0] * self.amp.next();
+
+        [sample, sample]
+    }
+    fn control_rate_update(&mut self) {
+        let amp = self.amp.target() * 0.98;
+        self.amp.set(amp);
+    }
+    fn trigger(&mut self, freq: f64) {
+        // Set the new frequency
+        self.params.freq.set(freq);
+        // Setting the amplitude triggers an attack
+        self.amp.set(0.5);
+        // The phases live inside the `sample` signals and can't be reset.
+    }
+}
 
-            // Trigger based on counter
-            const COUNTER_STEP: usize = 80;
-            for i in 0..16 {
-                if counter == i * COUNTER_STEP {
-                    // fm_synth.trigger(frequency)
-                }
+fn main() {
+    // Reports on the process callback when main returns, with the rt_audit feature.
+    let _audit = rt_audit::Monitor::start();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let backend_kind = match BackendKind::from_args(&mut args) {
+        Ok(kind) => kind,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    if let Err(e) = args::finish(&args, &backend::usage()) {
+        println!("{}", e);
+        return;
+    }
+    // Timed messages from the events file are only used when rendering.
+    let mut events = match &backend_kind {
+        BackendKind::Wav(settings) => match EventQueue::load(settings, parse_freq) {
+            Ok(events) => events,
+            Err(e) => {
+                println!("{}", e);
+                return;
             }
+        },
+        _ => EventQueue::new(vec![]),
+    };
 
-            counter = (counter+1) % (COUNTER_STEP*16);
+    // 1. open the backend, for JACK this opens a client and registers the ports.
+    // The ports are connected according to the --connect rules once it is started.
+    let backend = AnyBackend::open(backend_kind, "rust_jack_fm", &["out_l", "out_r"]).unwrap();
+    let is_realtime = backend.is_realtime();
 
-            // Continue as normal
-            jack::Control::Continue
-        },
-    );
-
-    // 4. activate the client
-    let active_client = client.activate_async((), process).unwrap();
-    // processing starts here
-
-    // Connect the client to the system outputs automatically.
-    // It seems like this has to be done after the client is activated, doing it just after creating the ports doesn't work.
-    // TODO: Get the local port names automatically from the client or by putting the client and port names in variables.
-    let res = active_client.as_client().connect_ports_by_name("rust_jack_fm:out_l", &ports[0]);
-    match res {
-        Ok(_) => (),
-        Err(e) => println!("Unable to connect to port {} with error {}", ports[0], e),
-    }
-    match active_client.as_client().connect_ports_by_name("rust_jack_fm:out_r", &ports[1]) {
-        Ok(_) => (),
-        Err(e) => println!("Unable to connect to port {} with error {}", ports[1], e),
+    // 2. define the process callback
+    let (tx, rx) = bounded::<[f64; 4]>(1_000_000);
+    let event_tx = tx.clone();
+    let mut process_block = fm_process(backend.sample_rate(), rx);
+    let process = backend::stereo(backend.buffer_size(), move |out_l, out_r, info| {
+        while let Some(f) = events.pop_due(info.time()) {
+            event_tx.try_send(f).ok();
+        }
+        process_block(out_l, out_r, info);
+    });
+
+    // 3. start processing, offline backends are done when this returns
+    let running = backend.start(process).unwrap();
+    if !is_realtime {
+        println!("Done rendering");
+        return;
     }
 
-    // 5. wait or do some processing while your handler is running in real time.
-    println!("Enter freq c_ratio m_ratio lfo_freq");
+    // 4. wait or do some processing while your handler is running in real time.
+    println!("Enter freq c_ratio m_ratio m_index");
     while let Some(f) = read_freq() {
         tx.send(f).unwrap();
     }
 
-    // 6. Optional deactivate. Not required since active_client will deactivate on
-    // drop, though explicit deactivate may help you identify errors in
-    // deactivate.
-    active_client.deactivate().unwrap();
+    // 5. Optional stop. Not required since the backend stops on drop.
+    drop(running);
+}
+
+/// Create the process logic of the synth: parameter messages from `rx`, the
+/// FM signal chain and the trigger counter. It is run by whichever audio
+/// backend was chosen on the command line. When the backend follows a
+/// transport with a tempo notes are triggered on its beats instead of by the
+/// counter.
+fn fm_process(
+    sample_rate: usize,
+    rx: Receiver<[f64; 4]>,
+) -> impl FnMut(&mut [f32], &mut [f32], &ProcessInfo) {
+    let mut frequency = 220.0;
+    let frame_t = 1.0 / sample_rate as f64;
+    let mut time = 0.0;
+
+    // FMSynth setup
+    let mut fm_synth = FMSynth::new(sample_rate as f64, frequency, 1.0, 2.5, 1.0, 6.0);
+    let params = fm_synth.params();
+    let mut counter = 0;
+    let mut beat_clock = BeatClock::new(1);
+
+    move |out_l: &mut [f32], out_r: &mut [f32], info: &ProcessInfo| {
+        // Check parameter requests
+        while let Ok(f) = rx.try_recv() {
+            time = 0.0;
+            frequency = f[0];
+            params.c_ratio.set(f[1]);
+            params.m_ratio.set(f[2]);
+            params.m_index.set(f[3]);
+            fm_synth.trigger(f[0]);
+        }
+
+        // Trigger on the beats of the transport, at the start of the block
+        if beat_clock.next_step(info).is_some() {
+            fm_synth.trigger(frequency);
+        }
+
+        // Write output
+        for (l, r) in out_l.iter_mut().zip(out_r.iter_mut()) {
+            let frame = fm_synth.next_stereo();
+            *l = frame[0] as f32;
+            *r = frame[1] as f32;
+            time += frame_t;
+        }
+
+        fm_synth.control_rate_update();
+
+        // Trigger based on counter
+        const COUNTER_STEP: usize = 80;
+        for i in 0..16 {
+            if counter == i * COUNTER_STEP && info.beat_position().is_none() {
+                fm_synth.trigger(frequency)
+            }
+        }
+
+        counter = (counter+1) % (COUNTER_STEP*16);
+    }
 }
 
 /// Attempt to read a frequency from standard in. Will block until there is
@@ -206,14 +205,16 @@ fn main() {
 fn read_freq() -> Option<[f64; 4]> {
     let mut user_input = String::new();
     match io::stdin().read_line(&mut user_input) {
-        Ok(_) => {
-            let mut values: [f64; 4] = [220.0, 1.0, 1.0, 1.0];
-            let strings: Vec<&str> = user_input.split(" ").collect();
-            for (i, string) in strings.into_iter().enumerate() {
-                values[i] = f64::from_str(string.trim()).unwrap();
-            }
-            Some(values)
-        },
+        Ok(_) => parse_freq(&user_input),
         Err(_) => None,
     }
 }
+
+/// Parse a line of `freq c_ratio m_ratio m_index`, missing values get a default.
+fn parse_freq(line: &str) -> Option<[f64; 4]> {
+    let mut values: [f64; 4] = [220.0, 1.0, 2.5, 6.0];
+    for (value, string) in values.iter_mut().zip(line.split(" ")) {
+        *value = f64::from_str(string.trim()).ok()?;
+    }
+    Some(values)
+}
@@ -0,0 +1,147 @@
+//! What happens to points at the edges of the sketch.
+//!
+//! The sketches used to clamp positions to the window without touching the
+//! velocity, so points pushed outwards stuck to the edges. A `Boundary` keeps
+//! them in the window or a container shape, and with `Wrap` measures offsets
+//! and draws lines the short way across the seam.
+use nannou::geom::Rect;
+use nannou::prelude::*;
+
+use crate::outline::Outline;
+
+pub enum Boundary {
+    /// Stop at the edges of the window, losing the velocity into the edge.
+    Clamp,
+    /// Bounce off the edges of the window, keeping `restitution` of the speed
+    /// into the edge.
+    Bounce { restitution: f32 },
+    /// Leave at one edge of the window and come back at the opposite one.
+    Wrap,
+    /// Pushed back by walls `margin` deep at the edges of the window, harder
+    /// the deeper the point is up to an acceleration of `strength` units per
+    /// second². Clamps as well, for points pushed through.
+    SoftWalls { margin: f32, strength: f32 },
+    /// Bounce off the inside of a circle.
+    Circle {
+        center: Point2,
+        radius: f32,
+        restitution: f32,
+    },
+    /// Bounce off the inside of the filled region of an outline, e.g. a
+    /// polygon.
+    Outline { outline: Outline, restitution: f32 },
+}
+
+impl Default for Boundary {
+    fn default() -> Self {
+        Boundary::Clamp
+    }
+}
+
+/// Reflect the part of `vel` going against the inward `normal`.
+fn reflect(vel: &mut Vector2, normal: Vector2, restitution: f32) {
+    let into = vel.dot(normal);
+    if into < 0.0 {
+        *vel -= normal * into * (1.0 + restitution);
+    }
+}
+
+impl Boundary {
+    /// A container with the shape of a polygon.
+    pub fn polygon(points: Vec<Point2>, restitution: f32) -> Self {
+        Boundary::Outline {
+            outline: Outline::new(vec![points]),
+            restitution,
+        }
+    }
+
+    /// Keep a point that moved to `pos` inside, call after moving it by `dt`
+    /// seconds. Only the push of `SoftWalls` depends on `dt`.
+    pub fn apply(&self, window: Rect, pos: &mut Point2, vel: &mut Vector2, dt: f32) {
+        match self {
+            Boundary::Clamp => Boundary::clamp(window, pos, vel, 0.0),
+            Boundary::Bounce { restitution } => Boundary::clamp(window, pos, vel, *restitution),
+            Boundary::Wrap => {
+                pos.x = window.left() + (pos.x - window.left()).rem_euclid(window.w());
+                pos.y = window.bottom() + (pos.y - window.bottom()).rem_euclid(window.h());
+            }
+            Boundary::SoftWalls { margin, strength } => {
+                if *margin > 0.0 {
+                    let push = |depth: f32| (1.0 - depth / margin).max(0.0) * strength * dt;
+                    vel.x += push(pos.x - window.left()) - push(window.right() - pos.x);
+                    vel.y += push(pos.y - window.bottom()) - push(window.top() - pos.y);
+                }
+                Boundary::clamp(window, pos, vel, 0.0);
+            }
+            Boundary::Circle {
+                center,
+                radius,
+                restitution,
+            } => {
+                let offset = *pos - *center;
+                let distance = offset.magnitude();
+                if distance > *radius {
+                    let normal = -offset / distance;
+                    *pos = *center - normal * *radius;
+                    reflect(vel, normal, *restitution);
+                }
+            }
+            Boundary::Outline { outline, restitution } => {
+                if outline.contains(*pos) {
+                    return;
+                }
+                if let Some(closest) = outline.closest(*pos) {
+                    let offset = closest - *pos;
+                    let distance = offset.magnitude();
+                    if distance > 0.0 {
+                        let normal = offset / distance;
+                        // Just inside, on the edge it could count as outside.
+                        *pos = closest + normal * 0.01;
+                        reflect(vel, normal, *restitution);
+                    }
+                }
+            }
+        }
+    }
+
+    fn clamp(window: Rect, pos: &mut Point2, vel: &mut Vector2, restitution: f32) {
+        if pos.x < window.left() {
+            pos.x = window.left();
+            reflect(vel, vec2(1.0, 0.0), restitution);
+        } else if pos.x > window.right() {
+            pos.x = window.right();
+            reflect(vel, vec2(-1.0, 0.0), restitution);
+        }
+        if pos.y < window.bottom() {
+            pos.y = window.bottom();
+            reflect(vel, vec2(0.0, 1.0), restitution);
+        } else if pos.y > window.top() {
+            pos.y = window.top();
+            reflect(vel, vec2(0.0, -1.0), restitution);
+        }
+    }
+
+    /// The offset from `from` to `to`, the shortest one across the seam when
+    /// wrapping. Use it for distances and forces between points.
+    pub fn offset(&self, window: Rect, from: Point2, to: Point2) -> Vector2 {
+        let offset = to - from;
+        match self {
+            Boundary::Wrap => {
+                let wrap = |d: f32, size: f32| d - (d / size).round() * size;
+                vec2(wrap(offset.x, window.w()), wrap(offset.y, window.h()))
+            }
+            _ => offset,
+        }
+    }
+
+    /// The segments of a line from `a` to `b`: one, or one at each side of the
+    /// seam when wrapping and the short way crosses it.
+    pub fn segments(&self, window: Rect, a: Point2, b: Point2) -> Vec<(Point2, Point2)> {
+        let offset = self.offset(window, a, b);
+        if offset == b - a {
+            vec![(a, b)]
+        } else {
+            vec![(a, a + offset), (b - offset, b)]
+        }
+    }
+}
@@ -0,0 +1,328 @@
+//! Closed parametric curves for seeding points, and arc-length
+//! reparameterisation so points placed at even parameter steps are evenly
+//! spaced along the curve.
+//!
+//! The curves are around the origin with a size of about 1 to 4, sketches
+//! scale and move them, e.g. with a `FromFn` closure.
+use nannou::prelude::*;
+use std::cmp::Ordering;
+
+use crate::expr::{Expr, ParseError};
+
+pub trait Curve {
+    fn point(&self, t: f32) -> Point2;
+
+    /// The parameter range of one loop around the curve, `0..period`.
+    fn period(&self) -> f32 {
+        PI * 2.0
+    }
+}
+
+/// A closure as a curve with a period of 2π.
+pub struct FromFn<F>(pub F);
+
+impl<F: Fn(f32) -> Point2> Curve for FromFn<F> {
+    fn point(&self, t: f32) -> Point2 {
+        (self.0)(t)
+    }
+}
+
+impl<C: Curve + ?Sized> Curve for Box<C> {
+    fn point(&self, t: f32) -> Point2 {
+        (**self).point(t)
+    }
+
+    fn period(&self) -> f32 {
+        (**self).period()
+    }
+}
+
+/// The number of turns of 2π until a curve with a frequency `ratio` closes,
+/// for ratios that are close to a fraction with a denominator up to 100.
+fn turns_until_closed(ratio: f32) -> f32 {
+    for turns in 1..=100 {
+        let x = ratio * turns as f32;
+        if (x - x.round()).abs() < 1e-3 {
+            return turns as f32;
+        }
+    }
+    100.0
+}
+
+/// Heart curve equation from here: https://mathworld.wolfram.com/HeartCurve.html
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Heart;
+
+impl Curve for Heart {
+    fn point(&self, t: f32) -> Point2 {
+        let r = 2.0 - 2.0 * t.sin() + (t.sin() * (t.cos().abs().sqrt() / (t.sin() + 1.4)));
+        pt2(t.cos() * r, t.sin() * r)
+    }
+}
+
+/// The rose-like heart of 20200318, `r = sin t + sin(5t/2)^3`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RoseHeart;
+
+impl Curve for RoseHeart {
+    fn point(&self, t: f32) -> Point2 {
+        let r = t.sin() + ((t * 5.0) / 2.0).sin().powf(3.0);
+        pt2(t.cos() * r, t.sin() * r)
+    }
+
+    fn period(&self) -> f32 {
+        PI * 4.0
+    }
+}
+
+/// A rose, `r = cos(k t)`.
+#[derive(Debug, Clone, Copy)]
+pub struct Rose {
+    pub k: f32,
+}
+
+impl Curve for Rose {
+    fn point(&self, t: f32) -> Point2 {
+        let r = (self.k * t).cos();
+        pt2(t.cos() * r, t.sin() * r)
+    }
+
+    fn period(&self) -> f32 {
+        PI * 2.0 * turns_until_closed(self.k)
+    }
+}
+
+/// `(sin(a t + delta), sin(b t))`
+#[derive(Debug, Clone, Copy)]
+pub struct Lissajous {
+    pub a: f32,
+    pub b: f32,
+    pub delta: f32,
+}
+
+impl Curve for Lissajous {
+    fn point(&self, t: f32) -> Point2 {
+        pt2((self.a * t + self.delta).sin(), (self.b * t).sin())
+    }
+
+    fn period(&self) -> f32 {
+        PI * 2.0 * turns_until_closed(self.a).max(turns_until_closed(self.b))
+    }
+}
+
+/// Gielis' superformula, `m` is the rotational symmetry, see
+/// https://en.wikipedia.org/wiki/Superformula
+#[derive(Debug, Clone, Copy)]
+pub struct Superformula {
+    pub a: f32,
+    pub b: f32,
+    pub m: f32,
+    pub n1: f32,
+    pub n2: f32,
+    pub n3: f32,
+}
+
+impl Curve for Superformula {
+    fn point(&self, t: f32) -> Point2 {
+        let angle = self.m * t / 4.0;
+        let r = ((angle.cos() / self.a).abs().powf(self.n2) + (angle.sin() / self.b).abs().powf(self.n3))
+            .powf(-1.0 / self.n1);
+        pt2(t.cos() * r, t.sin() * r)
+    }
+
+    fn period(&self) -> f32 {
+        PI * 2.0 * turns_until_closed(self.m / 4.0)
+    }
+}
+
+/// The path of a point on a circle of radius `rolling` rolling around the
+/// outside of a circle of radius `fixed`.
+#[derive(Debug, Clone, Copy)]
+pub struct Epicycloid {
+    pub fixed: f32,
+    pub rolling: f32,
+}
+
+impl Curve for Epicycloid {
+    fn point(&self, t: f32) -> Point2 {
+        let sum = self.fixed + self.rolling;
+        let k = sum / self.rolling;
+        pt2(
+            sum * t.cos() - self.rolling * (k * t).cos(),
+            sum * t.sin() - self.rolling * (k * t).sin(),
+        )
+    }
+
+    fn period(&self) -> f32 {
+        PI * 2.0 * turns_until_closed(self.fixed / self.rolling)
+    }
+}
+
+/// A curve typed in by the user as expressions of `t` for x and y, see
+/// `expr`.
+#[derive(Debug, Clone)]
+pub struct Expression {
+    pub x: Expr,
+    pub y: Expr,
+    pub period: f32,
+}
+
+impl Expression {
+    pub fn parse(x: &str, y: &str) -> Result<Self, ParseError> {
+        Ok(Expression {
+            x: Expr::parse(x)?,
+            y: Expr::parse(y)?,
+            period: PI * 2.0,
+        })
+    }
+}
+
+impl Curve for Expression {
+    fn point(&self, t: f32) -> Point2 {
+        pt2(self.x.eval(t), self.y.eval(t))
+    }
+
+    fn period(&self) -> f32 {
+        self.period
+    }
+}
+
+/// A curve reparameterised by arc length: equal steps of the parameter are
+/// equal distances along the curve. It has the period of the original curve
+/// and is periodic.
+///
+/// The length is measured once along `samples` straight segments, more
+/// samples follow sharp corners better.
+pub struct ArcLength<C> {
+    curve: C,
+    /// The parameter and length from the start of the curve at every sample.
+    params: Vec<f32>,
+    lengths: Vec<f32>,
+}
+
+impl<C: Curve> ArcLength<C> {
+    pub fn new(curve: C, samples: usize) -> Self {
+        let samples = samples.max(2);
+        let period = curve.period();
+        let mut params = Vec::with_capacity(samples + 1);
+        let mut lengths = Vec::with_capacity(samples + 1);
+        let mut length = 0.0;
+        let mut last: Option<Point2> = None;
+        for i in 0..=samples {
+            let t = period * i as f32 / samples as f32;
+            let point = curve.point(t);
+            // Curves with singularities can return NaN at a sample, also at
+            // the first one, so the length starts at the first finite point.
+            if point.x.is_finite() && point.y.is_finite() {
+                if let Some(last) = last {
+                    length += point.distance(last);
+                }
+                last = Some(point);
+            }
+            params.push(t);
+            lengths.push(length);
+        }
+        ArcLength {
+            curve,
+            params,
+            lengths,
+        }
+    }
+
+    pub fn curve(&self) -> &C {
+        &self.curve
+    }
+
+    /// The length of one loop around the curve.
+    pub fn length(&self) -> f32 {
+        *self.lengths.last().unwrap()
+    }
+
+    /// The parameter of the original curve at `distance` along it from the
+    /// start, for a `distance` in `0..length`.
+    pub fn param_at_distance(&self, distance: f32) -> f32 {
+        let i = match self
+            .lengths
+            .binary_search_by(|length| length.partial_cmp(&distance).unwrap_or(Ordering::Less))
+        {
+            Ok(i) => return self.params[i],
+            Err(i) => i.max(1).min(self.lengths.len() - 1),
+        };
+        let (l0, l1) = (self.lengths[i - 1], self.lengths[i]);
+        let (t0, t1) = (self.params[i - 1], self.params[i]);
+        if l1 > l0 {
+            t0 + (t1 - t0) * (distance - l0) / (l1 - l0)
+        } else {
+            t0
+        }
+    }
+
+    /// `count` evenly spaced points around the curve.
+    pub fn even_points(&self, count: usize) -> Vec<Point2> {
+        let period = self.period();
+        (0..count)
+            .map(|i| self.point(period * i as f32 / count as f32))
+            .collect()
+    }
+}
+
+impl<C: Curve> Curve for ArcLength<C> {
+    fn point(&self, t: f32) -> Point2 {
+        let period = self.period();
+        let loops = (t / period).floor();
+        let distance = (t / period - loops) * self.length();
+        self.curve.point(self.param_at_distance(distance) + loops * period)
+    }
+
+    fn period(&self) -> f32 {
+        self.curve.period()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The distances between neighbouring points of `even_points` relative
+    /// to their mean: the largest one and the share of them that are within
+    /// 2% of the mean.
+    fn spread<C: Curve>(curve: C, count: usize) -> (f32, f32) {
+        let points = ArcLength::new(curve, count * 20).even_points(count);
+        let distances: Vec<f32> = (0..count)
+            .map(|i| points[i].distance(points[(i + 1) % count]))
+            .collect();
+        let mean = distances.iter().sum::<f32>() / count as f32;
+        let max = distances.iter().cloned().fold(0.0, f32::max);
+        let even = distances.iter().filter(|d| (*d / mean - 1.0).abs() < 0.02).count();
+        (max / mean, even as f32 / count as f32)
+    }
+
+    #[test]
+    fn even_points_are_evenly_spaced() {
+        let curves: Vec<(&str, Box<dyn Curve>)> = vec![
+            ("heart", Box::new(Heart)),
+            ("rose heart", Box::new(RoseHeart)),
+            ("epicycloid", Box::new(Epicycloid { fixed: 3.0, rolling: 1.0 })),
+        ];
+        for (name, curve) in curves {
+            let (max, even) = spread(curve, 1000);
+            assert!(max < 1.01, "{}: the largest distance is {} times the mean", name, max);
+            // The straight distance across a cusp is shorter than the way
+            // along the curve, so a few points are closer together.
+            assert!(even > 0.98, "{}: only {} of the distances are even", name, even);
+        }
+    }
+
+    #[test]
+    fn length_starts_at_the_first_finite_point() {
+        let circle = FromFn(|t: f32| {
+            if t == 0.0 {
+                pt2(f32::NAN, f32::NAN)
+            } else {
+                pt2(t.cos(), t.sin())
+            }
+        });
+        let length = ArcLength::new(circle, 1000).length();
+        assert!((length - PI * 2.0).abs() < 0.01, "length {}", length);
+    }
+}
@@ -0,0 +1,508 @@
+//! Emitters decide where the points of a sketch are created.
+//!
+//! An `Emitter` pushes `Seed`s, the sketch turns them into its own points.
+//! Emitters that place all their points at once (grids, rings, images) do it
+//! on the first `emit` after they were created or `reset`, the others emit
+//! every frame. They are combined with `Group` and scheduled with the
+//! `EmitterExt` combinators, e.g. four points on a curve every third frame:
+//!
+//! ```ignore
+//! let mut heart = Parametric::new(0.0, 0.02, Heart).repeat(4).every(3);
+//! ```
+use nannou::geom::Rect;
+use nannou::image::Pixel;
+use nannou::prelude::*;
+use rand::seq::SliceRandom;
+
+use crate::curve::Curve;
+use crate::image_field::ImageField;
+
+/// A point to create.
+#[derive(Debug, Clone, Copy)]
+pub struct Seed {
+    pub pos: Point2,
+    /// Where the seed is on the shape of its emitter, see the emitters.
+    pub t: f32,
+    /// The colour under the seed, for emitters sampling an image.
+    pub color: Option<Rgba>,
+}
+
+impl Seed {
+    pub fn at(pos: Point2, t: f32) -> Self {
+        Seed { pos, t, color: None }
+    }
+}
+
+/// What an emitter can look at when it is asked for seeds.
+#[derive(Debug, Clone, Copy)]
+pub struct EmitContext {
+    pub frame: u64,
+    pub window: Rect,
+    pub mouse: Point2,
+    /// Where the left mouse button was pressed, while it is down.
+    pub pressed_at: Option<Point2>,
+}
+
+impl EmitContext {
+    pub fn from_app(app: &App) -> Self {
+        EmitContext {
+            frame: app.elapsed_frames(),
+            window: app.window_rect(),
+            mouse: pt2(app.mouse.x, app.mouse.y),
+            pressed_at: app.mouse.buttons.left().if_down(),
+        }
+    }
+}
+
+pub trait Emitter {
+    /// Push the seeds for this frame.
+    fn emit(&mut self, ctx: &EmitContext, seeds: &mut Vec<Seed>);
+
+    /// Start over, emitters placing all their points at once place them
+    /// again on the next `emit`.
+    fn reset(&mut self) {}
+
+    /// The seeds for this frame in a new `Vec`.
+    fn seeds(&mut self, ctx: &EmitContext) -> Vec<Seed> {
+        let mut seeds = vec![];
+        self.emit(ctx, &mut seeds);
+        seeds
+    }
+}
+
+impl<E: Emitter + ?Sized> Emitter for Box<E> {
+    fn emit(&mut self, ctx: &EmitContext, seeds: &mut Vec<Seed>) {
+        (**self).emit(ctx, seeds)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+}
+
+/// Scheduling and shuffling for any emitter.
+pub trait EmitterExt: Emitter + Sized {
+    /// Only emit on every `frames`th frame.
+    fn every(self, frames: u64) -> Every<Self> {
+        Every {
+            emitter: self,
+            frames: frames.max(1),
+        }
+    }
+
+    /// Only emit from frame `start` until before frame `end`.
+    fn between(self, start: u64, end: u64) -> Between<Self> {
+        Between {
+            emitter: self,
+            start,
+            end,
+        }
+    }
+
+    /// Emit on a frame with the given probability.
+    fn chance(self, probability: f32) -> Chance<Self> {
+        Chance {
+            emitter: self,
+            probability,
+        }
+    }
+
+    /// Emit `times` times per frame.
+    fn repeat(self, times: usize) -> Repeat<Self> {
+        Repeat {
+            emitter: self,
+            times,
+        }
+    }
+
+    /// Shuffle the seeds of every `emit`. The order points are created in is
+    /// the order they are checked for neighbours, so it decides which ones
+    /// connect first.
+    fn shuffled(self) -> Shuffled<Self> {
+        Shuffled { emitter: self }
+    }
+
+    fn boxed(self) -> Box<dyn Emitter>
+    where
+        Self: 'static,
+    {
+        Box::new(self)
+    }
+}
+
+impl<E: Emitter> EmitterExt for E {}
+
+pub struct Every<E> {
+    pub emitter: E,
+    frames: u64,
+}
+
+impl<E: Emitter> Emitter for Every<E> {
+    fn emit(&mut self, ctx: &EmitContext, seeds: &mut Vec<Seed>) {
+        if ctx.frame % self.frames == 0 {
+            self.emitter.emit(ctx, seeds);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.emitter.reset()
+    }
+}
+
+pub struct Between<E> {
+    pub emitter: E,
+    start: u64,
+    end: u64,
+}
+
+impl<E: Emitter> Emitter for Between<E> {
+    fn emit(&mut self, ctx: &EmitContext, seeds: &mut Vec<Seed>) {
+        if ctx.frame >= self.start && ctx.frame < self.end {
+            self.emitter.emit(ctx, seeds);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.emitter.reset()
+    }
+}
+
+pub struct Chance<E> {
+    pub emitter: E,
+    probability: f32,
+}
+
+impl<E: Emitter> Emitter for Chance<E> {
+    fn emit(&mut self, ctx: &EmitContext, seeds: &mut Vec<Seed>) {
+        if random_f32() < self.probability {
+            self.emitter.emit(ctx, seeds);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.emitter.reset()
+    }
+}
+
+pub struct Repeat<E> {
+    pub emitter: E,
+    times: usize,
+}
+
+impl<E: Emitter> Emitter for Repeat<E> {
+    fn emit(&mut self, ctx: &EmitContext, seeds: &mut Vec<Seed>) {
+        for _ in 0..self.times {
+            self.emitter.emit(ctx, seeds);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.emitter.reset()
+    }
+}
+
+pub struct Shuffled<E> {
+    pub emitter: E,
+}
+
+impl<E: Emitter> Emitter for Shuffled<E> {
+    fn emit(&mut self, ctx: &EmitContext, seeds: &mut Vec<Seed>) {
+        let start = seeds.len();
+        self.emitter.emit(ctx, seeds);
+        seeds[start..].shuffle(&mut rand::thread_rng());
+    }
+
+    fn reset(&mut self) {
+        self.emitter.reset()
+    }
+}
+
+/// Several emitters emitting in the order they were added.
+#[derive(Default)]
+pub struct Group {
+    emitters: Vec<Box<dyn Emitter>>,
+}
+
+impl Group {
+    pub fn new() -> Self {
+        Group::default()
+    }
+
+    pub fn with<E: Emitter + 'static>(mut self, emitter: E) -> Self {
+        self.push(emitter);
+        self
+    }
+
+    pub fn push<E: Emitter + 'static>(&mut self, emitter: E) {
+        self.emitters.push(Box::new(emitter));
+    }
+}
+
+impl Emitter for Group {
+    fn emit(&mut self, ctx: &EmitContext, seeds: &mut Vec<Seed>) {
+        for emitter in &mut self.emitters {
+            emitter.emit(ctx, seeds);
+        }
+    }
+
+    fn reset(&mut self) {
+        for emitter in &mut self.emitters {
+            emitter.reset();
+        }
+    }
+}
+
+/// One uniformly random point in `area`, or in the window if it is `None`.
+/// `t` is 0.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Random {
+    pub area: Option<Rect>,
+}
+
+impl Emitter for Random {
+    fn emit(&mut self, ctx: &EmitContext, seeds: &mut Vec<Seed>) {
+        let area = self.area.unwrap_or(ctx.window);
+        let pos = pt2(
+            random_range(area.left(), area.right()),
+            random_range(area.bottom(), area.top()),
+        );
+        seeds.push(Seed::at(pos, 0.0));
+    }
+}
+
+/// One point scattered around the mouse while it is dragged with the left
+/// button. `t` is 0.
+#[derive(Debug, Clone, Copy)]
+pub struct MouseBrush {
+    /// How far from the mouse the point may be on each axis, has to be
+    /// larger than 0.
+    pub radius: f32,
+}
+
+impl Emitter for MouseBrush {
+    fn emit(&mut self, ctx: &EmitContext, seeds: &mut Vec<Seed>) {
+        let pressed_at = match ctx.pressed_at {
+            Some(pressed_at) => pressed_at,
+            None => return,
+        };
+        if ctx.mouse.distance(pressed_at) > 0.0 {
+            let offset = pt2(
+                random_range(-self.radius, self.radius),
+                random_range(-self.radius, self.radius),
+            );
+            seeds.push(Seed::at(ctx.mouse + offset, 0.0));
+        }
+    }
+}
+
+/// Walks along a parametric curve, one point at `curve.point(param)` per
+/// `emit` after which the parameter advances by `step`. `t` is the parameter.
+/// Wrap the curve in `ArcLength` for evenly spaced points.
+pub struct Parametric<C> {
+    pub param: f32,
+    pub step: f32,
+    start: f32,
+    pub curve: C,
+}
+
+impl<C: Curve> Parametric<C> {
+    pub fn new(start: f32, step: f32, curve: C) -> Self {
+        Parametric {
+            param: start,
+            step,
+            start,
+            curve,
+        }
+    }
+}
+
+impl<C: Curve> Emitter for Parametric<C> {
+    fn emit(&mut self, _ctx: &EmitContext, seeds: &mut Vec<Seed>) {
+        let pos = self.curve.point(self.param);
+        seeds.push(Seed::at(pos, self.param));
+        self.param += self.step;
+    }
+
+    fn reset(&mut self) {
+        self.param = self.start;
+    }
+}
+
+/// A hex grid of points `spacing` apart filling `area`, or the window if it is
+/// `None`, placed at once. `t` goes from 0 at the top left to 1 at the bottom
+/// right.
+#[derive(Debug, Clone, Copy)]
+pub struct HexGrid {
+    pub spacing: f32,
+    pub area: Option<Rect>,
+    placed: bool,
+}
+
+impl HexGrid {
+    pub fn new(spacing: f32) -> Self {
+        HexGrid {
+            spacing,
+            area: None,
+            placed: false,
+        }
+    }
+}
+
+impl Emitter for HexGrid {
+    fn emit(&mut self, ctx: &EmitContext, seeds: &mut Vec<Seed>) {
+        if self.placed {
+            return;
+        }
+        self.placed = true;
+        let area = self.area.unwrap_or(ctx.window);
+        let columns = (area.w() / self.spacing) as usize;
+        let rows = (area.h() / self.spacing) as usize;
+        let diagonal = (columns + rows) as f32;
+        for ix in 1..columns {
+            for iy in 1..rows {
+                // Odd rows are shifted half a step to the right, even rows
+                // leave out their first point instead.
+                if iy % 2 == 0 && ix == 1 {
+                    continue;
+                }
+                let mut x = ix as f32 * self.spacing + area.left();
+                if iy % 2 == 1 {
+                    x += self.spacing / 2.0;
+                }
+                let y = area.top() - iy as f32 * self.spacing;
+                seeds.push(Seed::at(pt2(x, y), (ix + iy) as f32 / diagonal));
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.placed = false;
+    }
+}
+
+/// Concentric rings around `center`, placed at once. Ring `n` of `count` has
+/// a radius of `n * width` and points about `spacing` apart. `t` is the ring
+/// number over `count + 1`.
+#[derive(Debug, Clone, Copy)]
+pub struct Rings {
+    pub count: usize,
+    pub width: f32,
+    pub spacing: f32,
+    pub center: Point2,
+    /// Every ring is rotated by a random angle up to this.
+    pub rotation: f32,
+    /// Every point is moved along its ring by a random angle up to this.
+    pub jitter: f32,
+    /// Divide `rotation` and `jitter` by the number of points on the ring,
+    /// so they get smaller on the outer rings.
+    pub per_point: bool,
+    placed: bool,
+}
+
+impl Rings {
+    pub fn new(count: usize, width: f32, spacing: f32) -> Self {
+        Rings {
+            count,
+            width,
+            spacing,
+            center: pt2(0.0, 0.0),
+            rotation: 0.0,
+            jitter: 0.0,
+            per_point: false,
+            placed: false,
+        }
+    }
+}
+
+impl Emitter for Rings {
+    fn emit(&mut self, _ctx: &EmitContext, seeds: &mut Vec<Seed>) {
+        if self.placed {
+            return;
+        }
+        self.placed = true;
+        for ring in 1..=self.count {
+            let r = ring as f32 * self.width;
+            let num_points = r * PI * 2.0 / self.spacing;
+            let scale = if self.per_point { 1.0 / num_points } else { 1.0 };
+            let rotation = random_f32() * self.rotation * scale;
+            for n in 0..num_points as usize {
+                let jitter = random_f32() * self.jitter * scale;
+                let angle = PI * 2.0 * n as f32 / num_points + rotation + jitter;
+                let pos = self.center + pt2(angle.cos() * r, angle.sin() * r);
+                seeds.push(Seed::at(pos, ring as f32 / (self.count + 1) as f32));
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.placed = false;
+    }
+}
+
+/// Points placed at once where an image is bright, a white image gets about
+/// `count` points. Pixels outside of the window are skipped. `t` is the luma
+/// and `color` the colour of the pixel.
+pub struct ImageDensity {
+    pub field: ImageField,
+    pub count: f32,
+    /// How far from its pixel a point may be on each axis.
+    pub jitter: f32,
+    placed: bool,
+}
+
+impl ImageDensity {
+    pub fn new(field: ImageField, count: f32) -> Self {
+        ImageDensity {
+            field,
+            count,
+            jitter: 0.0,
+            placed: false,
+        }
+    }
+
+    /// The seeds `ImageDensity` places, for fields it doesn't own such as
+    /// the frames of a `video::VideoField`.
+    pub fn sample(field: &ImageField, count: f32, jitter: f32, window: Rect, seeds: &mut Vec<Seed>) {
+        let (w, h) = field.image().dimensions();
+        let probability = count / (w * h) as f32;
+        for (x, y, pixel) in field.image().enumerate_pixels() {
+            let luma = pixel.to_luma()[0] as f32 / 255.0;
+            if random_f32() >= luma * probability {
+                continue;
+            }
+            let mut pos = field.to_window(x as f32, y as f32);
+            if pos.x < window.left() || pos.x > window.right() || pos.y < window.bottom() || pos.y > window.top() {
+                continue;
+            }
+            if jitter > 0.0 {
+                pos += pt2(random_range(-jitter, jitter), random_range(-jitter, jitter));
+            }
+            let [r, g, b, a] = pixel.0;
+            let color = rgba(
+                r as f32 / 255.0,
+                g as f32 / 255.0,
+                b as f32 / 255.0,
+                a as f32 / 255.0,
+            );
+            seeds.push(Seed {
+                pos,
+                t: luma,
+                color: Some(color),
+            });
+        }
+    }
+}
+
+impl Emitter for ImageDensity {
+    fn emit(&mut self, ctx: &EmitContext, seeds: &mut Vec<Seed>) {
+        if self.placed {
+            return;
+        }
+        self.placed = true;
+        ImageDensity::sample(&self.field, self.count, self.jitter, ctx.window, seeds);
+    }
+
+    fn reset(&mut self) {
+        self.placed = false;
+    }
+}
@@ -0,0 +1,404 @@
+//! Arithmetic expressions of a parameter `t`, for curves typed in by the user
+//! like `sin(3*t) * cos(t)^2`.
+//!
+//! Supported are numbers, `t`, the constants `pi`, `tau` and `e`, `+ - * / ^`,
+//! parentheses and the functions `sin cos tan asin acos atan sinh cosh tanh
+//! abs sqrt exp ln floor ceil sign`.
+use std::f32::consts::{E, PI};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f32),
+    Param,
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Call(Function, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Floor,
+    Ceil,
+    Sign,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        let function = match name {
+            "sin" => Function::Sin,
+            "cos" => Function::Cos,
+            "tan" => Function::Tan,
+            "asin" => Function::Asin,
+            "acos" => Function::Acos,
+            "atan" => Function::Atan,
+            "sinh" => Function::Sinh,
+            "cosh" => Function::Cosh,
+            "tanh" => Function::Tanh,
+            "abs" => Function::Abs,
+            "sqrt" => Function::Sqrt,
+            "exp" => Function::Exp,
+            "ln" => Function::Ln,
+            "floor" => Function::Floor,
+            "ceil" => Function::Ceil,
+            "sign" => Function::Sign,
+            _ => return None,
+        };
+        Some(function)
+    }
+
+    fn apply(self, x: f32) -> f32 {
+        match self {
+            Function::Sin => x.sin(),
+            Function::Cos => x.cos(),
+            Function::Tan => x.tan(),
+            Function::Asin => x.asin(),
+            Function::Acos => x.acos(),
+            Function::Atan => x.atan(),
+            Function::Sinh => x.sinh(),
+            Function::Cosh => x.cosh(),
+            Function::Tanh => x.tanh(),
+            Function::Abs => x.abs(),
+            Function::Sqrt => x.sqrt(),
+            Function::Exp => x.exp(),
+            Function::Ln => x.ln(),
+            Function::Floor => x.floor(),
+            Function::Ceil => x.ceil(),
+            Function::Sign => x.signum(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The byte offset in the expression.
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr, ParseError> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            next: 0,
+            end: source.len(),
+        };
+        let expr = parser.sum()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some((position, token)) => Err(ParseError {
+                position,
+                message: format!("unexpected {}", token),
+            }),
+        }
+    }
+
+    pub fn eval(&self, t: f32) -> f32 {
+        match self {
+            Expr::Number(x) => *x,
+            Expr::Param => t,
+            Expr::Neg(x) => -x.eval(t),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(t), b.eval(t));
+                match op {
+                    Op::Add => a + b,
+                    Op::Sub => a - b,
+                    Op::Mul => a * b,
+                    Op::Div => a / b,
+                    Op::Pow => a.powf(b),
+                }
+            }
+            Expr::Call(function, x) => function.apply(x.eval(t)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f32),
+    Ident(String),
+    Op(char),
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(x) => write!(f, "number {}", x),
+            Token::Ident(name) => write!(f, "\"{}\"", name),
+            Token::Op(c) => write!(f, "\"{}\"", c),
+            Token::Open => write!(f, "\"(\""),
+            Token::Close => write!(f, "\")\""),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = vec![];
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_ascii_digit() || c == '.') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let number = source[start..end].parse().map_err(|_| ParseError {
+                position: start,
+                message: format!("invalid number \"{}\"", &source[start..end]),
+            })?;
+            tokens.push((start, Token::Number(number)));
+        } else if c.is_alphabetic() {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !c.is_alphanumeric() {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push((start, Token::Ident(source[start..end].to_lowercase())));
+        } else {
+            let token = match c {
+                '+' | '-' | '*' | '/' | '^' => Token::Op(c),
+                '(' => Token::Open,
+                ')' => Token::Close,
+                _ => {
+                    return Err(ParseError {
+                        position: start,
+                        message: format!("unexpected character '{}'", c),
+                    })
+                }
+            };
+            tokens.push((start, token));
+            chars.next();
+        }
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser, `^` binds tighter than unary minus so `-t^2`
+/// is `-(t^2)`.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    /// The length of the source, the position of errors at the end.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<(usize, &Token)> {
+        self.tokens.get(self.next).map(|(position, token)| (*position, token))
+    }
+
+    fn take_op(&mut self, ops: &[char]) -> Option<char> {
+        match self.peek() {
+            Some((_, Token::Op(c))) if ops.contains(c) => {
+                let c = *c;
+                self.next += 1;
+                Some(c)
+            }
+            _ => None,
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.product()?;
+        while let Some(c) = self.take_op(&['+', '-']) {
+            let op = if c == '+' { Op::Add } else { Op::Sub };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.unary()?;
+        while let Some(c) = self.take_op(&['*', '/']) {
+            let op = if c == '*' { Op::Mul } else { Op::Div };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        match self.take_op(&['+', '-']) {
+            Some('-') => Ok(Expr::Neg(Box::new(self.unary()?))),
+            Some(_) => self.unary(),
+            None => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        let base = self.atom()?;
+        if self.take_op(&['^']).is_some() {
+            // Right associative, the exponent may be negative.
+            let exponent = self.unary()?;
+            return Ok(Expr::Binary(Op::Pow, Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr, ParseError> {
+        let (position, token) = match self.tokens.get(self.next) {
+            Some((position, token)) => (*position, token.clone()),
+            None => {
+                return Err(ParseError {
+                    position: self.end,
+                    message: "unexpected end of the expression".to_string(),
+                })
+            }
+        };
+        self.next += 1;
+        match token {
+            Token::Number(x) => Ok(Expr::Number(x)),
+            Token::Open => {
+                let expr = self.sum()?;
+                self.close()?;
+                Ok(expr)
+            }
+            Token::Ident(name) => match name.as_str() {
+                "t" => Ok(Expr::Param),
+                "pi" => Ok(Expr::Number(PI)),
+                "tau" => Ok(Expr::Number(PI * 2.0)),
+                "e" => Ok(Expr::Number(E)),
+                _ => match Function::from_name(&name) {
+                    Some(function) => {
+                        self.open(&name)?;
+                        let argument = self.sum()?;
+                        self.close()?;
+                        Ok(Expr::Call(function, Box::new(argument)))
+                    }
+                    None => Err(ParseError {
+                        position,
+                        message: format!("unknown name \"{}\"", name),
+                    }),
+                },
+            },
+            token => Err(ParseError {
+                position,
+                message: format!("unexpected {}", token),
+            }),
+        }
+    }
+
+    fn open(&mut self, function: &str) -> Result<(), ParseError> {
+        match self.peek() {
+            Some((_, Token::Open)) => {
+                self.next += 1;
+                Ok(())
+            }
+            other => Err(ParseError {
+                position: other.map_or(self.end, |(position, _)| position),
+                message: format!("expected \"(\" after {}", function),
+            }),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), ParseError> {
+        match self.peek() {
+            Some((_, Token::Close)) => {
+                self.next += 1;
+                Ok(())
+            }
+            other => Err(ParseError {
+                position: other.map_or(self.end, |(position, _)| position),
+                message: "expected \")\"".to_string(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, t: f32) -> f32 {
+        Expr::parse(source).unwrap().eval(t)
+    }
+
+    fn error(source: &str) -> ParseError {
+        Expr::parse(source).unwrap_err()
+    }
+
+    #[test]
+    fn negation_binds_looser_than_power() {
+        assert_eq!(Expr::parse("-t^2"), Expr::parse("-(t^2)"));
+        assert_eq!(eval("-t^2", 3.0), -9.0);
+    }
+
+    #[test]
+    fn power_is_right_associative() {
+        assert_eq!(eval("2^3^2", 0.0), 512.0);
+        assert_eq!(eval("2^-t", 2.0), 0.25);
+    }
+
+    #[test]
+    fn products_bind_tighter_than_sums() {
+        assert_eq!(eval("1 + 2 * t - 6 / 3", 4.0), 7.0);
+        assert_eq!(eval("(1 + 2) * t", 4.0), 12.0);
+    }
+
+    #[test]
+    fn functions_are_applied_to_their_argument() {
+        let t: f32 = 0.7;
+        let expected = (3.0 * t).sin() * t.cos().powi(2);
+        assert!((eval("sin(3*t) * cos(t)^2", t) - expected).abs() < 1e-6);
+        assert_eq!(eval("abs(floor(-t))", 1.5), 2.0);
+        assert!((eval("tau / 2", 0.0) - PI).abs() < 1e-6);
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_token() {
+        let missing_open = error("sin t");
+        assert_eq!(missing_open.position, 4);
+        assert_eq!(missing_open.message, "expected \"(\" after sin");
+
+        let implicit_product = error("2t");
+        assert_eq!(implicit_product.position, 1);
+        assert!(implicit_product.message.starts_with("unexpected"));
+
+        let missing_close = error("(t");
+        assert_eq!(missing_close.position, 2);
+        assert_eq!(missing_close.message, "expected \")\"");
+
+        let unknown = error("2 * foo(t)");
+        assert_eq!(unknown.position, 4);
+        assert_eq!(unknown.message, "unknown name \"foo\"");
+    }
+}
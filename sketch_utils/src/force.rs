@@ -0,0 +1,390 @@
+//! Force fields added to the velocity of sketch points: noise flow, curl
+//! noise, vortices, attractors and gravity.
+//!
+//! Forces combine in a `ForceField`. Their parameters are `Param`s that can
+//! oscillate over time, and `ForceField::sliders` adds a slider for each of
+//! them below the sliders of the sketch. Forces that change by themselves,
+//! like the noise, move on with `ForceField::advance` after every step.
+use nannou::noise::{NoiseFn, OpenSimplex, Perlin, Seedable};
+use nannou::prelude::*;
+use nannou::ui::prelude::*;
+use nannou::ui::UiCell;
+
+/// A force parameter with the range of its slider.
+pub struct Param {
+    pub name: &'static str,
+    pub value: f32,
+    pub min: f32,
+    pub max: f32,
+    pub lfo: Option<Lfo>,
+}
+
+/// A sine oscillation around the value of a parameter.
+#[derive(Debug, Clone, Copy)]
+pub struct Lfo {
+    pub depth: f32,
+    /// In seconds.
+    pub period: f32,
+}
+
+impl Param {
+    pub fn new(name: &'static str, value: f32, min: f32, max: f32) -> Self {
+        Param {
+            name,
+            value,
+            min,
+            max,
+            lfo: None,
+        }
+    }
+
+    /// Oscillate `depth` around the value every `period` seconds.
+    pub fn animate(mut self, depth: f32, period: f32) -> Self {
+        self.lfo = Some(Lfo { depth, period });
+        self
+    }
+
+    /// The value at `time` in seconds.
+    pub fn at(&self, time: f32) -> f32 {
+        match self.lfo {
+            Some(Lfo { depth, period }) if period > 0.0 => self.value + depth * (time / period * PI * 2.0).sin(),
+            _ => self.value,
+        }
+    }
+}
+
+pub trait Force {
+    /// Prefixes the slider labels.
+    fn name(&self) -> &str;
+
+    /// The force on a point at `pos` at `time` in seconds.
+    fn force(&self, pos: Point2, time: f32) -> Vector2;
+
+    fn params(&mut self) -> Vec<&mut Param>;
+
+    /// Move on by `dt` seconds from `time`.
+    fn advance(&mut self, _time: f32, _dt: f32) {}
+}
+
+/// Pulls everything the same way.
+pub struct Gravity {
+    /// The direction in radians, down is -π/2.
+    pub angle: Param,
+    pub strength: Param,
+}
+
+impl Gravity {
+    pub fn new(angle: f32, strength: f32) -> Self {
+        Gravity {
+            angle: Param::new("angle", angle, -PI, PI),
+            strength: Param::new("strength", strength, 0.0, 1.0),
+        }
+    }
+}
+
+impl Force for Gravity {
+    fn name(&self) -> &str {
+        "Gravity"
+    }
+
+    fn force(&self, _pos: Point2, time: f32) -> Vector2 {
+        let angle = self.angle.at(time);
+        vec2(angle.cos(), angle.sin()) * self.strength.at(time)
+    }
+
+    fn params(&mut self) -> Vec<&mut Param> {
+        vec![&mut self.angle, &mut self.strength]
+    }
+}
+
+/// How much of a force is left at `distance` from the center of a force
+/// with a `radius`, from 1 at the center to 0 at the radius.
+fn falloff(distance: f32, radius: f32) -> f32 {
+    if radius <= 0.0 {
+        0.0
+    } else {
+        (1.0 - distance / radius).max(0.0)
+    }
+}
+
+/// Pulls points towards `center`, or pushes them away with a negative
+/// strength.
+pub struct Attractor {
+    pub center: Point2,
+    pub strength: Param,
+    pub radius: Param,
+}
+
+impl Attractor {
+    pub fn new(center: Point2, strength: f32, radius: f32) -> Self {
+        Attractor {
+            center,
+            strength: Param::new("strength", strength, -2.0, 2.0),
+            radius: Param::new("radius", radius, 0.0, 1000.0),
+        }
+    }
+}
+
+impl Force for Attractor {
+    fn name(&self) -> &str {
+        "Attractor"
+    }
+
+    fn force(&self, pos: Point2, time: f32) -> Vector2 {
+        let offset = self.center - pos;
+        let distance = offset.magnitude();
+        if distance == 0.0 {
+            return vec2(0.0, 0.0);
+        }
+        offset / distance * self.strength.at(time) * falloff(distance, self.radius.at(time))
+    }
+
+    fn params(&mut self) -> Vec<&mut Param> {
+        vec![&mut self.strength, &mut self.radius]
+    }
+}
+
+/// Swirls points around `center`, counterclockwise with a positive strength.
+pub struct Vortex {
+    pub center: Point2,
+    pub strength: Param,
+    pub radius: Param,
+}
+
+impl Vortex {
+    pub fn new(center: Point2, strength: f32, radius: f32) -> Self {
+        Vortex {
+            center,
+            strength: Param::new("strength", strength, -2.0, 2.0),
+            radius: Param::new("radius", radius, 0.0, 1000.0),
+        }
+    }
+}
+
+impl Force for Vortex {
+    fn name(&self) -> &str {
+        "Vortex"
+    }
+
+    fn force(&self, pos: Point2, time: f32) -> Vector2 {
+        let offset = pos - self.center;
+        let distance = offset.magnitude();
+        if distance == 0.0 {
+            return vec2(0.0, 0.0);
+        }
+        vec2(-offset.y, offset.x) / distance * self.strength.at(time) * falloff(distance, self.radius.at(time))
+    }
+
+    fn params(&mut self) -> Vec<&mut Param> {
+        vec![&mut self.strength, &mut self.radius]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+}
+
+fn noise_fn(kind: NoiseKind, seed: u32) -> Box<dyn NoiseFn<[f64; 3]>> {
+    match kind {
+        NoiseKind::Perlin => Box::new(Perlin::new().set_seed(seed)),
+        NoiseKind::Simplex => Box::new(OpenSimplex::new().set_seed(seed)),
+    }
+}
+
+/// The noise parameters shared by `NoiseFlow` and `CurlNoise`: `scale` is
+/// the size of the noise features in 1 / window units, `speed` how fast the
+/// noise changes in noise units per second.
+pub struct NoiseParams {
+    pub strength: Param,
+    pub scale: Param,
+    pub speed: Param,
+    /// How far the noise has moved, the sum of `speed` over the steps so a
+    /// change of the speed doesn't jump to another part of the noise.
+    z: f64,
+}
+
+impl NoiseParams {
+    fn new(strength: f32) -> Self {
+        NoiseParams {
+            strength: Param::new("strength", strength, 0.0, 2.0),
+            scale: Param::new("scale", 0.005, 0.0, 0.02),
+            speed: Param::new("speed", 0.1, 0.0, 2.0),
+            z: 0.0,
+        }
+    }
+
+    fn advance(&mut self, time: f32, dt: f32) {
+        self.z += (self.speed.at(time) * dt) as f64;
+    }
+
+    /// The noise around `pos`, at offsets in noise units.
+    fn sample<'a>(&self, noise: &'a dyn NoiseFn<[f64; 3]>, pos: Point2, time: f32) -> impl Fn(f32, f32) -> f32 + 'a {
+        let scale = self.scale.at(time);
+        let z = self.z;
+        let pos = pos * scale;
+        move |dx, dy| noise.get([(pos.x + dx) as f64, (pos.y + dy) as f64, z]) as f32
+    }
+
+    fn params(&mut self) -> Vec<&mut Param> {
+        vec![&mut self.strength, &mut self.scale, &mut self.speed]
+    }
+}
+
+/// Pushes points in a direction that is the angle of the noise at their
+/// position, so they gather along its valleys.
+pub struct NoiseFlow {
+    pub params: NoiseParams,
+    noise: Box<dyn NoiseFn<[f64; 3]>>,
+}
+
+impl NoiseFlow {
+    pub fn new(kind: NoiseKind, seed: u32, strength: f32) -> Self {
+        NoiseFlow {
+            params: NoiseParams::new(strength),
+            noise: noise_fn(kind, seed),
+        }
+    }
+}
+
+impl Force for NoiseFlow {
+    fn name(&self) -> &str {
+        "Noise flow"
+    }
+
+    fn force(&self, pos: Point2, time: f32) -> Vector2 {
+        let angle = self.params.sample(&*self.noise, pos, time)(0.0, 0.0) * PI * 2.0;
+        vec2(angle.cos(), angle.sin()) * self.params.strength.at(time)
+    }
+
+    fn params(&mut self) -> Vec<&mut Param> {
+        self.params.params()
+    }
+
+    fn advance(&mut self, time: f32, dt: f32) {
+        self.params.advance(time, dt);
+    }
+}
+
+/// The curl of noise: a flow without sources or sinks, points swirl around
+/// each other instead of clumping.
+pub struct CurlNoise {
+    pub params: NoiseParams,
+    noise: Box<dyn NoiseFn<[f64; 3]>>,
+}
+
+impl CurlNoise {
+    pub fn new(kind: NoiseKind, seed: u32, strength: f32) -> Self {
+        CurlNoise {
+            params: NoiseParams::new(strength),
+            noise: noise_fn(kind, seed),
+        }
+    }
+}
+
+impl Force for CurlNoise {
+    fn name(&self) -> &str {
+        "Curl noise"
+    }
+
+    fn force(&self, pos: Point2, time: f32) -> Vector2 {
+        const EPSILON: f32 = 0.01;
+        let noise = self.params.sample(&*self.noise, pos, time);
+        let dx = (noise(EPSILON, 0.0) - noise(-EPSILON, 0.0)) / (2.0 * EPSILON);
+        let dy = (noise(0.0, EPSILON) - noise(0.0, -EPSILON)) / (2.0 * EPSILON);
+        vec2(dy, -dx) * self.params.strength.at(time)
+    }
+
+    fn params(&mut self) -> Vec<&mut Param> {
+        self.params.params()
+    }
+
+    fn advance(&mut self, time: f32, dt: f32) {
+        self.params.advance(time, dt);
+    }
+}
+
+/// The sum of several forces.
+#[derive(Default)]
+pub struct ForceField {
+    pub forces: Vec<Box<dyn Force>>,
+    ids: Option<widget::id::List>,
+}
+
+impl ForceField {
+    pub fn new() -> Self {
+        ForceField::default()
+    }
+
+    pub fn with<F: Force + 'static>(mut self, force: F) -> Self {
+        self.push(force);
+        self
+    }
+
+    pub fn push<F: Force + 'static>(&mut self, force: F) {
+        self.forces.push(Box::new(force));
+    }
+
+    pub fn force(&self, pos: Point2, time: f32) -> Vector2 {
+        self.forces
+            .iter()
+            .fold(vec2(0.0, 0.0), |sum, force| sum + force.force(pos, time))
+    }
+
+    /// Move every force on by `dt` seconds from `time`, once per step after
+    /// the points moved.
+    pub fn advance(&mut self, time: f32, dt: f32) {
+        for force in &mut self.forces {
+            force.advance(time, dt);
+        }
+    }
+
+    /// A slider for every parameter, each below the last widget set.
+    pub fn sliders(&mut self, ui: &mut UiCell) {
+        let count = self.forces.iter_mut().map(|force| force.params().len()).sum();
+        let ids = self.ids.get_or_insert_with(widget::id::List::new);
+        ids.resize(count, &mut ui.widget_id_generator());
+
+        let mut ids = ids.iter();
+        for force in &mut self.forces {
+            let name = force.name().to_string();
+            for param in force.params() {
+                let id = *ids.next().unwrap();
+                for value in widget::Slider::new(param.value, param.min, param.max)
+                    .w_h(200.0, 30.0)
+                    .label_font_size(15)
+                    .rgb(0.3, 0.3, 0.3)
+                    .label_rgb(1.0, 1.0, 1.0)
+                    .border(1.0)
+                    .down(20.0)
+                    .label(&format!("{} {}: {:.3}", name, param.name, param.value))
+                    .set(id, ui)
+                {
+                    param.value = value;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changing_the_noise_speed_keeps_the_noise_in_place() {
+        let mut flow = NoiseFlow::new(NoiseKind::Perlin, 3, 1.0);
+        flow.params.speed.value = 0.5;
+        let dt = 1.0 / 60.0;
+        for i in 0..600 {
+            flow.advance(i as f32 * dt, dt);
+        }
+        let (pos, time) = (pt2(120.0, -45.0), 10.0);
+        let before = flow.force(pos, time);
+        flow.params.speed.value = 2.0;
+        assert_eq!(flow.force(pos, time), before);
+        // The noise moved 5 noise units, not 10 s times the new speed.
+        assert!((flow.params.z - 5.0).abs() < 1e-4);
+    }
+}
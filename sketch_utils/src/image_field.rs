@@ -0,0 +1,167 @@
+//! An image laid over the window, sampled at window coordinates.
+//!
+//! 20200321 used the luma of a photo both to place points and to slow them
+//! down. `ImageField` does the mapping between window and image for any
+//! sketch: look up luma or colour as a speed field or colour source, use it
+//! as a density map for `ImageDensity` or `poisson::Radius::Density`, or
+//! follow its gradient as a force.
+use nannou::geom::Rect;
+use nannou::image::{self, Pixel, RgbaImage};
+use nannou::prelude::*;
+use std::path::Path;
+
+/// How the image is laid over the window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFit {
+    /// Stretched to the size of the window.
+    Fit,
+    /// Scaled to cover the window keeping its aspect ratio, the parts that
+    /// stick out are cropped.
+    Fill,
+    /// Scaled to fit inside the window keeping its aspect ratio, the window
+    /// outside of it is empty.
+    Letterbox,
+}
+
+pub struct ImageField {
+    image: RgbaImage,
+    fit: ImageFit,
+    window: Rect,
+    /// Window units per pixel on each axis.
+    scale: Vector2,
+    /// The window position of the top left corner of the image.
+    top_left: Point2,
+}
+
+impl ImageField {
+    pub fn new(image: RgbaImage, fit: ImageFit, window: Rect) -> Self {
+        let mut field = ImageField {
+            image,
+            fit,
+            window,
+            scale: vec2(1.0, 1.0),
+            top_left: pt2(0.0, 0.0),
+        };
+        field.layout();
+        field
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P, fit: ImageFit, window: Rect) -> image::ImageResult<Self> {
+        let image = image::open(path)?.into_rgba();
+        Ok(ImageField::new(image, fit, window))
+    }
+
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    /// Replace the image, e.g. with the next frame of a video.
+    pub fn set_image(&mut self, image: RgbaImage) {
+        let resized = image.dimensions() != self.image.dimensions();
+        self.image = image;
+        if resized {
+            self.layout();
+        }
+    }
+
+    pub fn set_window(&mut self, window: Rect) {
+        self.window = window;
+        self.layout();
+    }
+
+    pub fn set_fit(&mut self, fit: ImageFit) {
+        self.fit = fit;
+        self.layout();
+    }
+
+    fn layout(&mut self) {
+        let (w, h) = self.image.dimensions();
+        let (w, h) = (w.max(1) as f32, h.max(1) as f32);
+        let (sx, sy) = (self.window.w() / w, self.window.h() / h);
+        self.scale = match self.fit {
+            ImageFit::Fit => vec2(sx, sy),
+            ImageFit::Fill => vec2(sx.max(sy), sx.max(sy)),
+            ImageFit::Letterbox => vec2(sx.min(sy), sx.min(sy)),
+        };
+        let size = vec2(w * self.scale.x, h * self.scale.y);
+        self.top_left = self.window.xy() + vec2(-size.x, size.y) / 2.0;
+    }
+
+    /// The position in the image, in pixels from the top left, `None` outside
+    /// of it.
+    pub fn to_image(&self, p: Point2) -> Option<(f32, f32)> {
+        let x = (p.x - self.top_left.x) / self.scale.x;
+        let y = (self.top_left.y - p.y) / self.scale.y;
+        let (w, h) = self.image.dimensions();
+        if x >= 0.0 && y >= 0.0 && x < w as f32 && y < h as f32 {
+            Some((x, y))
+        } else {
+            None
+        }
+    }
+
+    /// The window position of a position in the image.
+    pub fn to_window(&self, x: f32, y: f32) -> Point2 {
+        pt2(self.top_left.x + x * self.scale.x, self.top_left.y - y * self.scale.y)
+    }
+
+    fn pixel(&self, x: i64, y: i64) -> Option<&image::Rgba<u8>> {
+        let (w, h) = self.image.dimensions();
+        if x >= 0 && y >= 0 && x < w as i64 && y < h as i64 {
+            Some(self.image.get_pixel(x as u32, y as u32))
+        } else {
+            None
+        }
+    }
+
+    fn pixel_luma(&self, x: i64, y: i64) -> f32 {
+        self.pixel(x, y).map_or(0.0, |pixel| pixel.to_luma()[0] as f32 / 255.0)
+    }
+
+    /// The colour at `p`, transparent outside of the image.
+    pub fn color(&self, p: Point2) -> Rgba {
+        match self.to_image(p) {
+            Some((x, y)) => {
+                let [r, g, b, a] = self.image.get_pixel(x as u32, y as u32).0;
+                rgba(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, a as f32 / 255.0)
+            }
+            None => rgba(0.0, 0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Channel `channel` (red, green, blue, alpha) at `p` from 0 to 1, 0
+    /// outside of the image.
+    pub fn channel(&self, p: Point2, channel: usize) -> f32 {
+        match self.to_image(p) {
+            Some((x, y)) => self.image.get_pixel(x as u32, y as u32).0[channel.min(3)] as f32 / 255.0,
+            None => 0.0,
+        }
+    }
+
+    /// The luma at `p` from 0 to 1, 0 outside of the image.
+    pub fn luma(&self, p: Point2) -> f32 {
+        match self.to_image(p) {
+            Some((x, y)) => self.pixel_luma(x as i64, y as i64),
+            None => 0.0,
+        }
+    }
+
+    /// The direction luma increases in at `p` in window coordinates, with a
+    /// Sobel filter. Its length is the change of luma per pixel.
+    pub fn gradient(&self, p: Point2) -> Vector2 {
+        let (x, y) = match self.to_image(p) {
+            Some((x, y)) => (x as i64, y as i64),
+            None => return vec2(0.0, 0.0),
+        };
+        let l = |dx: i64, dy: i64| self.pixel_luma(x + dx, y + dy);
+        let gx = (l(1, -1) + 2.0 * l(1, 0) + l(1, 1)) - (l(-1, -1) + 2.0 * l(-1, 0) + l(-1, 1));
+        let gy = (l(-1, 1) + 2.0 * l(0, 1) + l(1, 1)) - (l(-1, -1) + 2.0 * l(0, -1) + l(1, -1));
+        // The image has y down.
+        vec2(gx, -gy) / 8.0
+    }
+
+    /// How strong an edge is at `p`, the length of the gradient.
+    pub fn edge(&self, p: Point2) -> f32 {
+        self.gradient(p).magnitude()
+    }
+}
@@ -0,0 +1,13 @@
+//! Shared helpers for the nannou sketches in this workspace.
+
+pub mod boundary;
+pub mod curve;
+pub mod emit;
+pub mod expr;
+pub mod force;
+pub mod image_field;
+pub mod outline;
+pub mod poisson;
+pub mod spring;
+pub mod step;
+pub mod video;
@@ -0,0 +1,501 @@
+//! Outlines of shapes as polygons, for growing the network along a logo or a
+//! word instead of a formula.
+//!
+//! With the `import` feature outlines are loaded from SVG files (paths,
+//! shapes and their transforms, text has to be converted to paths first) or
+//! rendered from a string in a TTF/OTF font. Curves are flattened to straight
+//! segments. Points are sampled along the outline or inside the filled
+//! region, `OutlineEmitter` does it as an `Emitter`.
+use nannou::geom::Rect;
+use nannou::prelude::*;
+use std::cmp::Ordering;
+use std::fmt;
+#[cfg(feature = "import")]
+use std::path::Path;
+
+use crate::emit::{EmitContext, Emitter, Seed};
+
+/// The number of straight segments a bezier curve is flattened to.
+#[cfg(feature = "import")]
+const CURVE_SEGMENTS: usize = 16;
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(std::io::Error),
+    #[cfg(feature = "import")]
+    Svg(usvg::Error),
+    Font(String),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Io(e) => write!(f, "IO error: {}", e),
+            #[cfg(feature = "import")]
+            ImportError::Svg(e) => write!(f, "SVG error: {}", e),
+            ImportError::Font(e) => write!(f, "Font error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<std::io::Error> for ImportError {
+    fn from(e: std::io::Error) -> Self {
+        ImportError::Io(e)
+    }
+}
+
+#[cfg(feature = "import")]
+impl From<usvg::Error> for ImportError {
+    fn from(e: usvg::Error) -> Self {
+        ImportError::Svg(e)
+    }
+}
+
+/// Closed polygons, the filled region is where an odd number of them
+/// overlap.
+#[derive(Debug, Clone, Default)]
+pub struct Outline {
+    pub contours: Vec<Vec<Point2>>,
+}
+
+impl Outline {
+    pub fn new(contours: Vec<Vec<Point2>>) -> Self {
+        Outline { contours }
+    }
+
+    /// The edges of all contours, including the closing ones.
+    pub fn edges<'a>(&'a self) -> impl Iterator<Item = (Point2, Point2)> + 'a {
+        self.contours.iter().flat_map(|contour| {
+            let next = contour.iter().cycle().skip(1);
+            contour.iter().cloned().zip(next.cloned())
+        })
+    }
+
+    pub fn bounds(&self) -> Option<Rect> {
+        let mut points = self.contours.iter().flatten();
+        let first = *points.next()?;
+        let (mut min, mut max) = (first, first);
+        for p in points {
+            min = pt2(min.x.min(p.x), min.y.min(p.y));
+            max = pt2(max.x.max(p.x), max.y.max(p.y));
+        }
+        Some(Rect::from_corners(min, max))
+    }
+
+    /// Scale and move the outline to fit in the middle of `rect`, keeping its
+    /// aspect ratio.
+    pub fn fit(&mut self, rect: Rect) {
+        let bounds = match self.bounds() {
+            Some(bounds) if bounds.w() > 0.0 || bounds.h() > 0.0 => bounds,
+            _ => return,
+        };
+        let scale = (rect.w() / bounds.w()).min(rect.h() / bounds.h());
+        let (from, to) = (bounds.xy(), rect.xy());
+        for p in self.contours.iter_mut().flatten() {
+            *p = (*p - from) * scale + to;
+        }
+    }
+
+    /// The length of all the contours.
+    pub fn length(&self) -> f32 {
+        self.edges().map(|(a, b)| a.distance(b)).sum()
+    }
+
+    /// Whether `p` is in the filled region, by the even-odd rule.
+    pub fn contains(&self, p: Point2) -> bool {
+        let mut inside = false;
+        for (a, b) in self.edges() {
+            if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+                inside = !inside;
+            }
+        }
+        inside
+    }
+
+    /// The point on the contours closest to `p`.
+    pub fn closest(&self, p: Point2) -> Option<Point2> {
+        self.edges()
+            .map(|(a, b)| {
+                let ab = b - a;
+                let len2 = ab.magnitude2();
+                let t = if len2 > 0.0 { ((p - a).dot(ab) / len2).max(0.0).min(1.0) } else { 0.0 };
+                a + ab * t
+            })
+            .min_by(|a, b| a.distance2(p).partial_cmp(&b.distance2(p)).unwrap_or(Ordering::Equal))
+    }
+
+    /// Points `spacing` apart along every contour, starting at its first
+    /// point. The second value is how far along the whole outline the point
+    /// is, from 0 to 1.
+    pub fn points_along(&self, spacing: f32) -> Vec<(Point2, f32)> {
+        let total = self.length();
+        let mut points = vec![];
+        if spacing <= 0.0 || total == 0.0 {
+            return points;
+        }
+        let mut walked = 0.0;
+        for contour in &self.contours {
+            let next = contour.iter().cycle().skip(1);
+            // How far into the next edge the next point is.
+            let mut offset = 0.0;
+            for (&a, &b) in contour.iter().zip(next) {
+                let length = a.distance(b);
+                let mut d = offset;
+                while d < length {
+                    points.push((a + (b - a) * (d / length), (walked + d) / total));
+                    d += spacing;
+                }
+                offset = d - length;
+                walked += length;
+            }
+        }
+        points
+    }
+
+    /// About `count` random points inside the filled region.
+    pub fn points_inside(&self, count: usize) -> Vec<Point2> {
+        let bounds = match self.bounds() {
+            Some(bounds) if bounds.w() > 0.0 && bounds.h() > 0.0 => bounds,
+            _ => return vec![],
+        };
+        let mut points = Vec::with_capacity(count);
+        // Thin shapes fill little of their bounds, give up eventually.
+        for _ in 0..count * 100 {
+            if points.len() == count {
+                break;
+            }
+            let p = pt2(
+                random_range(bounds.left(), bounds.right()),
+                random_range(bounds.bottom(), bounds.top()),
+            );
+            if self.contains(p) {
+                points.push(p);
+            }
+        }
+        points
+    }
+}
+
+#[cfg(feature = "import")]
+impl Outline {
+    /// The paths of an SVG file, y up like nannou.
+    pub fn from_svg_file<P: AsRef<Path>>(path: P) -> Result<Self, ImportError> {
+        let data = std::fs::read(path)?;
+        let tree = usvg::Tree::from_data(&data, &usvg::Options::default())?;
+        Ok(Outline::from_svg_tree(&tree))
+    }
+
+    pub fn from_svg_str(text: &str) -> Result<Self, ImportError> {
+        let tree = usvg::Tree::from_str(text, &usvg::Options::default())?;
+        Ok(Outline::from_svg_tree(&tree))
+    }
+
+    fn from_svg_tree(tree: &usvg::Tree) -> Self {
+        use usvg::NodeExt;
+
+        let mut flattener = Flattener::default();
+        for node in tree.root().descendants() {
+            if tree.is_in_defs(&node) {
+                continue;
+            }
+            if let usvg::NodeKind::Path(ref path) = *node.borrow() {
+                let mut ts = node.abs_transform();
+                ts.append(&path.transform);
+                // SVG has y down.
+                let p = |x: f64, y: f64| {
+                    let (x, y) = ts.apply(x, y);
+                    pt2(x as f32, -y as f32)
+                };
+                for segment in path.data.iter() {
+                    match *segment {
+                        usvg::PathSegment::MoveTo { x, y } => flattener.move_to(p(x, y)),
+                        usvg::PathSegment::LineTo { x, y } => flattener.line_to(p(x, y)),
+                        usvg::PathSegment::CurveTo {
+                            x1,
+                            y1,
+                            x2,
+                            y2,
+                            x,
+                            y,
+                        } => flattener.curve_to(p(x1, y1), p(x2, y2), p(x, y)),
+                        usvg::PathSegment::ClosePath => flattener.close(),
+                    }
+                }
+                flattener.close();
+            }
+        }
+        flattener.finish()
+    }
+
+    /// The outlines of `text` in the font in `path`, `size` is the size of
+    /// the em square. The baseline of the first line starts at the origin.
+    pub fn from_font_file<P: AsRef<Path>>(path: P, text: &str, size: f32) -> Result<Self, ImportError> {
+        let data = std::fs::read(path)?;
+        Outline::from_text(&data, text, size)
+    }
+
+    pub fn from_text(font_data: &[u8], text: &str, size: f32) -> Result<Self, ImportError> {
+        let font = ttf_parser::Font::from_data(font_data, 0)
+            .ok_or_else(|| ImportError::Font("unsupported font data".to_string()))?;
+        let line_height = (font.ascender() - font.descender() + font.line_gap()) as f32;
+        let units_per_em = font.units_per_em().unwrap_or(1000) as f32;
+        let scale = size / units_per_em;
+        let mut builder = GlyphBuilder {
+            flattener: Flattener::default(),
+            origin: pt2(0.0, 0.0),
+            scale,
+        };
+        for (line, text) in text.lines().enumerate() {
+            builder.origin = pt2(0.0, -(line as f32) * line_height * scale);
+            for c in text.chars() {
+                let glyph = match font.glyph_index(c) {
+                    Some(glyph) => glyph,
+                    None => continue,
+                };
+                font.outline_glyph(glyph, &mut builder);
+                let advance = font.glyph_hor_advance(glyph).unwrap_or(0);
+                builder.origin.x += advance as f32 * scale;
+            }
+        }
+        Ok(builder.flattener.finish())
+    }
+}
+
+/// Collects path segments as polygons.
+#[cfg(feature = "import")]
+#[derive(Default)]
+struct Flattener {
+    contours: Vec<Vec<Point2>>,
+    current: Vec<Point2>,
+}
+
+#[cfg(feature = "import")]
+impl Flattener {
+    fn move_to(&mut self, p: Point2) {
+        self.close();
+        self.current.push(p);
+    }
+
+    fn line_to(&mut self, p: Point2) {
+        self.current.push(p);
+    }
+
+    fn quad_to(&mut self, c: Point2, p: Point2) {
+        let start = self.last();
+        for i in 1..=CURVE_SEGMENTS {
+            let t = i as f32 / CURVE_SEGMENTS as f32;
+            let u = 1.0 - t;
+            self.current.push(start * (u * u) + c * (2.0 * u * t) + p * (t * t));
+        }
+    }
+
+    fn curve_to(&mut self, c1: Point2, c2: Point2, p: Point2) {
+        let start = self.last();
+        for i in 1..=CURVE_SEGMENTS {
+            let t = i as f32 / CURVE_SEGMENTS as f32;
+            let u = 1.0 - t;
+            self.current.push(
+                start * (u * u * u) + c1 * (3.0 * u * u * t) + c2 * (3.0 * u * t * t) + p * (t * t * t),
+            );
+        }
+    }
+
+    fn last(&self) -> Point2 {
+        self.current.last().cloned().unwrap_or(pt2(0.0, 0.0))
+    }
+
+    /// End the current contour, dropping the closing point if it repeats the
+    /// first one.
+    fn close(&mut self) {
+        let mut contour = std::mem::replace(&mut self.current, vec![]);
+        if contour.len() > 1 && contour.first() == contour.last() {
+            contour.pop();
+        }
+        if contour.len() > 1 {
+            self.contours.push(contour);
+        }
+    }
+
+    fn finish(mut self) -> Outline {
+        self.close();
+        Outline::new(self.contours)
+    }
+}
+
+#[cfg(feature = "import")]
+struct GlyphBuilder {
+    flattener: Flattener,
+    origin: Point2,
+    scale: f32,
+}
+
+#[cfg(feature = "import")]
+impl GlyphBuilder {
+    fn p(&self, x: f32, y: f32) -> Point2 {
+        self.origin + pt2(x, y) * self.scale
+    }
+}
+
+#[cfg(feature = "import")]
+impl ttf_parser::OutlineBuilder for GlyphBuilder {
+    fn move_to(&mut self, x: f32, y: f32) {
+        let p = self.p(x, y);
+        self.flattener.move_to(p);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let p = self.p(x, y);
+        self.flattener.line_to(p);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (c, p) = (self.p(x1, y1), self.p(x, y));
+        self.flattener.quad_to(c, p);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (c1, c2, p) = (self.p(x1, y1), self.p(x2, y2), self.p(x, y));
+        self.flattener.curve_to(c1, c2, p);
+    }
+
+    fn close(&mut self) {
+        self.flattener.close();
+    }
+}
+
+/// Where `OutlineEmitter` places its points.
+#[derive(Debug, Clone, Copy)]
+pub enum Sampling {
+    /// `spacing` apart along the outline, `t` is how far along it they are.
+    Along { spacing: f32 },
+    /// About `count` random points in the filled region, `t` is 0.
+    Inside { count: usize },
+}
+
+/// Points on or in an outline, placed at once.
+pub struct OutlineEmitter {
+    pub outline: Outline,
+    pub sampling: Sampling,
+    placed: bool,
+}
+
+impl OutlineEmitter {
+    pub fn new(outline: Outline, sampling: Sampling) -> Self {
+        OutlineEmitter {
+            outline,
+            sampling,
+            placed: false,
+        }
+    }
+}
+
+impl Emitter for OutlineEmitter {
+    fn emit(&mut self, _ctx: &EmitContext, seeds: &mut Vec<Seed>) {
+        if self.placed {
+            return;
+        }
+        self.placed = true;
+        match self.sampling {
+            Sampling::Along { spacing } => {
+                for (pos, t) in self.outline.points_along(spacing) {
+                    seeds.push(Seed::at(pos, t));
+                }
+            }
+            Sampling::Inside { count } => {
+                for pos in self.outline.points_inside(count) {
+                    seeds.push(Seed::at(pos, 0.0));
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.placed = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(left: f32, bottom: f32, size: f32) -> Vec<Point2> {
+        vec![
+            pt2(left, bottom),
+            pt2(left + size, bottom),
+            pt2(left + size, bottom + size),
+            pt2(left, bottom + size),
+        ]
+    }
+
+    fn assert_close(a: Point2, b: Point2) {
+        assert!(a.distance(b) < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn contains_uses_the_even_odd_rule() {
+        let ring = Outline::new(vec![square(0.0, 0.0, 3.0), square(1.0, 1.0, 1.0)]);
+        assert!(ring.contains(pt2(0.5, 0.5)));
+        assert!(ring.contains(pt2(2.5, 1.5)));
+        assert!(!ring.contains(pt2(1.5, 1.5)));
+        assert!(!ring.contains(pt2(4.0, 1.5)));
+        assert!(!ring.contains(pt2(1.5, -0.5)));
+    }
+
+    #[test]
+    fn closest_is_on_the_nearest_edge() {
+        let outline = Outline::new(vec![square(0.0, 0.0, 1.0)]);
+        assert_close(outline.closest(pt2(0.5, 2.0)).unwrap(), pt2(0.5, 1.0));
+        assert_close(outline.closest(pt2(0.2, 0.5)).unwrap(), pt2(0.0, 0.5));
+        assert_close(outline.closest(pt2(3.0, -2.0)).unwrap(), pt2(1.0, 0.0));
+        assert_eq!(Outline::default().closest(pt2(0.0, 0.0)), None);
+    }
+
+    #[test]
+    fn points_along_carry_the_spacing_across_edges() {
+        let outline = Outline::new(vec![square(0.0, 0.0, 1.0)]);
+        let points = outline.points_along(0.3);
+        // 0, 0.3, .. 3.9 along the length of 4.
+        assert_eq!(points.len(), 14);
+        for (i, (_, t)) in points.iter().enumerate() {
+            assert!((t - i as f32 * 0.3 / 4.0).abs() < 1e-5);
+        }
+        assert_close(points[3].0, pt2(0.9, 0.0));
+        assert_close(points[4].0, pt2(1.0, 0.2));
+        assert_close(points[7].0, pt2(0.9, 1.0));
+        assert_close(points[13].0, pt2(0.0, 0.1));
+    }
+
+    #[cfg(feature = "import")]
+    #[test]
+    fn flattener_drops_closing_points_and_single_points() {
+        let mut flattener = Flattener::default();
+        flattener.move_to(pt2(0.0, 0.0));
+        flattener.line_to(pt2(1.0, 0.0));
+        flattener.line_to(pt2(1.0, 1.0));
+        flattener.line_to(pt2(0.0, 0.0));
+        flattener.close();
+        flattener.move_to(pt2(5.0, 5.0));
+        flattener.move_to(pt2(2.0, 2.0));
+        flattener.line_to(pt2(3.0, 2.0));
+        let outline = flattener.finish();
+        assert_eq!(
+            outline.contours,
+            vec![
+                vec![pt2(0.0, 0.0), pt2(1.0, 0.0), pt2(1.0, 1.0)],
+                vec![pt2(2.0, 2.0), pt2(3.0, 2.0)],
+            ]
+        );
+    }
+
+    #[cfg(feature = "import")]
+    #[test]
+    fn svg_paths_are_flipped_to_y_up() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+            <path d="M 1 1 L 5 1 L 5 3 Z"/>
+        </svg>"#;
+        let outline = Outline::from_svg_str(svg).unwrap();
+        assert_eq!(outline.contours, vec![vec![pt2(1.0, -1.0), pt2(5.0, -1.0), pt2(5.0, -3.0)]]);
+    }
+}
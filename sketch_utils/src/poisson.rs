@@ -0,0 +1,320 @@
+//! Poisson-disk sampling: random points that are never closer than a radius,
+//! with Bridson's algorithm. It avoids both the regularity of grids and the
+//! clumps of uniform random points.
+//!
+//! The radius can vary over the region, e.g. from an image used as a density
+//! map. The points only depend on the seed, the same seed gives the same
+//! points.
+use nannou::geom::Rect;
+use nannou::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::f32::consts::SQRT_2;
+
+use crate::emit::{EmitContext, Emitter, Seed};
+use crate::outline::Outline;
+
+/// The number of candidates tried around a point before it is retired.
+const CANDIDATES: usize = 30;
+
+/// The minimum distance for sketches connecting points closer than
+/// `max_line_length2.sqrt()`. New points are placed between one and two radii
+/// from an existing one, so with half the maximum line length every point has
+/// a neighbour in reach when it is placed.
+pub fn radius_for_line_length(max_line_length2: f32) -> f32 {
+    max_line_length2.sqrt() * 0.5
+}
+
+/// Where the points go.
+pub enum Region {
+    Rect(Rect),
+    Circle { center: Point2, radius: f32 },
+    /// Inside the bounds where `inside` is true.
+    Mask {
+        bounds: Rect,
+        inside: Box<dyn Fn(Point2) -> bool>,
+    },
+}
+
+impl Region {
+    /// The filled region of an outline.
+    pub fn outline(outline: Outline) -> Option<Self> {
+        let bounds = outline.bounds()?;
+        Some(Region::Mask {
+            bounds,
+            inside: Box::new(move |p| outline.contains(p)),
+        })
+    }
+
+    fn bounds(&self) -> Rect {
+        match self {
+            Region::Rect(rect) => *rect,
+            Region::Circle { center, radius } => Rect::from_xy_wh(*center, vec2(radius * 2.0, radius * 2.0)),
+            Region::Mask { bounds, .. } => *bounds,
+        }
+    }
+
+    fn contains(&self, p: Point2) -> bool {
+        let bounds = self.bounds();
+        let in_bounds = p.x >= bounds.left() && p.x <= bounds.right() && p.y >= bounds.bottom() && p.y <= bounds.top();
+        in_bounds
+            && match self {
+                Region::Rect(_) => true,
+                Region::Circle { center, radius } => p.distance2(*center) <= radius * radius,
+                Region::Mask { inside, .. } => inside(p),
+            }
+    }
+}
+
+/// The minimum distance between points.
+pub enum Radius {
+    Uniform(f32),
+    /// Varies from `max` where `density` is 0 to `min` where it is 1.
+    Density {
+        min: f32,
+        max: f32,
+        density: Box<dyn Fn(Point2) -> f32>,
+    },
+}
+
+impl Radius {
+    fn at(&self, p: Point2) -> f32 {
+        match self {
+            Radius::Uniform(r) => *r,
+            Radius::Density { min, max, density } => {
+                let density = density(p).max(0.0).min(1.0);
+                max + (min - max) * density
+            }
+        }
+    }
+
+    fn min(&self) -> f32 {
+        match self {
+            Radius::Uniform(r) => *r,
+            Radius::Density { min, .. } => *min,
+        }
+    }
+}
+
+pub struct PoissonDisk {
+    pub region: Region,
+    pub radius: Radius,
+    pub seed: u64,
+}
+
+impl PoissonDisk {
+    pub fn new(region: Region, radius: Radius, seed: u64) -> Self {
+        PoissonDisk { region, radius, seed }
+    }
+
+    pub fn points(&self) -> Vec<Point2> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let bounds = self.region.bounds();
+        let min_radius = self.radius.min();
+        if min_radius <= 0.0 || bounds.w() <= 0.0 || bounds.h() <= 0.0 {
+            return vec![];
+        }
+        let mut grid = Grid::new(bounds, min_radius / SQRT_2);
+
+        let mut points = vec![];
+        let mut active = vec![];
+        // The first point, anywhere in the region.
+        for _ in 0..CANDIDATES * 100 {
+            let p = random_point(&mut rng, bounds);
+            if self.region.contains(p) {
+                grid.insert(p, points.len());
+                active.push(points.len());
+                points.push(p);
+                break;
+            }
+        }
+
+        while !active.is_empty() {
+            let i = rng.gen_range(0, active.len());
+            let p = points[active[i]];
+            let r = self.radius.at(p);
+            let mut found = false;
+            for _ in 0..CANDIDATES {
+                let angle = rng.gen_range(0.0, PI * 2.0);
+                let distance = rng.gen_range(r, r * 2.0);
+                let candidate = p + vec2(angle.cos(), angle.sin()) * distance;
+                if !self.region.contains(candidate) {
+                    continue;
+                }
+                let candidate_r = self.radius.at(candidate);
+                let too_close = grid
+                    .near(candidate, candidate_r)
+                    .any(|j| points[j].distance2(candidate) < candidate_r * candidate_r);
+                if !too_close {
+                    grid.insert(candidate, points.len());
+                    active.push(points.len());
+                    points.push(candidate);
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                active.swap_remove(i);
+            }
+        }
+        points
+    }
+}
+
+/// All points at once. `t` goes from 0 at the top left of the region to 1 at
+/// the bottom right.
+pub struct PoissonEmitter {
+    pub sampler: PoissonDisk,
+    placed: bool,
+}
+
+impl PoissonEmitter {
+    pub fn new(sampler: PoissonDisk) -> Self {
+        PoissonEmitter {
+            sampler,
+            placed: false,
+        }
+    }
+}
+
+impl Emitter for PoissonEmitter {
+    fn emit(&mut self, _ctx: &EmitContext, seeds: &mut Vec<Seed>) {
+        if self.placed {
+            return;
+        }
+        self.placed = true;
+        let bounds = self.sampler.region.bounds();
+        for p in self.sampler.points() {
+            let t = ((p.x - bounds.left()) / bounds.w() + (bounds.top() - p.y) / bounds.h()) / 2.0;
+            seeds.push(Seed::at(p, t));
+        }
+    }
+
+    fn reset(&mut self) {
+        self.placed = false;
+    }
+}
+
+fn random_point(rng: &mut StdRng, bounds: Rect) -> Point2 {
+    pt2(
+        rng.gen_range(bounds.left(), bounds.right()),
+        rng.gen_range(bounds.bottom(), bounds.top()),
+    )
+}
+
+/// The indices of the points in square cells. With a varying radius a cell
+/// can hold several points.
+struct Grid {
+    origin: Point2,
+    cell: f32,
+    columns: usize,
+    rows: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+impl Grid {
+    fn new(bounds: Rect, cell: f32) -> Self {
+        let columns = (bounds.w() / cell).ceil() as usize + 1;
+        let rows = (bounds.h() / cell).ceil() as usize + 1;
+        Grid {
+            origin: pt2(bounds.left(), bounds.bottom()),
+            cell,
+            columns,
+            rows,
+            cells: vec![vec![]; columns * rows],
+        }
+    }
+
+    fn cell_of(&self, p: Point2) -> (isize, isize) {
+        let p = (p - self.origin) / self.cell;
+        (p.x.floor() as isize, p.y.floor() as isize)
+    }
+
+    fn insert(&mut self, p: Point2, index: usize) {
+        let (x, y) = self.cell_of(p);
+        let (x, y) = (x.max(0) as usize, y.max(0) as usize);
+        let i = y.min(self.rows - 1) * self.columns + x.min(self.columns - 1);
+        self.cells[i].push(index);
+    }
+
+    /// The points in the cells within `radius` of `p`.
+    fn near<'a>(&'a self, p: Point2, radius: f32) -> impl Iterator<Item = usize> + 'a {
+        let (cx, cy) = self.cell_of(p);
+        let reach = (radius / self.cell).ceil() as isize;
+        let x_range = (cx - reach).max(0)..=(cx + reach).min(self.columns as isize - 1);
+        let y_range = (cy - reach).max(0)..=(cy + reach).min(self.rows as isize - 1);
+        y_range
+            .flat_map(move |y| x_range.clone().map(move |x| (x, y)))
+            .flat_map(move |(x, y)| self.cells[y as usize * self.columns + x as usize].iter().cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The smallest distance between two of `points`.
+    fn min_distance(points: &[Point2]) -> f32 {
+        let mut min = f32::INFINITY;
+        for (i, a) in points.iter().enumerate() {
+            for b in &points[i + 1..] {
+                min = min.min(a.distance(*b));
+            }
+        }
+        min
+    }
+
+    fn rect() -> Region {
+        Region::Rect(Rect::from_corners(pt2(-100.0, -50.0), pt2(100.0, 50.0)))
+    }
+
+    #[test]
+    fn points_are_at_least_the_radius_apart() {
+        let points = PoissonDisk::new(rect(), Radius::Uniform(10.0), 1).points();
+        assert!(points.len() > 50);
+        assert!(min_distance(&points) >= 10.0);
+    }
+
+    #[test]
+    fn density_points_are_at_least_the_min_radius_apart() {
+        let radius = Radius::Density {
+            min: 5.0,
+            max: 20.0,
+            density: Box::new(|p| (p.x + 100.0) / 200.0),
+        };
+        let points = PoissonDisk::new(rect(), radius, 2).points();
+        assert!(points.len() > 50);
+        assert!(min_distance(&points) >= 5.0);
+        // Denser on the right.
+        let right = points.iter().filter(|p| p.x > 0.0).count();
+        assert!(right > points.len() - right);
+    }
+
+    #[test]
+    fn points_are_in_the_region() {
+        let regions = vec![
+            rect(),
+            Region::Circle {
+                center: pt2(10.0, -20.0),
+                radius: 60.0,
+            },
+            Region::Mask {
+                bounds: Rect::from_corners(pt2(0.0, 0.0), pt2(100.0, 100.0)),
+                inside: Box::new(|p| p.x > p.y),
+            },
+        ];
+        for region in regions {
+            let sampler = PoissonDisk::new(region, Radius::Uniform(8.0), 3);
+            let points = sampler.points();
+            assert!(points.len() > 20);
+            assert!(points.iter().all(|p| sampler.region.contains(*p)));
+        }
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_points() {
+        let points = |seed| PoissonDisk::new(rect(), Radius::Uniform(10.0), seed).points();
+        assert_eq!(points(7), points(7));
+        assert_ne!(points(7), points(8));
+    }
+}
@@ -0,0 +1,94 @@
+//! Springs for the lines between points.
+//!
+//! The sketches pulled connected points together or pushed them apart with a
+//! linear map of the squared distance, the same for every line, and only
+//! removed lines that got too long. A `Spring` has its own rest length,
+//! stiffness and damping, snaps when its tension gets too high and grows to
+//! full strength over its birth, so a network stretches, snaps and knits
+//! back together.
+//!
+//! The units are those of `step`: stiffness is the acceleration per unit of
+//! stretch (per second²) and damping per second.
+use nannou::prelude::*;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Spring {
+    pub rest_length: f32,
+    /// Per second².
+    pub stiffness: f32,
+    /// Per second, damps the speed the ends move apart or together at.
+    pub damping: f32,
+    /// The tension, stiffness times stretch, the spring snaps at.
+    pub breaking_tension: f32,
+    /// Seconds to grow to full strength.
+    pub birth: f32,
+    age: f32,
+}
+
+impl Spring {
+    /// A spring that doesn't break, is born at full strength and isn't
+    /// damped.
+    pub fn new(rest_length: f32, stiffness: f32) -> Self {
+        Spring {
+            rest_length,
+            stiffness,
+            damping: 0.0,
+            breaking_tension: std::f32::INFINITY,
+            birth: 0.0,
+            age: 0.0,
+        }
+    }
+
+    /// A newborn spring with the parameters of this one and another rest
+    /// length, for springs made from a template.
+    pub fn with_rest_length(&self, rest_length: f32) -> Self {
+        Spring {
+            rest_length,
+            age: 0.0,
+            ..*self
+        }
+    }
+
+    /// Take the parameters of `template`, keeping the rest length and age.
+    pub fn tune(&mut self, template: &Spring) {
+        self.stiffness = template.stiffness;
+        self.damping = template.damping;
+        self.breaking_tension = template.breaking_tension;
+        self.birth = template.birth;
+    }
+
+    pub fn age(&mut self, dt: f32) {
+        self.age += dt;
+    }
+
+    /// How far the spring has grown, from 0 when it is born to 1, easing in
+    /// and out.
+    pub fn growth(&self) -> f32 {
+        if self.birth <= 0.0 {
+            return 1.0;
+        }
+        let t = (self.age / self.birth).min(1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    /// Positive when stretched, negative when compressed.
+    pub fn tension(&self, length: f32) -> f32 {
+        self.stiffness * (length - self.rest_length)
+    }
+
+    pub fn breaks(&self, length: f32) -> bool {
+        self.tension(length) > self.breaking_tension
+    }
+
+    /// The acceleration of one end, with `offset` from it to the other end
+    /// and `relative_vel` the velocity of the other end relative to it.
+    pub fn acceleration(&self, offset: Vector2, relative_vel: Vector2) -> Vector2 {
+        let length = offset.magnitude();
+        if length == 0.0 {
+            return vec2(0.0, 0.0);
+        }
+        let direction = offset / length;
+        let pull = self.tension(length) + self.damping * relative_vel.dot(direction);
+        direction * pull * self.growth()
+    }
+}
@@ -0,0 +1,109 @@
+//! Fixed-timestep simulation, decoupled from the frame rate.
+//!
+//! The sketches moved points once per rendered frame with `vel *= friction;
+//! vel += force; pos += vel`, so they behaved differently at another frame
+//! rate or while `capture_frame` slowed rendering down. With `FixedStep` the
+//! simulation runs in steps of a fixed `dt` seconds, as many per frame as the
+//! elapsed time needs, and `Integrator` moves points in seconds: velocities
+//! in units per second, accelerations in units per second², and drag as the
+//! rate velocity decays per second.
+use nannou::prelude::*;
+
+/// The frame rate the per-frame values of the sketches were tuned at.
+pub const REFERENCE_RATE: f32 = 60.0;
+
+/// The drag for a friction that kept `keep` of the velocity every frame at
+/// `REFERENCE_RATE`, e.g. 0.7 becomes about 21.4 per second.
+pub fn drag_from_friction(keep: f32) -> f32 {
+    -keep.max(1e-6).ln() * REFERENCE_RATE
+}
+
+/// The acceleration in units per second² for a force that was added to the
+/// velocity every frame at `REFERENCE_RATE`, like those of `force`.
+pub fn per_frame_force<T: std::ops::Mul<f32, Output = T>>(force: T) -> T {
+    force * (REFERENCE_RATE * REFERENCE_RATE)
+}
+
+/// How much of the velocity is left after `dt` seconds of `drag`.
+pub fn damping(drag: f32, dt: f32) -> f32 {
+    (-drag.max(0.0) * dt).exp()
+}
+
+pub struct FixedStep {
+    /// Seconds per step.
+    pub dt: f32,
+    /// Steps per frame at most, the rest of a long frame is dropped so a slow
+    /// frame doesn't make the next one slower.
+    pub max_steps: usize,
+    /// The simulated time in seconds.
+    pub time: f32,
+    accumulator: f32,
+}
+
+impl FixedStep {
+    /// `rate` steps per second.
+    pub fn new(rate: f32) -> Self {
+        FixedStep {
+            dt: 1.0 / rate,
+            max_steps: 8,
+            time: 0.0,
+            accumulator: 0.0,
+        }
+    }
+
+    /// The number of steps to run for a frame `elapsed` seconds long. Pass
+    /// `1.0 / fps` instead of the real time when rendering to files, so every
+    /// file is the same time apart.
+    pub fn steps(&mut self, elapsed: f32) -> usize {
+        self.accumulator += elapsed.max(0.0);
+        let steps = (self.accumulator / self.dt) as usize;
+        if steps > self.max_steps {
+            self.accumulator = 0.0;
+            self.time += self.max_steps as f32 * self.dt;
+            return self.max_steps;
+        }
+        self.accumulator -= steps as f32 * self.dt;
+        self.time += steps as f32 * self.dt;
+        steps
+    }
+
+    /// How far the time is between the last step and the next one, from 0 to
+    /// 1, for drawing in between steps.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / self.dt
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Integrator {
+    /// Updates the velocity, then moves with the new velocity. Cheap and
+    /// stable for springs.
+    SemiImplicitEuler,
+    /// Velocity Verlet: moves with the velocity and the acceleration, then
+    /// updates the velocity with the average of the acceleration before and
+    /// after. More accurate, evaluates the acceleration twice.
+    Verlet,
+}
+
+impl Integrator {
+    /// Move a point by `dt` seconds with the acceleration `accel` gives at a
+    /// position, slowed down by `drag`.
+    pub fn step<A>(self, pos: &mut Point2, vel: &mut Vector2, dt: f32, drag: f32, accel: A)
+    where
+        A: Fn(Point2) -> Vector2,
+    {
+        let damping = damping(drag, dt);
+        match self {
+            Integrator::SemiImplicitEuler => {
+                *vel = (*vel + accel(*pos) * dt) * damping;
+                *pos += *vel * dt;
+            }
+            Integrator::Verlet => {
+                let a0 = accel(*pos);
+                *pos += *vel * dt + a0 * (0.5 * dt * dt);
+                let a1 = accel(*pos);
+                *vel = (*vel + (a0 + a1) * (0.5 * dt)) * damping;
+            }
+        }
+    }
+}
@@ -0,0 +1,478 @@
+//! Moving footage as an `ImageField` that advances one frame per simulation
+//! frame, so points can be seeded from and react to video.
+//!
+//! The frames come from a numbered image sequence, a Y4M file or a raw RGB24
+//! file. They are decoded on a background thread, either all of them up front
+//! (`Loading::Preload`, frames are kept and loop without decoding again) or
+//! a few ahead of playback (`Loading::Stream`, for footage that doesn't fit
+//! in memory). If the next frame isn't decoded yet the field keeps showing
+//! the last one. Decoding stops at the first error, `VideoField::error`
+//! returns it once the field has received it.
+use nannou::geom::Rect;
+use nannou::image::{self, RgbaImage};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crate::image_field::{ImageField, ImageFit};
+
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// Image files shown in this order.
+    Sequence(Vec<PathBuf>),
+    Y4m(PathBuf),
+    /// Packed 8 bit RGB frames without a header.
+    RawRgb {
+        path: PathBuf,
+        width: u32,
+        height: u32,
+    },
+}
+
+impl Source {
+    /// The image files in `dir` ordered by the number in their names, e.g.
+    /// `frame_9.png` before `frame_10.png`.
+    pub fn sequence_in<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let mut files = vec![];
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if image::ImageFormat::from_path(&path).is_ok() {
+                files.push(path);
+            }
+        }
+        files.sort_by_key(|path| {
+            let name = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            let digits: String = name.chars().filter(|c| c.is_ascii_digit()).collect();
+            (digits.parse::<u64>().unwrap_or(0), name)
+        });
+        Ok(Source::Sequence(files))
+    }
+
+    /// A sequence for the image files in a directory, a Y4M file otherwise.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            Source::sequence_in(path)
+        } else {
+            Ok(Source::Y4m(path.to_path_buf()))
+        }
+    }
+
+    /// Decode every frame in order, stopping when `frame` returns false.
+    fn decode(&self, mut frame: impl FnMut(RgbaImage) -> bool) -> Result<(), VideoError> {
+        match self {
+            Source::Sequence(files) => {
+                for file in files {
+                    let image = image::open(file).map_err(VideoError::Image)?.into_rgba();
+                    if !frame(image) {
+                        break;
+                    }
+                }
+            }
+            Source::Y4m(path) => {
+                let mut reader = Y4mReader::open(path)?;
+                while let Some(image) = reader.next_frame()? {
+                    if !frame(image) {
+                        break;
+                    }
+                }
+            }
+            Source::RawRgb { path, width, height } => {
+                let mut reader = BufReader::new(File::open(path)?);
+                let mut rgb = vec![0; (*width * *height * 3) as usize];
+                while read_frame(&mut reader, &mut rgb)? {
+                    let mut image = RgbaImage::new(*width, *height);
+                    for (pixel, rgb) in image.pixels_mut().zip(rgb.chunks(3)) {
+                        *pixel = image::Rgba([rgb[0], rgb[1], rgb[2], 255]);
+                    }
+                    if !frame(image) {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum VideoError {
+    Io(io::Error),
+    Image(image::ImageError),
+    Y4m(String),
+}
+
+impl fmt::Display for VideoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VideoError::Io(e) => write!(f, "IO error: {}", e),
+            VideoError::Image(e) => write!(f, "Image error: {}", e),
+            VideoError::Y4m(e) => write!(f, "Y4M error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for VideoError {}
+
+impl From<io::Error> for VideoError {
+    fn from(e: io::Error) -> Self {
+        VideoError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Loading {
+    /// Decode every frame and keep them.
+    Preload,
+    /// Decode up to `buffer` frames ahead.
+    Stream { buffer: usize },
+}
+
+pub struct VideoField {
+    field: ImageField,
+    rx: Receiver<Result<RgbaImage, VideoError>>,
+    /// Every frame so far when preloading.
+    frames: Option<Vec<RgbaImage>>,
+    next: usize,
+    looping: bool,
+    decoded_all: bool,
+    error: Option<VideoError>,
+}
+
+impl VideoField {
+    /// Start decoding `source` on a background thread. The field is black
+    /// until the first frame arrives.
+    pub fn open(source: Source, loading: Loading, looping: bool, fit: ImageFit, window: Rect) -> Self {
+        let preload = match loading {
+            Loading::Preload => true,
+            Loading::Stream { .. } => false,
+        };
+        let (tx, rx) = match loading {
+            // Preloading never waits for playback.
+            Loading::Preload => {
+                let (tx, rx) = mpsc::channel();
+                (FrameSender::Unbounded(tx), rx)
+            }
+            Loading::Stream { buffer } => {
+                let (tx, rx) = mpsc::sync_channel(buffer.max(1));
+                (FrameSender::Bounded(tx), rx)
+            }
+        };
+        thread::spawn(move || loop {
+            let mut sent = 0;
+            let mut closed = false;
+            let result = source.decode(|image| {
+                if tx.send(Ok(image)) {
+                    sent += 1;
+                    true
+                } else {
+                    closed = true;
+                    false
+                }
+            });
+            if let Err(e) = result {
+                tx.send(Err(e));
+                return;
+            }
+            // Preloaded frames are looped by the field. A source without
+            // frames or a dropped field stop the thread.
+            if preload || !looping || sent == 0 || closed {
+                return;
+            }
+        });
+        VideoField {
+            field: ImageField::new(RgbaImage::new(1, 1), fit, window),
+            rx,
+            frames: if preload { Some(vec![]) } else { None },
+            next: 0,
+            looping,
+            decoded_all: false,
+            error: None,
+        }
+    }
+
+    pub fn field(&self) -> &ImageField {
+        &self.field
+    }
+
+    pub fn field_mut(&mut self) -> &mut ImageField {
+        &mut self.field
+    }
+
+    /// Why decoding stopped early, the frames before the error are still
+    /// shown.
+    pub fn error(&self) -> Option<&VideoError> {
+        self.error.as_ref()
+    }
+
+    /// Whether the last frame was shown and the video doesn't loop.
+    pub fn finished(&self) -> bool {
+        self.decoded_all
+            && !self.looping
+            && self.frames.as_ref().map_or(true, |frames| self.next >= frames.len())
+    }
+
+    /// Show the next frame if it is decoded, call once per simulation frame.
+    /// Returns whether the frame changed.
+    pub fn advance(&mut self) -> bool {
+        loop {
+            match self.rx.try_recv() {
+                Ok(Ok(image)) => match &mut self.frames {
+                    Some(frames) => frames.push(image),
+                    None => {
+                        self.field.set_image(image);
+                        return true;
+                    }
+                },
+                Ok(Err(e)) => self.error = Some(e),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.decoded_all = true;
+                    break;
+                }
+            }
+        }
+        let frames = match &self.frames {
+            Some(frames) => frames,
+            None => return false,
+        };
+        if self.next >= frames.len() {
+            if !(self.decoded_all && self.looping) || frames.is_empty() {
+                return false;
+            }
+            self.next = 0;
+        }
+        self.field.set_image(frames[self.next].clone());
+        self.next += 1;
+        true
+    }
+}
+
+/// The sending end of either kind of channel.
+enum FrameSender {
+    Unbounded(mpsc::Sender<Result<RgbaImage, VideoError>>),
+    Bounded(mpsc::SyncSender<Result<RgbaImage, VideoError>>),
+}
+
+impl FrameSender {
+    /// Blocks while a bounded channel is full, false once the field is gone.
+    fn send(&self, frame: Result<RgbaImage, VideoError>) -> bool {
+        match self {
+            FrameSender::Unbounded(tx) => tx.send(frame).is_ok(),
+            FrameSender::Bounded(tx) => tx.send(frame).is_ok(),
+        }
+    }
+}
+
+/// Fill `buf` with the next frame, false at the end of the file.
+fn read_frame<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool, VideoError> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated frame").into()),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+/// How the chroma planes of a Y4M file are subsampled.
+#[derive(Debug, Clone, Copy)]
+enum Chroma {
+    /// 4:2:0, half the width and height.
+    Half,
+    /// 4:2:2, half the width.
+    HalfWidth,
+    /// 4:4:4
+    Full,
+    /// Only the luma plane.
+    Mono,
+}
+
+/// 8 bit Y4M as written by `ffmpeg -i in.mp4 -pix_fmt yuv420p out.y4m`, see
+/// https://wiki.multimedia.cx/index.php/YUV4MPEG2
+struct Y4mReader {
+    reader: BufReader<File>,
+    width: u32,
+    height: u32,
+    chroma: Chroma,
+    line: Vec<u8>,
+    frame: Vec<u8>,
+}
+
+impl Y4mReader {
+    fn open(path: &Path) -> Result<Self, VideoError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header = vec![];
+        reader.read_until(b'\n', &mut header)?;
+        let header = String::from_utf8_lossy(&header);
+        let mut params = header.split_whitespace();
+        if params.next() != Some("YUV4MPEG2") {
+            return Err(VideoError::Y4m("not a YUV4MPEG2 file".to_string()));
+        }
+        let (mut width, mut height, mut chroma) = (0, 0, Chroma::Half);
+        for param in params {
+            let mut chars = param.chars();
+            let tag = chars.next();
+            let value = chars.as_str();
+            match tag {
+                Some('W') => width = value.parse().unwrap_or(0),
+                Some('H') => height = value.parse().unwrap_or(0),
+                Some('C') => {
+                    chroma = match value {
+                        v if v.starts_with("420") => Chroma::Half,
+                        "422" => Chroma::HalfWidth,
+                        "444" => Chroma::Full,
+                        "mono" => Chroma::Mono,
+                        v => return Err(VideoError::Y4m(format!("unsupported colour space {}", v))),
+                    }
+                }
+                _ => (),
+            }
+        }
+        if width == 0 || height == 0 {
+            return Err(VideoError::Y4m("missing frame size".to_string()));
+        }
+        let (cw, ch) = Y4mReader::chroma_size(chroma, width, height);
+        let size = (width * height + 2 * cw * ch) as usize;
+        Ok(Y4mReader {
+            reader,
+            width,
+            height,
+            chroma,
+            line: vec![],
+            frame: vec![0; size],
+        })
+    }
+
+    fn chroma_size(chroma: Chroma, width: u32, height: u32) -> (u32, u32) {
+        match chroma {
+            Chroma::Half => ((width + 1) / 2, (height + 1) / 2),
+            Chroma::HalfWidth => ((width + 1) / 2, height),
+            Chroma::Full => (width, height),
+            Chroma::Mono => (0, 0),
+        }
+    }
+
+    fn next_frame(&mut self) -> Result<Option<RgbaImage>, VideoError> {
+        self.line.clear();
+        if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+            return Ok(None);
+        }
+        if !self.line.starts_with(b"FRAME") {
+            return Err(VideoError::Y4m("missing FRAME header".to_string()));
+        }
+        if !read_frame(&mut self.reader, &mut self.frame)? {
+            return Ok(None);
+        }
+
+        let (w, h) = (self.width, self.height);
+        let (cw, ch) = Y4mReader::chroma_size(self.chroma, w, h);
+        let (luma, chroma) = self.frame.split_at((w * h) as usize);
+        let (u_plane, v_plane) = chroma.split_at((cw * ch) as usize);
+        let mut image = RgbaImage::new(w, h);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let l = luma[(y * w + x) as usize] as f32;
+            let (u, v) = match self.chroma {
+                Chroma::Mono => (128.0, 128.0),
+                _ => {
+                    let cx = x * cw / w;
+                    let cy = y * ch / h;
+                    let i = (cy * cw + cx) as usize;
+                    (u_plane[i] as f32, v_plane[i] as f32)
+                }
+            };
+            // BT.601 limited range.
+            let l = (l - 16.0) * 1.164;
+            let (u, v) = (u - 128.0, v - 128.0);
+            let r = l + 1.596 * v;
+            let g = l - 0.392 * u - 0.813 * v;
+            let b = l + 2.017 * u;
+            let c = |c: f32| c.max(0.0).min(255.0) as u8;
+            *pixel = image::Rgba([c(r), c(g), c(b), 255]);
+        }
+        Ok(Some(image))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    /// Write `data` to a file in the temporary directory.
+    fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("sketch_utils_{}_{}.y4m", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    /// A 2x2 4:2:0 file, each frame is four luma values and one U and V.
+    fn y4m(header: &str, frames: &[[u8; 6]]) -> Vec<u8> {
+        let mut data = format!("{}\n", header).into_bytes();
+        for frame in frames {
+            data.extend_from_slice(b"FRAME\n");
+            data.extend_from_slice(frame);
+        }
+        data
+    }
+
+    #[test]
+    fn y4m_frames_are_decoded() {
+        let gray = [16, 235, 235, 16, 128, 128];
+        let red = [81, 81, 81, 81, 90, 240];
+        let path = temp_file("decode", &y4m("YUV4MPEG2 W2 H2 F25:1 Ip A1:1 C420jpeg", &[gray, red]));
+        let mut reader = Y4mReader::open(&path).unwrap();
+
+        let frame = reader.next_frame().unwrap().unwrap();
+        assert_eq!(frame.dimensions(), (2, 2));
+        assert_eq!(frame.get_pixel(0, 0).0, [0, 0, 0, 255]);
+        assert_eq!(frame.get_pixel(1, 0).0, [254, 254, 254, 255]);
+        assert_eq!(frame.get_pixel(0, 1).0, [254, 254, 254, 255]);
+        assert_eq!(frame.get_pixel(1, 1).0, [0, 0, 0, 255]);
+
+        let frame = reader.next_frame().unwrap().unwrap();
+        for pixel in frame.pixels() {
+            assert_eq!(pixel.0, [254, 0, 0, 255]);
+        }
+        assert!(reader.next_frame().unwrap().is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unknown_non_ascii_params_are_skipped() {
+        let path = temp_file("params", &y4m("YUV4MPEG2 ÄX W2 H2", &[[16; 6]]));
+        let mut reader = Y4mReader::open(&path).unwrap();
+        assert!(reader.next_frame().unwrap().is_some());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn decode_errors_reach_the_field() {
+        let mut data = y4m("YUV4MPEG2 W2 H2 C420", &[[16; 6]]);
+        data.extend_from_slice(b"FRAME\n\x10\x10");
+        let path = temp_file("truncated", &data);
+        let window = Rect::from_w_h(2.0, 2.0);
+        let mut video = VideoField::open(Source::Y4m(path.clone()), Loading::Preload, false, ImageFit::Fill, window);
+
+        let start = Instant::now();
+        while video.error().is_none() {
+            assert!(start.elapsed() < Duration::from_secs(5), "no error");
+            video.advance();
+            thread::sleep(Duration::from_millis(1));
+        }
+        match video.error() {
+            Some(VideoError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            e => panic!("unexpected error {:?}", e),
+        }
+        // The frame before the error was shown.
+        assert_eq!(video.field().image().dimensions(), (2, 2));
+        std::fs::remove_file(path).unwrap();
+    }
+}